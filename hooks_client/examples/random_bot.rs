@@ -33,8 +33,7 @@ fn main() {
         let mut client = Client::connect(&host, port, &name, timeout_ms).unwrap();
         let my_player_id = client.ready(timeout_ms).unwrap();
 
-        let mut reg = Registry::new();
-        hooks_client::game::register(&mut reg, client.game_info());
+        let reg = Registry::view(&hooks_client::game::plugins(), client.game_info());
 
         let mut game = Game::new(reg, my_player_id, client.game_info(), false);
 
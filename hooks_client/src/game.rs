@@ -7,8 +7,8 @@ use specs::prelude::{RunNow, World};
 use hooks_game::net::protocol::ClientGameMsg;
 use hooks_game::physics::{Orientation, Position};
 use hooks_game::registry::Registry;
-use hooks_game::repl::{self, interp, tick};
-use hooks_game::{self, event, game, GameInfo, PlayerId, PlayerInput, TickNum};
+use hooks_game::repl::{self, interp, resource, tick};
+use hooks_game::{self, event, game, GameInfo, PlayerId, PlayerInput, Plugin, Plugins, TickNum};
 use hooks_util::debug;
 use hooks_util::profile;
 use hooks_util::stats;
@@ -77,11 +77,29 @@ pub struct Game {
     server_receive_ack_tick: Option<TickNum>,
 }
 
-pub fn register(reg: &mut Registry, game_info: &GameInfo) {
-    hooks_game::view::register(reg, game_info);
+/// Client-side components for interpolating replicated entities.
+pub struct InterpPlugin;
 
-    reg.component::<interp::State<Position>>();
-    reg.component::<interp::State<Orientation>>();
+impl Plugin for InterpPlugin {
+    fn name(&self) -> &'static str {
+        "client::interp"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["core"]
+    }
+
+    fn register_view(&self, reg: &mut Registry, _: &GameInfo) {
+        reg.component::<interp::State<Position>>();
+        reg.component::<interp::State<Orientation>>();
+    }
+}
+
+/// The game plugins, together with the client-specific plugins.
+pub fn plugins() -> Plugins {
+    let mut plugins = hooks_game::plugins();
+    plugins.add(InterpPlugin);
+    plugins
 }
 
 #[derive(Debug)]
@@ -138,8 +156,9 @@ impl Game {
         let mut reader = BitReader::new(Cursor::new(data));
 
         let entity_classes = self.game_state.world.read_resource::<game::EntityClasses>();
+        let resource_types = self.game_state.world.read_resource::<resource::Types>();
         let read_info = self.tick_history
-            .delta_read_tick(&entity_classes, &mut reader)?;
+            .delta_read_tick(&entity_classes, &resource_types, &mut reader)?;
 
         if let Some(read_info) = read_info {
             if let Some(last_input_tick) = read_info.last_input_tick {
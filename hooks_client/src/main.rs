@@ -20,21 +20,24 @@ use ggez::{conf, ContextBuilder};
 
 use hooks_client::client::Client;
 use hooks_client::game::Game;
-use hooks_game::defs::{GameInfo, PlayerInput};
+use hooks_game::defs::PlayerInput;
 use hooks_game::physics::Position;
 use hooks_game::registry::Registry;
+use hooks_game::Plugins;
 use hooks_show::{Assets, Show};
 use hooks_util::debug::{self, Inspect};
 use hooks_util::profile::{self, PROFILER};
 use hooks_util::stats;
 use hooks_util::timer::{duration_to_secs, Stopwatch};
 
-fn register(reg: &mut Registry, game_info: &GameInfo) {
+fn plugins() -> Plugins {
     // Game state
-    hooks_client::game::register(reg, game_info);
+    let mut plugins = hooks_client::game::plugins();
 
     // Components for showing game state
-    hooks_show::register(reg);
+    plugins.add(hooks_show::Plugin);
+
+    plugins
 }
 
 struct Config {
@@ -313,8 +316,7 @@ fn main() {
     let font = Font::default_font().unwrap();
 
     // Register and create game
-    let mut reg = Registry::new();
-    register(&mut reg, client.game_info());
+    let reg = Registry::view(&plugins(), client.game_info());
 
     let game = Game::new(reg, my_player_id, client.game_info(), true);
 
@@ -0,0 +1,213 @@
+//! Finding contacts with our grid broadphase compared to ncollide's collision world, in a scene
+//! with hundreds of hook segments. Run with `cargo bench -p hooks_game`.
+
+#![feature(test)]
+
+extern crate hooks_game;
+extern crate nalgebra;
+extern crate ncollide;
+extern crate specs;
+extern crate test;
+
+use std::f32;
+
+use nalgebra::{Isometry2, Point2, Vector2};
+use ncollide::shape::{Cuboid, ShapeHandle, ShapeHandle2};
+use ncollide::world::{CollisionGroups, CollisionWorld2, GeometricQueryType};
+use specs::prelude::{Entity, World};
+use test::Bencher;
+
+use hooks_game::game::entity::hook::{NUM_SEGMENTS, SEGMENT_LENGTH};
+use hooks_game::physics::collision::CollisionWorld;
+
+/// Each player has two hooks.
+const NUM_HOOKS: usize = 20;
+
+/// Radius of the arena that the hooks are shot from.
+const ARENA_RADIUS: f32 = 1000.0;
+
+struct Scene {
+    walls: Vec<(Isometry2<f32>, ShapeHandle2<f32>)>,
+    segment_shape: ShapeHandle2<f32>,
+}
+
+impl Scene {
+    fn new() -> Scene {
+        let wall_shape = ShapeHandle::new(Cuboid::new(Vector2::new(ARENA_RADIUS, 10.0)));
+        let walls = (0..4)
+            .map(|i| {
+                let angle = i as f32 * f32::consts::PI / 2.0;
+                let pos = Vector2::new(angle.cos(), angle.sin()) * ARENA_RADIUS;
+                (
+                    Isometry2::new(pos, angle + f32::consts::PI / 2.0),
+                    wall_shape.clone(),
+                )
+            })
+            .collect();
+
+        Scene {
+            walls,
+            segment_shape: ShapeHandle::new(Cuboid::new(Vector2::new(SEGMENT_LENGTH / 2.0, 1.5))),
+        }
+    }
+
+    /// Pose of a hook segment at some point in time. The hooks swing around, so that every
+    /// iteration of a benchmark sees the segments at new positions.
+    fn segment(&self, hook: usize, segment: usize, time: usize) -> Isometry2<f32> {
+        let base_angle = hook as f32 * 2.0 * f32::consts::PI / NUM_HOOKS as f32;
+        let angle = base_angle + (time as f32 * 0.1 + hook as f32).sin() * 0.2;
+        let distance = (segment as f32 + 0.5) * SEGMENT_LENGTH;
+        let origin = Point2::new(base_angle.cos(), base_angle.sin()) * ARENA_RADIUS * 0.5;
+        let pos = origin + Vector2::new(angle.cos(), angle.sin()) * distance;
+
+        Isometry2::new(pos.coords, angle)
+    }
+}
+
+fn query_type() -> GeometricQueryType<f32> {
+    GeometricQueryType::Contacts(0.0, 0.0)
+}
+
+/// Set up our collision world, returning the segment entities of each hook.
+fn grid_world(scene: &Scene) -> (CollisionWorld, Vec<Vec<Entity>>) {
+    let mut world = World::new();
+    let mut collision_world = CollisionWorld::new();
+
+    for &(isometry, ref shape) in &scene.walls {
+        let entity = world.create_entity().build();
+        collision_world.add(
+            entity,
+            isometry,
+            shape.clone(),
+            CollisionGroups::new(),
+            query_type(),
+        );
+    }
+
+    let hooks = (0..NUM_HOOKS)
+        .map(|hook| {
+            (0..NUM_SEGMENTS)
+                .map(|segment| {
+                    let entity = world.create_entity().build();
+                    collision_world.add(
+                        entity,
+                        scene.segment(hook, segment, 0),
+                        scene.segment_shape.clone(),
+                        CollisionGroups::new(),
+                        query_type(),
+                    );
+                    entity
+                })
+                .collect()
+        })
+        .collect();
+
+    (collision_world, hooks)
+}
+
+/// Move all segments, and find the contacts of the segments of the given hooks.
+fn run_grid(
+    scene: &Scene,
+    collision_world: &mut CollisionWorld,
+    hooks: &[Vec<Entity>],
+    simulated_hooks: usize,
+    time: usize,
+) -> usize {
+    for (hook, segments) in hooks.iter().enumerate() {
+        for (segment, &entity) in segments.iter().enumerate() {
+            collision_world.set_position(entity, scene.segment(hook, segment, time));
+        }
+    }
+
+    let simulated = hooks[..simulated_hooks]
+        .iter()
+        .flat_map(|segments| segments.iter().cloned())
+        .collect::<Vec<_>>();
+
+    let mut contacts = Vec::new();
+    for (a, b) in collision_world.candidate_pairs(&simulated) {
+        collision_world.contacts(a, b, &mut contacts);
+    }
+
+    contacts.len()
+}
+
+#[bench]
+fn bench_grid_all_hooks(b: &mut Bencher) {
+    let scene = Scene::new();
+    let (mut collision_world, hooks) = grid_world(&scene);
+    let mut time = 0;
+
+    b.iter(|| {
+        time += 1;
+        run_grid(&scene, &mut collision_world, &hooks, NUM_HOOKS, time)
+    });
+}
+
+/// Running one player's input only simulates that player's entities.
+#[bench]
+fn bench_grid_one_player(b: &mut Bencher) {
+    let scene = Scene::new();
+    let (mut collision_world, hooks) = grid_world(&scene);
+    let mut time = 0;
+
+    b.iter(|| {
+        time += 1;
+        run_grid(&scene, &mut collision_world, &hooks, 2, time)
+    });
+}
+
+/// ncollide's collision world, as used before, always runs the broadphase and narrow phase for
+/// all objects.
+#[bench]
+fn bench_ncollide_world(b: &mut Bencher) {
+    let scene = Scene::new();
+    let mut collision_world = CollisionWorld2::<f32, usize>::new(0.02);
+
+    for (i, &(isometry, ref shape)) in scene.walls.iter().enumerate() {
+        collision_world.add(
+            isometry,
+            shape.clone(),
+            CollisionGroups::new(),
+            query_type(),
+            i,
+        );
+    }
+
+    let hooks = (0..NUM_HOOKS)
+        .map(|hook| {
+            (0..NUM_SEGMENTS)
+                .map(|segment| {
+                    collision_world.add(
+                        scene.segment(hook, segment, 0),
+                        scene.segment_shape.clone(),
+                        CollisionGroups::new(),
+                        query_type(),
+                        scene.walls.len() + hook * NUM_SEGMENTS + segment,
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut time = 0;
+
+    b.iter(|| {
+        time += 1;
+
+        for (hook, segments) in hooks.iter().enumerate() {
+            for (segment, &handle) in segments.iter().enumerate() {
+                collision_world.set_position(handle, scene.segment(hook, segment, time));
+            }
+        }
+
+        collision_world.update();
+
+        let mut contacts = Vec::new();
+        for (_, _, generator) in collision_world.contact_pairs() {
+            generator.contacts(&mut contacts);
+        }
+
+        contacts.len()
+    });
+}
@@ -0,0 +1,46 @@
+//! Locating the data files, e.g. prefabs, that server and clients load at startup.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that overrides the data directory.
+pub const ENV_VAR: &str = "HOOKS_DATA_DIR";
+
+/// Name of the data directory.
+pub const DIR_NAME: &str = "data";
+
+/// Directory that contains the data files.
+///
+/// This is given by `HOOKS_DATA_DIR` if it is set. Otherwise, we look for a `data` directory next
+/// to the executable and in its ancestors, so that binaries in `target/` find the data of the
+/// repository no matter where they are started from. As a last resort, `data` in the working
+/// directory is used.
+pub fn dir() -> PathBuf {
+    if let Some(dir) = env::var_os(ENV_VAR) {
+        return PathBuf::from(dir);
+    }
+
+    if let Ok(exe) = env::current_exe() {
+        if let Some(dir) = find_in_ancestors(&exe) {
+            return dir;
+        }
+    }
+
+    PathBuf::from(DIR_NAME)
+}
+
+fn find_in_ancestors(path: &Path) -> Option<PathBuf> {
+    let mut current = path.parent();
+
+    while let Some(path) = current {
+        let dir = path.join(DIR_NAME);
+
+        if dir.is_dir() {
+            return Some(dir);
+        }
+
+        current = path.parent();
+    }
+
+    None
+}
@@ -47,6 +47,31 @@ impl PlayerInfo {
     }
 }
 
+/// Settings of the physics simulation. These are part of `GameInfo`, so that the server and
+/// predicting clients simulate in the same way.
+#[derive(Debug, Clone, BitStore)]
+pub struct PhysicsConfig {
+    /// Number of iterations of the constraint solver in each physics step.
+    pub solver_iterations: u32,
+
+    /// Distance that contact constraints keep between touching entities.
+    pub contact_margin: f32,
+
+    /// Number of physics steps in each tick. More steps make stiff constraints, such as long
+    /// hook chains, more stable at low tick rates.
+    pub substeps: u32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> PhysicsConfig {
+        PhysicsConfig {
+            solver_iterations: 20,
+            contact_margin: 1.0,
+            substeps: 1,
+        }
+    }
+}
+
 /// Sent to the clients by the server after connecting.
 #[derive(Debug, Clone, BitStore)]
 pub struct GameInfo {
@@ -56,6 +81,7 @@ pub struct GameInfo {
     pub player_entity_class: String,
     pub server_target_lag_inputs: TickNum,
     pub client_target_lag_snapshots: TickNum,
+    pub physics: PhysicsConfig,
 }
 
 impl GameInfo {
@@ -67,6 +93,16 @@ impl GameInfo {
         1.0 / (self.ticks_per_second as f32)
     }
 
+    /// Number of physics steps in each tick.
+    pub fn physics_substeps(&self) -> u32 {
+        self.physics.substeps.max(1)
+    }
+
+    /// Duration of one physics step.
+    pub fn physics_step_secs(&self) -> f32 {
+        self.tick_duration_secs() / self.physics_substeps() as f32
+    }
+
     /// Estimate in which tick a client's input will be run on the server.
     pub fn input_target_tick(&self, ping_secs: f32, client_tick: TickNum) -> TickNum {
         // Crude estimate based on ping, I guess time synchronization stuff could help here...
@@ -107,5 +143,9 @@ pub enum DeathReason {
     Caught(PlayerId),
 }
 
+/// New statistics of a player, sent by the server whenever they change.
 #[derive(Debug, Clone, BitStore)]
-pub struct PlayerStatsUpdate;
+pub struct PlayerStatsUpdate {
+    pub id: PlayerId,
+    pub stats: PlayerStats,
+}
@@ -1,27 +1,45 @@
 use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Arc;
 
-use specs::prelude::{Entity, EntityBuilder, Join, VecStorage, World};
-use specs::storage::NullStorage;
+use specs::prelude::{Entities, Entity, EntityBuilder, Join, ReadStorage, VecStorage, World,
+                     WriteStorage};
+use specs::storage::{BTreeStorage, NullStorage};
 
-use defs::EntityClassId;
+use defs::{EntityClassId, EntityId};
 use registry::Registry;
 
 pub fn register(reg: &mut Registry) {
-    reg.resource(Ctors(BTreeMap::new()));
+    reg.resource(Ctors::default());
     reg.resource(ClassIds(BTreeMap::new()));
 
+    reg.freeze_fn(freeze);
+
     reg.component::<Meta>();
     reg.component::<Active>();
+    reg.component::<Parent>();
     reg.component::<Remove>();
 }
 
-pub type Ctor = fn(EntityBuilder) -> EntityBuilder;
+/// Constructor for adding components to an entity. Constructors are shared, so that they can carry
+/// data, e.g. the definition of a class that was loaded from a file.
+pub type Ctor = Arc<Fn(EntityBuilder) -> EntityBuilder + Send + Sync>;
 
 /// Constructors, e.g. for adding client-side-specific components to replicated entities.
-struct Ctors(BTreeMap<EntityClassId, Vec<Ctor>>);
+#[derive(Default)]
+struct Ctors {
+    /// Constructors by class name, filled during registration.
+    by_name: BTreeMap<String, Vec<Ctor>>,
+
+    /// Constructors indexed by class id, filled in `freeze`.
+    by_id: Vec<Vec<Ctor>>,
+
+    frozen: bool,
+}
 
 /// Maps from entity class names to their unique id. This map should be exactly the same on server
-/// and clients and not change during a game.
+/// and clients and not change during a game. The ids are assigned in `freeze`, in the order of the
+/// sorted class names, so they do not depend on the order in which classes are registered.
 pub struct ClassIds(pub BTreeMap<String, EntityClassId>);
 
 /// Meta-information about entities.
@@ -29,6 +47,10 @@ pub struct ClassIds(pub BTreeMap<String, EntityClassId>);
 #[storage(VecStorage)]
 pub struct Meta {
     pub class_id: EntityClassId,
+
+    /// Shared id of the parent entity, if this is a replicated entity that has a parent. Since
+    /// `Meta` is sent along with every new entity, this is how clients learn about the hierarchy.
+    pub parent: Option<EntityId>,
 }
 
 /// Is this entity active in the game?
@@ -36,13 +58,19 @@ pub struct Meta {
 #[storage(NullStorage)]
 pub struct Active;
 
+/// The parent of an entity. When an entity is removed with `deferred_remove`, all of its
+/// descendants are removed as well. For replicated entities, this is maintained by `repl::entity`.
+#[derive(Component, PartialEq, Debug, Clone)]
+#[storage(BTreeStorage)]
+pub struct Parent(pub Entity);
+
 /// Entities tagged with this component shall be removed at the end of the tick.
 #[derive(Component, Debug, Default)]
 #[storage(NullStorage)]
 pub struct Remove;
 
 pub fn is_class_id_valid(world: &World, class_id: EntityClassId) -> bool {
-    world.read_resource::<Ctors>().0.contains_key(&class_id)
+    (class_id as usize) < world.read_resource::<Ctors>().by_id.len()
 }
 
 pub fn get_class_id(world: &World, name: &str) -> Option<EntityClassId> {
@@ -50,51 +78,72 @@ pub fn get_class_id(world: &World, name: &str) -> Option<EntityClassId> {
 }
 
 /// Register a new entity class with a base constructor to add components that are always present.
-pub fn register_class(reg: &mut Registry, name: &str, ctor: Ctor) -> EntityClassId {
-    let world = reg.world();
-
-    let mut ctors = world.write_resource::<Ctors>();
-    let mut class_ids = world.write_resource::<ClassIds>();
-
-    let class_id = ctors.0.keys().next_back().map(|&id| id + 1).unwrap_or(0);
-
-    assert!(!ctors.0.contains_key(&class_id));
-    assert!(!class_ids.0.values().any(|&id| id == class_id));
-
-    ctors.0.insert(class_id, vec![ctor]);
-    class_ids.0.insert(name.to_string(), class_id);
+/// The class id is only assigned once registration is finished.
+pub fn register_class<F>(reg: &mut Registry, name: &str, ctor: F)
+where
+    F: Fn(EntityBuilder) -> EntityBuilder + Send + Sync + 'static,
+{
+    let mut ctors = reg.world().write_resource::<Ctors>();
 
-    assert!(ctors.0.len() == class_ids.0.len());
+    assert!(!ctors.frozen, "entity class {} registered after freezing", name);
+    assert!(
+        !ctors.by_name.contains_key(name),
+        "entity class {} was registered twice",
+        name
+    );
 
-    class_id
+    ctors.by_name.insert(name.to_string(), vec![Arc::new(ctor)]);
 }
 
 /// Add a constructor to an existing entity class. This can be used by clients, for example, to add
 /// rendering-specific components to entities.
-pub fn add_ctor(reg: &mut Registry, name: &str, ctor: Ctor) {
-    let world = reg.world();
+pub fn add_ctor<F>(reg: &mut Registry, name: &str, ctor: F)
+where
+    F: Fn(EntityBuilder) -> EntityBuilder + Send + Sync + 'static,
+{
+    let mut ctors = reg.world().write_resource::<Ctors>();
 
-    let class_id = {
-        let class_ids = world.read_resource::<ClassIds>();
-        class_ids.0[name]
-    };
+    assert!(!ctors.frozen, "constructor for {} added after freezing", name);
+
+    let ctor_vec = ctors
+        .by_name
+        .get_mut(name)
+        .unwrap_or_else(|| panic!("entity class {} has not been registered", name));
+    ctor_vec.push(Arc::new(ctor));
+}
 
+/// Assign ids to the registered entity classes. This is called when the `Registry` is turned into
+/// a `game::State`, after which no more classes can be registered.
+fn freeze(world: &mut World) {
     let mut ctors = world.write_resource::<Ctors>();
-    let ctor_vec = ctors.0.get_mut(&class_id).unwrap();
-    ctor_vec.push(ctor);
+    let mut class_ids = world.write_resource::<ClassIds>();
+
+    assert!(!ctors.frozen, "entity classes were frozen twice");
+
+    // `BTreeMap` iterates in the order of the sorted names
+    let by_name = mem::replace(&mut ctors.by_name, BTreeMap::new());
+
+    for (class_id, (name, class_ctors)) in by_name.into_iter().enumerate() {
+        info!("Entity class {} has id {}", name, class_id);
+
+        class_ids.0.insert(name, class_id as EntityClassId);
+        ctors.by_id.push(class_ctors);
+    }
+
+    ctors.frozen = true;
 }
 
 /// Create a new entity of the given entity class, using the constructors associated with the
 /// class. Note that entities created with this function are not replicated automatically.
 /// Replicated entities should be created with `repl::entity::create`.
-pub fn create<F>(world: &mut World, class_id: EntityClassId, ctor: F) -> Entity
+pub fn create<F>(world: &mut World, meta: Meta, ctor: F) -> Entity
 where
     F: FnOnce(EntityBuilder) -> EntityBuilder,
 {
-    let ctors = world.read_resource::<Ctors>().0[&class_id].clone();
+    let ctors = world.read_resource::<Ctors>().by_id[meta.class_id as usize].clone();
 
     // Build entity
-    let builder = world.create_entity().with(Meta { class_id }).with(Active);
+    let builder = world.create_entity().with(meta).with(Active);
 
     let builder = ctors.iter().fold(builder, |builder, ctor| ctor(builder));
 
@@ -104,11 +153,59 @@ where
     builder.build()
 }
 
-/// Register that an entity should be removed. The entity is tagged with a `Remove` component,
-/// giving systems a chance to know about the removal. The entities are removed with the next call
-/// to `perform_removals`.
+/// Register that an entity should be removed. The entity and all of its descendants are tagged
+/// with a `Remove` component, giving systems a chance to know about the removal. The entities are
+/// removed with the next call to `perform_removals`.
 pub fn deferred_remove(world: &World, entity: Entity) {
-    world.write::<Remove>().insert(entity, Remove);
+    let entities = world.entities();
+    let parent = world.read::<Parent>();
+    let mut remove = world.write::<Remove>();
+
+    deferred_remove_with(&entities, &parent, &mut remove, entity);
+}
+
+/// Same as `deferred_remove`, but for use in systems.
+pub fn deferred_remove_with(
+    entities: &Entities,
+    parent: &ReadStorage<Parent>,
+    remove: &mut WriteStorage<Remove>,
+    entity: Entity,
+) {
+    // Only built once we know that there is something to remove
+    let mut children: Option<BTreeMap<Entity, Vec<Entity>>> = None;
+
+    let mut queue = vec![entity];
+
+    while let Some(entity) = queue.pop() {
+        if remove.get(entity).is_some() {
+            // Already tagged, and so are its descendants
+            continue;
+        }
+
+        remove.insert(entity, Remove);
+
+        let children = children.get_or_insert_with(|| children_map(entities, parent));
+        if let Some(entity_children) = children.get(&entity) {
+            queue.extend(entity_children);
+        }
+    }
+}
+
+/// Map from entities to their children.
+fn children_map(
+    entities: &Entities,
+    parent: &ReadStorage<Parent>,
+) -> BTreeMap<Entity, Vec<Entity>> {
+    let mut children = BTreeMap::new();
+
+    for (child, parent) in (&**entities, parent).join() {
+        children
+            .entry(parent.0)
+            .or_insert_with(Vec::new)
+            .push(child);
+    }
+
+    children
 }
 
 /// Remove entities tagged with `Remove` from the world.
@@ -126,3 +223,63 @@ pub fn perform_removals(world: &mut World) {
 
     world.maintain();
 }
+
+#[cfg(test)]
+mod tests {
+    use specs::prelude::{Entity, World};
+
+    use super::{deferred_remove, perform_removals, Parent, Remove};
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Parent>();
+        world.register::<Remove>();
+        world
+    }
+
+    fn create(world: &mut World, parent: Option<Entity>) -> Entity {
+        let builder = world.create_entity();
+
+        match parent {
+            Some(parent) => builder.with(Parent(parent)).build(),
+            None => builder.build(),
+        }
+    }
+
+    #[test]
+    fn test_remove_descendants() {
+        let mut world = world();
+
+        let root = create(&mut world, None);
+        let a = create(&mut world, Some(root));
+        let b = create(&mut world, Some(root));
+        let a_child = create(&mut world, Some(a));
+        let a_grandchild = create(&mut world, Some(a_child));
+        let other = create(&mut world, None);
+        let other_child = create(&mut world, Some(other));
+
+        // Removing a descendant first does not stop us from removing the rest of the tree
+        deferred_remove(&world, a_child);
+        deferred_remove(&world, root);
+
+        {
+            let remove = world.read::<Remove>();
+            for &entity in &[root, a, b, a_child, a_grandchild] {
+                assert!(remove.get(entity).is_some());
+            }
+            for &entity in &[other, other_child] {
+                assert!(remove.get(entity).is_none());
+            }
+        }
+
+        perform_removals(&mut world);
+
+        for &entity in &[root, a, b, a_child, a_grandchild] {
+            assert!(!world.entities().is_alive(entity));
+        }
+        for &entity in &[other, other_child] {
+            assert!(world.entities().is_alive(entity));
+        }
+        assert!(world.read::<Remove>().get(other).is_none());
+    }
+}
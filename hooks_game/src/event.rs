@@ -83,6 +83,8 @@ macro_rules! match_event {
 /// Event type
 #[derive(Clone)]
 struct Type {
+    pub name: &'static str,
+    pub type_id: any::TypeId,
     pub read: fn(&mut Reader) -> bit_manager::Result<Box<Event>>,
 }
 
@@ -90,13 +92,19 @@ fn read_event<T: Event + BitStore>(reader: &mut Reader) -> bit_manager::Result<B
     Ok(Box::new(T::read_from(reader)?))
 }
 
+/// Registry of all event types that can be sent over the network. Every type is registered with a
+/// unique name, and type indices are assigned in `freeze`, in the order of the sorted names. This
+/// way, the indices do not depend on the order in which server and clients register their events,
+/// nor on the compiler that they have been built with.
 #[derive(Clone, Default)]
 pub struct Registry {
-    /// Event types, indexed by TypeIndex
+    /// Event types, indexed by TypeIndex after freezing
     types: Vec<Type>,
 
-    /// Map from TypeId to index into `types`
+    /// Map from TypeId to index into `types`, filled in `freeze`
     type_indices: BTreeMap<any::TypeId, TypeIndex>,
+
+    frozen: bool,
 }
 
 impl Registry {
@@ -104,24 +112,63 @@ impl Registry {
         Default::default()
     }
 
-    pub fn register<T: Event + BitStore>(&mut self) {
+    pub fn register<T: Event + BitStore>(&mut self, name: &'static str) {
+        let type_id = any::TypeId::of::<T>();
+
+        assert!(!self.frozen, "event type {} registered after freezing", name);
         assert!(
-            self.types.len() <= u16::MAX as usize,
+            !self.types.iter().any(|t| t.type_id == type_id),
+            "event type {} was registered twice",
+            name
+        );
+        assert!(
+            !self.types.iter().any(|t| t.name == name),
+            "event type name {} was used twice",
+            name
+        );
+        assert!(
+            self.types.len() < u16::MAX as usize,
             "too many event types"
         );
 
-        let type_id = any::TypeId::of::<T>();
-        let type_index = self.types.len() as u16;
-
-        let event_type = Type {
+        self.types.push(Type {
+            name,
+            type_id,
             read: read_event::<T>,
-        };
+        });
+    }
 
-        self.types.push(event_type);
-        self.type_indices.insert(type_id, type_index);
+    /// Assign type indices to the registered event types. No more types can be registered
+    /// afterwards.
+    pub fn freeze(&mut self) {
+        assert!(!self.frozen, "event registry was frozen twice");
+
+        self.types.sort_by_key(|t| t.name);
+        self.type_indices = self.types
+            .iter()
+            .enumerate()
+            .map(|(index, t)| (t.type_id, index as TypeIndex))
+            .collect();
+        self.frozen = true;
+
+        for (index, t) in self.types.iter().enumerate() {
+            debug!("Event type {} has index {}", t.name, index);
+        }
+    }
+
+    /// Name with which the type of an event was registered.
+    pub fn type_name(&self, event: &Event) -> Option<&'static str> {
+        let type_id = event.type_id();
+
+        self.types
+            .iter()
+            .find(|t| t.type_id == type_id)
+            .map(|t| t.name)
     }
 
     pub fn write(&self, event: &Event, writer: &mut Writer) -> Result<(), bit_manager::Error> {
+        assert!(self.frozen, "event registry needs to be frozen before writing");
+
         let type_id = event.type_id();
         let type_index = self.type_indices[&type_id];
 
@@ -130,6 +177,8 @@ impl Registry {
     }
 
     pub fn read(&self, reader: &mut Reader) -> Result<Box<Event>, Error> {
+        assert!(self.frozen, "event registry needs to be frozen before reading");
+
         let type_index = reader.read::<TypeIndex>()?;
 
         if let Some(event_type) = self.types.get(type_index as usize) {
@@ -255,9 +304,10 @@ mod tests {
     #[test]
     fn test_write_read() {
         let mut reg = Registry::new();
-        reg.register::<A>();
-        reg.register::<B>();
-        reg.register::<C>();
+        reg.register::<A>("A");
+        reg.register::<B>("B");
+        reg.register::<C>("C");
+        reg.freeze();
 
         let event: Box<Event> = Box::new(C::Y(42, true));
 
@@ -284,4 +334,53 @@ mod tests {
 
         assert!(n == 1);
     }
+
+    #[test]
+    fn test_order_independent() {
+        let mut reg_a = Registry::new();
+        reg_a.register::<A>("A");
+        reg_a.register::<B>("B");
+        reg_a.register::<C>("C");
+        reg_a.freeze();
+
+        let mut reg_b = Registry::new();
+        reg_b.register::<C>("C");
+        reg_b.register::<A>("A");
+        reg_b.register::<B>("B");
+        reg_b.freeze();
+
+        let event: Box<Event> = Box::new(B(true));
+
+        let data = {
+            let mut writer = BitWriter::new(Vec::new());
+            reg_a.write(&*event, &mut writer).unwrap();
+            writer.into_inner().unwrap()
+        };
+
+        let read_event = {
+            let mut reader = BitReader::new(Cursor::new(data));
+            reg_b.read(&mut reader).unwrap()
+        };
+
+        assert!(read_event.downcast_ref::<B>().is_some());
+    }
+
+    #[test]
+    fn test_type_name() {
+        let mut reg = Registry::new();
+        reg.register::<A>("A");
+        reg.register::<C>("C");
+        reg.freeze();
+
+        assert_eq!(reg.type_name(&C::X), Some("C"));
+        assert_eq!(reg.type_name(&B(true)), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_name_twice() {
+        let mut reg = Registry::new();
+        reg.register::<A>("A");
+        reg.register::<B>("A");
+    }
 }
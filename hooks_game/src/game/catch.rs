@@ -1,18 +1,240 @@
+use defs::{DeathReason, GameInfo, PlayerId};
+use event::{self, Event};
+use plugin;
+use registry::Registry;
+
+/// Game mode in which players are spawned at random positions and catch each other with their
+/// hooks.
+///
+/// A player is caught once an opponent has kept a hook fixed to the player's entity for
+/// `CATCH_SECS` seconds. The catcher scores a point, and the victim dies and is respawned. Two
+/// players that would catch each other in the same tick cancel out, and nobody scores.
+pub struct Plugin;
+
+impl plugin::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "catch"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["game::entity"]
+    }
+
+    fn register(&self, reg: &mut Registry, _: &GameInfo) {
+        reg.event::<DiedEvent>("DiedEvent");
+    }
+
+    fn register_auth(&self, reg: &mut Registry, _: &GameInfo) {
+        auth::register(reg);
+    }
+}
+
+/// How long a hook needs to stay fixed to an opponent to catch them.
+pub const CATCH_SECS: f32 = 2.0;
+
+/// This event is emitted when the entity of a player is removed because the player died. The
+/// server spawns a new entity for the player in the next tick.
+#[derive(Debug, Clone, BitStore)]
+pub struct DiedEvent {
+    pub id: PlayerId,
+    pub reason: DeathReason,
+}
+
+impl Event for DiedEvent {
+    fn class(&self) -> event::Class {
+        event::Class::Order
+    }
+}
+
 pub mod auth {
-    use nalgebra::Point2;
-    use rand::{self, Rng};
-    use specs::prelude::World;
+    use std::collections::{BTreeMap, BTreeSet};
 
-    use defs::GameInfo;
-    use game::entity;
+    use bit_manager::data::BitStore;
+    use bit_manager::{self, BitRead, BitWrite};
+    use nalgebra::{Isometry2, Point2};
+    use rand::{Rng, XorShiftRng};
+    use specs::prelude::{Join, World};
+
+    use defs::{DeathReason, GameInfo, PlayerId, PlayerStatsUpdate};
+    use entity;
+    use event;
+    use game::entity::{hook, player};
+    use physics::layer;
+    use physics::query::{self, Filter};
     use registry::Registry;
     use repl;
+    use save;
+
+    use super::{DiedEvent, CATCH_SECS};
 
     pub fn register(reg: &mut Registry) {
+        reg.resource(SpawnRng(XorShiftRng::new_unseeded()));
+        reg.saved_resource(PinTimers::default());
         reg.pre_tick_fn(pre_tick);
     }
 
+    /// Random number generator for spawn positions. We use a fixed seed, so that running the same
+    /// ticks twice gives the same result.
+    struct SpawnRng(XorShiftRng);
+
+    /// For every pair of `(victim, catcher)` players, the number of seconds for which the catcher
+    /// has had a hook fixed to the victim without interruption.
+    #[derive(Default)]
+    struct PinTimers(BTreeMap<(PlayerId, PlayerId), f32>);
+
+    impl BitStore for PinTimers {
+        fn read_from<R: BitRead>(reader: &mut R) -> bit_manager::Result<Self> {
+            let len: u32 = reader.read()?;
+
+            let mut timers = BTreeMap::new();
+            for _ in 0..len {
+                let pair = (reader.read()?, reader.read()?);
+                timers.insert(pair, reader.read()?);
+            }
+
+            Ok(PinTimers(timers))
+        }
+
+        fn write_to<W: BitWrite>(&self, writer: &mut W) -> bit_manager::Result<()> {
+            writer.write(&(self.0.len() as u32))?;
+            for (&(victim, catcher), secs) in &self.0 {
+                writer.write(&victim)?;
+                writer.write(&catcher)?;
+                writer.write(secs)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl save::Resource for PinTimers {}
+
+    impl PinTimers {
+        /// Advance the timers of the pinned pairs and forget about all other pairs. Returns the
+        /// pairs that have been pinned for long enough to count as a catch.
+        ///
+        /// If two players would catch each other, neither of them is caught, and both of their
+        /// timers start again.
+        fn update(
+            &mut self,
+            pinned: &BTreeSet<(PlayerId, PlayerId)>,
+            dt: f32,
+        ) -> Vec<(PlayerId, PlayerId)> {
+            self.0 = pinned
+                .iter()
+                .map(|&pair| (pair, self.0.get(&pair).cloned().unwrap_or(0.0) + dt))
+                .collect();
+
+            let caught = self.0
+                .iter()
+                .filter(|&(_, &secs)| secs >= CATCH_SECS)
+                .map(|(&pair, _)| pair)
+                .collect::<BTreeSet<_>>();
+
+            let mutual = caught
+                .iter()
+                .filter(|&&(victim, catcher)| caught.contains(&(catcher, victim)))
+                .cloned()
+                .collect::<BTreeSet<_>>();
+
+            for pair in &mutual {
+                self.0.remove(pair);
+            }
+
+            caught.difference(&mutual).cloned().collect()
+        }
+    }
+
     fn pre_tick(world: &mut World) -> Result<(), repl::Error> {
+        // Catching looks at the hooks as they were at the end of the last tick. Caught players
+        // are removed after the pre-tick functions, and respawned in the next tick.
+        catch(world);
+        spawn(world);
+
+        Ok(())
+    }
+
+    /// Find the `(victim, catcher)` pairs of players for which the catcher has a hook fixed to
+    /// the main entity of the victim.
+    fn pinned_pairs(world: &World) -> BTreeSet<(PlayerId, PlayerId)> {
+        let players = world.read_resource::<repl::player::Players>();
+        let repl_id = world.read::<repl::Id>();
+        let hook_def = world.read::<hook::Def>();
+        let hook_state = world.read::<hook::State>();
+
+        let player_entity_ids = players
+            .iter()
+            .filter_map(|(&player_id, player)| {
+                let id = repl_id.get(player.entity?)?;
+                Some((id.0, player_id))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        (&hook_def, &hook_state)
+            .join()
+            .filter_map(|(def, state)| {
+                let (fixed_id, _) = state.0.as_ref()?.fixed?;
+                let victim = *player_entity_ids.get(&fixed_id)?;
+                let catcher = def.owner.0;
+
+                if catcher != victim && players.get(catcher).is_some() {
+                    Some((victim, catcher))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn catch(world: &mut World) {
+        let dt = world.read_resource::<GameInfo>().tick_duration_secs();
+        let pinned = pinned_pairs(world);
+        let caught = world.write_resource::<PinTimers>().update(&pinned, dt);
+
+        let mut died = BTreeSet::new();
+
+        for (victim, catcher) in caught {
+            if !died.insert(victim) {
+                // Caught by multiple players at once, only the first one scores
+                continue;
+            }
+
+            debug!("Player {} was caught by player {}", victim, catcher);
+
+            // Clients update their statistics with the `PlayerStatsUpdate` events
+            let victim_entity = {
+                let mut players = world.write_resource::<repl::player::Players>();
+                let mut events = world.write_resource::<event::Sink>();
+
+                players.0.get_mut(&catcher).unwrap().info.stats.score += 1;
+                players.0.get_mut(&victim).unwrap().info.stats.deaths += 1;
+
+                events.push(DiedEvent {
+                    id: victim,
+                    reason: DeathReason::Caught(catcher),
+                });
+
+                for &id in &[victim, catcher] {
+                    events.push(PlayerStatsUpdate {
+                        id,
+                        stats: players.0[&id].info.stats.clone(),
+                    });
+                }
+
+                players.0[&victim].entity
+            };
+
+            if let Some(victim_entity) = victim_entity {
+                entity::deferred_remove(world, victim_entity);
+            }
+        }
+    }
+
+    /// How many random positions are tried for spawning a player before giving up on finding a
+    /// free one.
+    const SPAWN_ATTEMPTS: usize = 20;
+
+    fn spawn(world: &mut World) {
         let player_entity_class = world
             .read_resource::<GameInfo>()
             .player_entity_class
@@ -21,19 +243,212 @@ pub mod auth {
 
         let players = world.read_resource::<repl::player::Players>().clone();
 
+        // Players that are spawned in this tick are not in the collision world yet
+        let mut spawned = Vec::new();
+
         for (&player_id, player) in players.iter() {
             if player.entity.is_none() {
-                let mut rng = rand::thread_rng();
+                let pos = spawn_pos(world, &spawned);
+                player::auth::create(world, player_id, pos);
+                spawned.push(pos);
+            }
+        }
+    }
 
-                // TODO: Spawn points here
-                let pos = Point2::new(
-                    rng.next_f32() * 200.00 - 100.0,
+    /// Find a random position at which a player does not overlap any entity. If there is none,
+    /// the player is spawned at the last position that was tried.
+    fn spawn_pos(world: &World, spawned: &[Point2<f32>]) -> Point2<f32> {
+        let shape = player::shape();
+        let filter = Filter::groups(layer::groups(world, "player"));
+
+        let mut pos = Point2::origin();
+
+        for _ in 0..SPAWN_ATTEMPTS {
+            pos = {
+                let rng = &mut world.write_resource::<SpawnRng>().0;
+                Point2::new(
                     rng.next_f32() * 200.0 - 100.0,
-                );
-                entity::player::auth::create(world, player_id, pos);
+                    rng.next_f32() * 200.0 - 100.0,
+                )
+            };
+
+            let overlaps_spawned = spawned.iter().any(|other| {
+                (pos.x - other.x).abs() < player::WIDTH && (pos.y - other.y).abs() < player::HEIGHT
+            });
+            let isometry = Isometry2::new(pos.coords, 0.0);
+
+            if !overlaps_spawned && query::is_free(world, &shape, &isometry, &filter) {
+                break;
             }
         }
 
-        Ok(())
+        pos
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::BTreeSet;
+        use std::io::Cursor;
+
+        use bit_manager::{BitReader, BitWriter};
+        use specs::prelude::Join;
+
+        use defs::{DeathReason, EntityId, PlayerId, PlayerInfo, PlayerInput, PlayerStatsUpdate};
+        use event::Event;
+        use game::entity::hook;
+        use game::run::AuthRunner;
+        use game::{init, save, testing, State};
+        use repl::player::{JoinedEvent, Players};
+        use repl::{self, Id};
+
+        use super::super::DiedEvent;
+        use super::{PinTimers, CATCH_SECS};
+
+        #[test]
+        fn test_pin_timers() {
+            let dt = CATCH_SECS / 4.0;
+            let mut timers = PinTimers::default();
+
+            let both = [(1, 2), (3, 2)].iter().cloned().collect::<BTreeSet<_>>();
+            let one = [(1, 2)].iter().cloned().collect::<BTreeSet<_>>();
+
+            for _ in 0..3 {
+                assert_eq!(timers.update(&both, dt), vec![]);
+            }
+
+            // Letting go resets the timer
+            assert_eq!(timers.update(&one, dt), vec![(1, 2)]);
+            assert_eq!(timers.update(&both, dt), vec![(1, 2)]);
+            assert_eq!(timers.0[&(3, 2)], dt);
+        }
+
+        #[test]
+        fn test_mutual_pins() {
+            let mut timers = PinTimers::default();
+
+            let mutual = [(1, 2), (2, 1), (3, 1)]
+                .iter()
+                .cloned()
+                .collect::<BTreeSet<_>>();
+
+            // Player 3 still catches player 1, but players 1 and 2 cancel out
+            assert_eq!(timers.update(&mutual, CATCH_SECS), vec![(1, 3)]);
+            assert_eq!(timers.0.get(&(1, 2)), None);
+            assert_eq!(timers.0.get(&(2, 1)), None);
+        }
+
+        #[test]
+        fn test_save_pin_timers() {
+            let game_info = testing::game_info();
+
+            let state = testing::auth_state(&game_info);
+            state.world.write_resource::<PinTimers>().0.insert((1, 2), 0.5);
+            state.world.write_resource::<PinTimers>().0.insert((2, 3), 1.5);
+
+            let mut writer = BitWriter::new(Vec::new());
+            save::save(&state.world, 0, &mut writer).unwrap();
+            let data = writer.into_inner().unwrap();
+
+            let mut loaded = testing::auth_state(&game_info);
+            let mut reader = BitReader::new(Cursor::new(data));
+            save::load(&mut loaded.world, &mut reader).unwrap();
+
+            assert_eq!(
+                loaded.world.read_resource::<PinTimers>().0,
+                state.world.read_resource::<PinTimers>().0
+            );
+        }
+
+        fn entity_id(state: &State, player_id: PlayerId) -> Option<EntityId> {
+            let players = state.world.read_resource::<Players>();
+            let entity = players.get(player_id)?.entity?;
+
+            state.world.read::<Id>().get(entity).map(|id| id.0)
+        }
+
+        #[test]
+        fn test_catch() {
+            let game_info = testing::game_info();
+            let mut state = testing::auth_state(&game_info);
+            let mut runner = AuthRunner::new(&mut state.world);
+
+            init::auth::create_state(&mut state.world);
+
+            for &id in &[1, 2] {
+                state.push_events(vec![
+                    Box::new(JoinedEvent {
+                        id,
+                        info: PlayerInfo::new(format!("player{}", id)),
+                    }),
+                ]);
+            }
+
+            // Players are spawned in the first tick, then player 1 shoots a hook
+            runner.run_tick(&mut state, vec![]).unwrap();
+
+            let shoot = PlayerInput {
+                shoot_one: true,
+                ..PlayerInput::default()
+            };
+            runner.run_tick(&mut state, vec![vec![(1, shoot)]]).unwrap();
+
+            // Fix the hook to player 2. Hooks only change when their owner's input is run, so it
+            // stays fixed while we run ticks without input.
+            let victim_id = entity_id(&state, 2).unwrap();
+            {
+                let hook_def = state.world.read::<hook::Def>();
+                let mut hook_state = state.world.write::<hook::State>();
+
+                let active_state = (&hook_def, &mut hook_state)
+                    .join()
+                    .filter(|&(def, _)| def.owner.0 == 1)
+                    .filter_map(|(_, state)| state.0.as_mut())
+                    .next()
+                    .unwrap();
+
+                active_state.fixed = Some((victim_id, [0.0, 0.0]));
+            }
+
+            let max_ticks = (CATCH_SECS * game_info.ticks_per_second as f32) as usize + 2;
+            let mut events: Vec<Box<Event>> = Vec::new();
+
+            for _ in 0..max_ticks {
+                events = runner.run_tick(&mut state, vec![]).unwrap();
+
+                if events.iter().any(|event| event.downcast_ref::<DiedEvent>().is_some()) {
+                    break;
+                }
+            }
+
+            let died = events
+                .iter()
+                .filter_map(|event| event.downcast_ref::<DiedEvent>())
+                .map(|event| match event.reason {
+                    DeathReason::Caught(catcher) => (event.id, catcher),
+                })
+                .collect::<Vec<_>>();
+            let stats = events
+                .iter()
+                .filter_map(|event| event.downcast_ref::<PlayerStatsUpdate>())
+                .map(|event| (event.id, event.stats.score, event.stats.deaths))
+                .collect::<Vec<_>>();
+
+            assert_eq!(died, vec![(2, 1)]);
+            assert_eq!(stats, vec![(2, 0, 1), (1, 1, 0)]);
+
+            // The victim's entity is gone at the end of the tick, and a new one is spawned in the
+            // next tick
+            assert!(repl::get_id_to_entity(&state.world, victim_id).is_none());
+            assert_eq!(entity_id(&state, 2), None);
+
+            runner.run_tick(&mut state, vec![]).unwrap();
+
+            let respawned_id = entity_id(&state, 2).unwrap();
+            assert!(respawned_id != victim_id);
+
+            let players = state.world.read_resource::<Players>();
+            assert_eq!(players.get(1).unwrap().info.stats.score, 1);
+            assert_eq!(players.get(2).unwrap().info.stats.deaths, 1);
+        }
     }
 }
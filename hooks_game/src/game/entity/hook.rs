@@ -9,12 +9,12 @@ use defs::{EntityId, GameInfo, INVALID_ENTITY_ID};
 use entity::Active;
 use event::{self, Event};
 use game::ComponentType;
-use physics::collision::{self, CollisionGroups, Cuboid, GeometricQueryType, ShapeHandle};
+use physics::collision::{self, Cuboid, ShapeHandle};
 use physics::constraint::{self, Constraint, Pose};
 use physics::sim::Constraints;
-use physics::{self, interaction};
-use physics::{AngularVelocity, Dynamic, Friction, InvAngularMass, InvMass, Orientation, Position,
-              Update, Velocity};
+use physics::{self, interaction, layer};
+use physics::{AngularVelocity, Ccd, Dynamic, Friction, InvAngularMass, InvMass, Orientation,
+              Position, Update, Velocity};
 use registry::Registry;
 use repl;
 
@@ -25,9 +25,9 @@ pub fn register(reg: &mut Registry) {
     reg.component::<CurrentInput>();
     reg.component::<CurrentConstraints>();
 
-    reg.event::<FixedEvent>();
-    reg.event::<UnfixedEvent>();
-    reg.event::<DespawnedEvent>();
+    reg.event::<FixedEvent>("FixedEvent");
+    reg.event::<UnfixedEvent>("UnfixedEvent");
+    reg.event::<DespawnedEvent>("DespawnedEvent");
 
     repl::entity::register_class(
         reg,
@@ -67,6 +67,14 @@ pub fn register(reg: &mut Registry) {
         |builder| builder,
     );
 
+    // Hook segments are in the layer of entities that belong to a player
+    layer::add(reg, "player_entity");
+    layer::set_class(reg, "hook_segment", "player_entity");
+    layer::set_class(reg, "first_hook_segment", "player_entity");
+    layer::set_collide(reg, "player_entity", "wall");
+    layer::set_collide(reg, "player_entity", "player");
+    //layer::set_collide(reg, "player_entity", "player_entity");
+
     interaction::set(
         reg,
         "hook_segment",
@@ -75,7 +83,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
     interaction::set(
         reg,
@@ -85,14 +93,14 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        Some(first_segment_interaction),
+        interaction::Callbacks::touching(first_segment_interaction),
     );
     interaction::set(
         reg,
         "first_hook_segment",
         "hook_segment",
         None,
-        Some(first_segment_interaction),
+        interaction::Callbacks::touching(first_segment_interaction),
     );
     interaction::set(
         reg,
@@ -102,7 +110,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        Some(first_segment_interaction),
+        interaction::Callbacks::touching(first_segment_interaction),
     );
     interaction::set(
         reg,
@@ -112,7 +120,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
     interaction::set(
         reg,
@@ -122,7 +130,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
     interaction::set(
         reg,
@@ -132,7 +140,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
 
     interaction::set(
@@ -143,7 +151,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        Some(first_segment_interaction),
+        interaction::Callbacks::touching(first_segment_interaction),
     );
     interaction::set(
         reg,
@@ -153,7 +161,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
 }
 
@@ -164,8 +172,8 @@ pub const JOIN_MARGIN: f32 = 1.0;
 pub const SHOOT_SPEED: f32 = 1000.0;
 pub const LUNCH_TIME_SECS: f32 = 0.025;
 pub const LUNCH_RADIUS: f32 = 5.0;
-pub const ANGLE_STIFFNESS: f32 = 0.7;
-pub const OWNER_ANGLE_STIFFNESS: f32 = 1.0;
+/// Compliance of the angle between neighboring segments, allowing the hook to bend a bit.
+pub const ANGLE_COMPLIANCE: f32 = 1e-7;
 pub const FIX_MAX_DISTANCE: f32 = 15.0;
 pub const SEGMENT_MAX_DISTANCE: f32 = 20.0;
 pub const MAX_VIOLATION_SECS: f32 = 0.35;
@@ -334,16 +342,7 @@ struct CurrentConstraints {
 fn build_segment(builder: EntityBuilder) -> EntityBuilder {
     let shape = Cuboid::new(Vector2::new(SEGMENT_LENGTH / 2.0, 1.5));
 
-    let mut groups = CollisionGroups::new();
-    groups.set_membership(&[collision::GROUP_PLAYER_ENTITY]);
-    groups.set_whitelist(&[
-        collision::GROUP_WALL,
-        collision::GROUP_PLAYER,
-        //collision::GROUP_PLAYER_ENTITY,
-        collision::GROUP_NEUTRAL,
-    ]);
-
-    let query_type = GeometricQueryType::Contacts(0.0, 0.0);
+    let object = layer::object(builder.world, "player_entity");
 
     // TODO: Velocity (and Dynamic?) component should be added only for owners
     builder
@@ -356,9 +355,10 @@ fn build_segment(builder: EntityBuilder) -> EntityBuilder {
             12.0 / (5.0 * (SEGMENT_LENGTH.powi(2) + 18.0)),
         ))
         .with(Dynamic)
+        .with(Ccd)
         .with(Friction(5.0))
         .with(collision::Shape(ShapeHandle::new(shape)))
-        .with(collision::Object { groups, query_type })
+        .with(object)
 }
 
 /// Only the server can create hooks, so the following is nested in an `auth` module.
@@ -368,9 +368,10 @@ pub mod auth {
     pub fn create(world: &mut World, owner: EntityId, index: u32) -> (EntityId, Entity) {
         assert!(repl::is_entity(world, owner));
 
-        // Create hook
-        let (id, entity) =
-            repl::entity::auth::create(world, owner.0, "hook", |builder| builder.with(State(None)));
+        // Create hook as a child of the owner, so that it is removed together with the owner
+        let (id, entity) = repl::entity::auth::create_child(world, owner, "hook", |builder| {
+            builder.with(State(None))
+        });
 
         // Create hook segments
         let mut segments = [INVALID_ENTITY_ID; NUM_SEGMENTS];
@@ -382,7 +383,7 @@ pub mod auth {
                 "hook_segment"
             };
 
-            let (segment_id, _) = repl::entity::auth::create(world, owner.0, class, |builder| {
+            let (segment_id, _) = repl::entity::auth::create_child(world, id, class, |builder| {
                 builder.with(SegmentDef { hook: id })
             });
             *segment = segment_id;
@@ -415,6 +416,10 @@ struct InteractionData<'a> {
     hook_state: WriteStorage<'a, State>,
 }
 
+/// Attach the first segment of a hook to an entity that it touches. This is called in every step
+/// in which the two are touching, so that the hook also attaches if the player starts shooting
+/// while the segment already touches the entity. Note that `want_fix` and `fixed` do not track the
+/// contact: they hold the player's wish and the attachment, which outlives the contact.
 fn first_segment_interaction(
     world: &World,
     segment_info: &interaction::EntityInfo,
@@ -849,7 +854,8 @@ fn segments_joint_constraint(entity_a: Entity, entity_b: Entity) -> Constraint {
     };
     Constraint {
         def: joint_def,
-        stiffness: 1.0,
+        compliance: 0.0,
+        damping: 0.0,
         entity_a,
         entity_b,
         vars_a: constraint::Vars::pose(),
@@ -859,10 +865,10 @@ fn segments_joint_constraint(entity_a: Entity, entity_b: Entity) -> Constraint {
 
 fn segments_angle_constraint(entity_a: Entity, entity_b: Entity) -> Constraint {
     let angle_def = constraint::Def::Angle { angle: 0.0 };
-    let stiffness = ANGLE_STIFFNESS;
     Constraint {
         def: angle_def,
-        stiffness,
+        compliance: ANGLE_COMPLIANCE,
+        damping: 0.0,
         entity_a,
         entity_b,
         vars_a: constraint::Vars::angle(),
@@ -884,7 +890,8 @@ fn owner_segment_joint_constraint(
     };
     Constraint {
         def: joint_def,
-        stiffness: 1.0,
+        compliance: 0.0,
+        damping: 0.0,
         entity_a: owner_entity,
         entity_b: last_entity,
         vars_a: constraint::Vars {
@@ -899,7 +906,8 @@ fn owner_segment_angle_constraint(owner_entity: Entity, last_entity: Entity) ->
     let angle_def = constraint::Def::Angle { angle: 0.0 };
     Constraint {
         def: angle_def,
-        stiffness: OWNER_ANGLE_STIFFNESS,
+        compliance: 0.0,
+        damping: 0.0,
         entity_a: owner_entity,
         entity_b: last_entity,
         vars_a: constraint::Vars::angle(),
@@ -919,7 +927,8 @@ fn fix_first_segment_constraint(
     };
     Constraint {
         def: joint_def,
-        stiffness: 1.0,
+        compliance: 0.0,
+        damping: 0.0,
         entity_a: first_entity,
         entity_b: fix_entity,
         vars_a: constraint::Vars::pose(),
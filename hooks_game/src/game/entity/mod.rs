@@ -3,6 +3,9 @@ pub mod player;
 pub mod test;
 pub mod wall;
 
+use defs::GameInfo;
+use game::init;
+use plugin;
 use registry::Registry;
 
 // For nicer names in the component enum generated by the `snapshot` macro in `game`.
@@ -12,28 +15,25 @@ pub type HookState = hook::State;
 pub type PlayerState = player::State;
 pub type PlayerInputState = player::InputState;
 
-fn register(reg: &mut Registry) {
-    hook::register(reg);
-    player::register(reg);
-    wall::register(reg);
-}
-
-pub mod auth {
-    use super::*;
+pub struct Plugin;
 
-    pub fn register(reg: &mut Registry) {
-        super::register(reg);
-
-        test::auth::register(reg);
+impl plugin::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "game::entity"
     }
-}
 
-pub mod view {
-    use super::*;
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["repl::entity", "prefab"]
+    }
 
-    pub fn register(reg: &mut Registry) {
-        super::register(reg);
+    fn register(&self, reg: &mut Registry, _: &GameInfo) {
+        hook::register(reg);
+        player::register(reg);
+        wall::register(reg);
+        init::register(reg);
+    }
 
-        test::register(reg);
+    fn register_auth(&self, reg: &mut Registry, _: &GameInfo) {
+        test::auth::register(reg);
     }
 }
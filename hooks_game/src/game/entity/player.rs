@@ -9,9 +9,9 @@ use defs::{EntityId, GameInfo, PlayerId, PlayerInput, INVALID_ENTITY_ID};
 use event::{self, Event};
 use game::entity::hook;
 use game::ComponentType;
-use physics::collision::{self, CollisionGroups, Cuboid, GeometricQueryType, ShapeHandle};
-use physics::interaction;
-use physics::{AngularVelocity, Drag, Dynamic, InvAngularMass, InvMass, Orientation, Position,
+use physics::collision;
+use physics::{interaction, layer};
+use physics::{AngularVelocity, Ccd, Drag, Dynamic, InvAngularMass, InvMass, Orientation, Position,
               Velocity};
 use registry::Registry;
 use repl;
@@ -22,7 +22,7 @@ pub fn register(reg: &mut Registry) {
     reg.component::<Player>();
     reg.component::<State>();
 
-    reg.event::<DashedEvent>();
+    reg.event::<DashedEvent>("DashedEvent");
 
     repl::entity::register_class(
         reg,
@@ -40,6 +40,11 @@ pub fn register(reg: &mut Registry) {
         build_player,
     );
 
+    layer::add(reg, "player");
+    layer::set_class(reg, "player", "player");
+    layer::set_collide(reg, "player", "player");
+    layer::set_collide(reg, "player", "wall");
+
     interaction::set(
         reg,
         "player",
@@ -48,7 +53,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
 
     interaction::set(
@@ -59,10 +64,9 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
 
-    // FIXME: Due to a bug in physics sim, other player also gets moved
     interaction::set(
         reg,
         "player",
@@ -71,7 +75,7 @@ pub fn register(reg: &mut Registry) {
             rotate_a: false,
             rotate_b: false,
         }),
-        None,
+        interaction::Callbacks::none(),
     );
 }
 
@@ -275,19 +279,13 @@ pub mod auth {
     }
 }
 
-fn build_player(builder: EntityBuilder) -> EntityBuilder {
-    let shape = Cuboid::new(Vector2::new(WIDTH / 2.0, HEIGHT / 2.0));
-
-    let mut groups = CollisionGroups::new();
-    groups.set_membership(&[collision::GROUP_PLAYER]);
-    groups.set_whitelist(&[
-        collision::GROUP_PLAYER,
-        collision::GROUP_WALL,
-        collision::GROUP_PLAYER_ENTITY,
-        collision::GROUP_NEUTRAL,
-    ]);
+/// Collision shape of player entities.
+pub fn shape() -> collision::Shape {
+    collision::Shape::cuboid(Vector2::new(WIDTH, HEIGHT))
+}
 
-    let query_type = GeometricQueryType::Contacts(0.0, 0.0);
+fn build_player(builder: EntityBuilder) -> EntityBuilder {
+    let object = layer::object(builder.world, "player");
 
     // TODO: Velocity (and Dynamic?) component should be added only for owners
     builder
@@ -297,9 +295,10 @@ fn build_player(builder: EntityBuilder) -> EntityBuilder {
         .with(InvMass(1.0 / MASS))
         .with(InvAngularMass(1.0 / 10.0))
         .with(Dynamic)
+        .with(Ccd)
         .with(Drag(DRAG))
-        .with(collision::Shape(ShapeHandle::new(shape)))
-        .with(collision::Object { groups, query_type })
+        .with(shape())
+        .with(object)
         .with(InputState::default())
         .with(State::default())
 }
@@ -3,7 +3,8 @@ use specs::prelude::*;
 
 use defs::{EntityId, INVALID_PLAYER_ID};
 use game;
-use physics::collision::{self, CollisionGroups, Cuboid, GeometricQueryType, ShapeHandle};
+use physics::collision::{self, Cuboid, ShapeHandle};
+use physics::layer;
 use physics::{Orientation, Position};
 use registry::Registry;
 use repl;
@@ -11,7 +12,10 @@ use repl;
 pub fn register(reg: &mut Registry) {
     reg.component::<Size>();
 
-    repl::entity::register_class_nosync::<game::ComponentType>(reg, "wall", |builder| builder);
+    repl::entity::register_class_nosync::<game::ComponentType, _>(reg, "wall", |builder| builder);
+
+    layer::add(reg, "wall");
+    layer::set_class(reg, "wall", "wall");
 }
 
 #[derive(Component)]
@@ -28,12 +32,7 @@ pub fn create(
     assert!(size.y > 0.0);
 
     let shape = Cuboid::new(size / 2.0);
-
-    let mut groups = CollisionGroups::new();
-    groups.set_membership(&[collision::GROUP_WALL]);
-    groups.set_whitelist(&[collision::GROUP_PLAYER, collision::GROUP_PLAYER_ENTITY]);
-
-    let query_type = GeometricQueryType::Contacts(0.0, 0.0);
+    let object = layer::object(world, "wall");
 
     let (entity_id, entity) =
         repl::entity::auth::create(world, INVALID_PLAYER_ID, "wall", |builder| {
@@ -42,7 +41,7 @@ pub fn create(
                 .with(Orientation(angle))
                 .with(Size(size))
                 .with(collision::Shape(ShapeHandle::new(shape)))
-                .with(collision::Object { groups, query_type })
+                .with(object)
         });
 
     (entity_id, entity)
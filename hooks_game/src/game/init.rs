@@ -3,32 +3,49 @@ use std::f32;
 use nalgebra::{Point2, Vector2};
 //use rand::{IsaacRng, Rng};
 use specs::prelude::World;
+use specs::storage::NullStorage;
 
 use game::entity::test;
 use game::entity::wall;
-use physics::{AngularVelocity, Position, Velocity};
+use physics::{AngularVelocity, KinematicPath, Position, Velocity};
+use registry::Registry;
 use repl;
 
+pub fn register(reg: &mut Registry) {
+    reg.component::<Map>();
+}
+
+/// Tag for the entities of the static map. These are created by `create_map` on the server as
+/// well as on the clients. They are not included in saves, since loading creates them again.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Map;
+
+fn create_wall(world: &mut World, pos: Point2<f32>, size: Vector2<f32>, angle: f32) {
+    let (_, entity) = wall::create(world, pos, size, angle);
+    world.write::<Map>().insert(entity, Map);
+}
+
 fn create_wall_rect(world: &mut World, center: Point2<f32>, size: Vector2<f32>, d: f32) {
-    wall::create(
+    create_wall(
         world,
         center - Vector2::new(0.0, size.y) / 2.0,
         Vector2::new(size.x + d, d),
         0.0,
     );
-    wall::create(
+    create_wall(
         world,
         center + Vector2::new(0.0, size.y) / 2.0,
         Vector2::new(size.x + d, d),
         0.0,
     );
-    wall::create(
+    create_wall(
         world,
         center - Vector2::new(size.x, 0.0) / 2.0,
         Vector2::new(size.y + d, d),
         f32::consts::PI / 2.0,
     );
-    wall::create(
+    create_wall(
         world,
         center + Vector2::new(size.x, 0.0) / 2.0,
         Vector2::new(size.y + d, d),
@@ -36,7 +53,9 @@ fn create_wall_rect(world: &mut World, center: Point2<f32>, size: Vector2<f32>,
     );
 }
 
-fn create_state(world: &mut World) {
+/// Create the static map. This needs to happen before any other replicated entities are created,
+/// so that the map always gets the same entity ids.
+pub fn create_map(world: &mut World) {
     /*let n_walls = 200;
     let mut rng = IsaacRng::new_unseeded();
 
@@ -51,7 +70,7 @@ fn create_state(world: &mut World) {
 
         let angle = rng.gen::<f32>() * f32::consts::PI;
 
-        wall::create(world, pos, size, angle);
+        create_wall(world, pos, size, angle);
     }*/
 
     create_wall_rect(
@@ -61,25 +80,25 @@ fn create_state(world: &mut World) {
         200.0,
     );
 
-    wall::create(
+    create_wall(
         world,
         Point2::new(0.0, -500.0),
         Vector2::new(1500.0, 20.0),
         0.0,
     );
-    wall::create(
+    create_wall(
         world,
         Point2::new(-2000.0, 0.0),
         Vector2::new(1000.0, 20.0),
         f32::consts::PI / 2.0,
     );
-    wall::create(
+    create_wall(
         world,
         Point2::new(2000.0, 0.0),
         Vector2::new(3000.0, 20.0),
         0.0,
     );
-    wall::create(
+    create_wall(
         world,
         Point2::new(4500.0, 0.0),
         Vector2::new(3000.0, 20.0),
@@ -94,7 +113,7 @@ pub mod auth {
     use super::*;
 
     pub fn create_state(world: &mut World) {
-        super::create_state(world);
+        super::create_map(world);
 
         // Just some stupid entities for initial testing
 
@@ -126,6 +145,19 @@ pub mod auth {
                 .with(AngularVelocity(0.0))
                 .with(test::auth::Test(2.5, 5.0))
         });
+        repl::entity::auth::create(world, 0, "test", |builder| {
+            let waypoints = vec![
+                Point2::new(600.0, -600.0),
+                Point2::new(1200.0, -600.0),
+                Point2::new(1200.0, 0.0),
+            ];
+
+            builder
+                .with(Position(waypoints[0]))
+                .with(Velocity(Vector2::new(0.0, 0.0)))
+                .with(AngularVelocity(0.0))
+                .with(KinematicPath::new(waypoints, 150.0))
+        });
         /*repl::entity::auth::create(world, 0, "test", |builder| {
             builder
                 .with(Position(Point2::new(100.0, 0.0)))
@@ -140,6 +172,6 @@ pub mod view {
     use super::*;
 
     pub fn create_state(world: &mut World) {
-        super::create_state(world);
+        super::create_map(world);
     }
 }
@@ -0,0 +1,187 @@
+use specs::prelude::*;
+use specs::storage::BTreeStorage;
+
+use defs::GameInfo;
+use entity;
+use plugin;
+use registry::Registry;
+use repl;
+use save;
+
+/// Plugin for removing entities once their `Lifetime` has expired.
+pub struct Plugin;
+
+impl plugin::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "lifetime"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["core"]
+    }
+
+    fn register(&self, reg: &mut Registry, _: &GameInfo) {
+        reg.component::<Lifetime>();
+    }
+
+    fn register_auth(&self, reg: &mut Registry, _: &GameInfo) {
+        // Not every entity class replicates the lifetime
+        reg.saved_component::<Lifetime>();
+        reg.tick_system(LifetimeSys { auth: true }, "lifetime", &[]);
+    }
+
+    fn register_view(&self, reg: &mut Registry, _: &GameInfo) {
+        reg.tick_system(LifetimeSys { auth: false }, "lifetime", &[]);
+    }
+}
+
+/// Entities with a `Lifetime` are removed, together with their descendants, after the given
+/// number of ticks.
+///
+/// Entity classes can choose to replicate this component. Clients then know how long the entity
+/// has left to live, e.g. for fading it out. Clients only remove entities on their own if they
+/// are not replicated; replicated entities are removed by the server.
+#[derive(Component, PartialEq, Clone, Copy, Debug, BitStore)]
+#[storage(BTreeStorage)]
+pub struct Lifetime {
+    /// Number of ticks until the entity is removed.
+    pub remaining_ticks: u32,
+
+    /// Number of ticks that the entity lives in total.
+    pub total_ticks: u32,
+}
+
+impl repl::Component for Lifetime {}
+
+impl save::Component for Lifetime {}
+
+impl Lifetime {
+    pub fn ticks(ticks: u32) -> Lifetime {
+        Lifetime {
+            remaining_ticks: ticks,
+            total_ticks: ticks,
+        }
+    }
+
+    /// Lifetime of at least the given number of seconds, rounded up to whole ticks.
+    pub fn secs(game_info: &GameInfo, secs: f32) -> Lifetime {
+        assert!(secs >= 0.0);
+
+        Lifetime::ticks((secs * game_info.ticks_per_second as f32).ceil() as u32)
+    }
+
+    /// Fraction of the lifetime that is left, going from one down to zero.
+    pub fn fraction_left(&self) -> f32 {
+        if self.total_ticks == 0 {
+            0.0
+        } else {
+            self.remaining_ticks as f32 / self.total_ticks as f32
+        }
+    }
+
+    pub fn remaining_secs(&self, game_info: &GameInfo) -> f32 {
+        self.remaining_ticks as f32 * game_info.tick_duration_secs()
+    }
+}
+
+struct LifetimeSys {
+    /// On clients, we leave the removal of replicated entities to the server.
+    auth: bool,
+}
+
+#[derive(SystemData)]
+struct LifetimeData<'a> {
+    entities: Entities<'a>,
+    repl_id: ReadStorage<'a, repl::Id>,
+    parent: ReadStorage<'a, entity::Parent>,
+
+    lifetime: WriteStorage<'a, Lifetime>,
+    remove: WriteStorage<'a, entity::Remove>,
+}
+
+impl<'a> System<'a> for LifetimeSys {
+    type SystemData = LifetimeData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let mut expired = Vec::new();
+
+        for (entity, lifetime) in (&*data.entities, &mut data.lifetime).join() {
+            if !self.auth && data.repl_id.get(entity).is_some() {
+                continue;
+            }
+
+            if lifetime.remaining_ticks > 0 {
+                lifetime.remaining_ticks -= 1;
+            }
+
+            if lifetime.remaining_ticks == 0 {
+                expired.push(entity);
+            }
+        }
+
+        for entity in expired {
+            entity::deferred_remove_with(&data.entities, &data.parent, &mut data.remove, entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point2;
+
+    use game::run::AuthRunner;
+    use game::testing;
+    use physics::Position;
+    use repl;
+
+    use super::Lifetime;
+
+    #[test]
+    fn test_remove_with_children() {
+        let game_info = testing::game_info();
+        let mut state = testing::auth_state(&game_info);
+
+        let (parent, _) = repl::entity::auth::create(&mut state.world, 0, "test", |builder| {
+            builder
+                .with(Position(Point2::origin()))
+                .with(Lifetime::ticks(3))
+        });
+        let (child, _) =
+            repl::entity::auth::create_child(&mut state.world, parent, "test", |builder| {
+                builder.with(Position(Point2::origin()))
+            });
+        let (other, _) = repl::entity::auth::create(&mut state.world, 0, "test", |builder| {
+            builder
+                .with(Position(Point2::origin()))
+                .with(Lifetime::ticks(4))
+        });
+
+        let mut runner = AuthRunner::new(&mut state.world);
+
+        for _ in 0..2 {
+            runner.run_tick(&mut state, Vec::new()).unwrap();
+
+            assert!(repl::is_entity(&state.world, parent));
+            assert!(repl::is_entity(&state.world, child));
+        }
+
+        runner.run_tick(&mut state, Vec::new()).unwrap();
+
+        assert!(!repl::is_entity(&state.world, parent));
+        assert!(!repl::is_entity(&state.world, child));
+        assert!(repl::is_entity(&state.world, other));
+
+        let entity = repl::get_id_to_entity(&state.world, other).unwrap();
+        assert_eq!(
+            state.world.read::<Lifetime>().get(entity),
+            Some(&Lifetime {
+                remaining_ticks: 1,
+                total_ticks: 4,
+            })
+        );
+
+        runner.run_tick(&mut state, Vec::new()).unwrap();
+
+        assert!(!repl::is_entity(&state.world, other));
+    }
+}
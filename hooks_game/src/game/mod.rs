@@ -2,38 +2,37 @@ pub mod catch;
 pub mod entity;
 pub mod init;
 pub mod input;
+pub mod lifetime;
 pub mod predict;
+pub mod prefab;
 pub mod run;
+pub mod save;
+pub mod script;
 pub mod state;
 
-use registry::Registry;
+#[cfg(test)]
+pub mod testing;
+
+use std::path::Path;
+
+use plugin::Plugins;
 use repl;
 
 pub use self::snapshot::{ComponentType, EntityClasses, EntitySnapshot, LoadSnapshotSys,
-                         StoreSnapshotSys, WorldSnapshot};
+                         StoreFullSnapshotSys, StoreSnapshotSys, WorldSnapshot};
 pub use self::state::State;
 
-fn register(_: &mut Registry) {}
-
-pub mod auth {
-    use super::*;
-
-    pub fn register(reg: &mut Registry) {
-        super::register(reg);
-        repl::entity::auth::register::<EntitySnapshot>(reg);
-        entity::auth::register(reg);
-        catch::auth::register(reg);
-    }
-}
-
-pub mod view {
-    use super::*;
-
-    pub fn register(reg: &mut Registry) {
-        super::register(reg);
-        repl::entity::view::register::<EntitySnapshot>(reg);
-        entity::view::register(reg);
-    }
+/// The plugins of the game itself, without the core modules. Data files are loaded from
+/// `data_dir`.
+pub fn plugins(data_dir: &Path) -> Plugins {
+    let mut plugins = Plugins::new();
+    plugins.add(repl::entity::Plugin::<EntitySnapshot>::new());
+    plugins.add(lifetime::Plugin);
+    plugins.add(prefab::Plugin::new(data_dir));
+    plugins.add(entity::Plugin);
+    plugins.add(catch::Plugin);
+    plugins.add(script::Plugin::new(data_dir));
+    plugins
 }
 
 snapshot! {
@@ -50,6 +49,8 @@ snapshot! {
     use game::entity::PlayerState;
     use game::entity::PlayerInputState;
 
+    use game::lifetime::Lifetime;
+
     mod snapshot {
         position: Position,
         orientation: Orientation,
@@ -64,5 +65,7 @@ snapshot! {
         player: Player,
         player_state: PlayerState,
         player_input_state: PlayerInputState,
+
+        lifetime: Lifetime,
     }
 }
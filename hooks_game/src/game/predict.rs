@@ -24,6 +24,10 @@ struct LogEntry {
     /// We will use this to calculate an error for the prediction when the correct snapshot arrives
     /// from the server (not implemented yet).
     snapshot: game::WorldSnapshot,
+
+    /// Pairs of entities that were touching after executing the input. Replaying the following
+    /// inputs starts from these, so that contact and sensor events are not repeated.
+    pairs: physics::sim::Pairs,
 }
 
 pub struct Log {
@@ -116,7 +120,13 @@ impl Log {
         }
     }
 
-    fn record(&mut self, world: &World, tick: TickNum, input: &PlayerInput) {
+    fn record(
+        &mut self,
+        world: &World,
+        physics_runner: &physics::sim::Runner,
+        tick: TickNum,
+        input: &PlayerInput,
+    ) {
         // Snapshot the predicted state of our entities
         let mut sys = game::StoreSnapshotSys {
             snapshot: game::WorldSnapshot::new(),
@@ -129,6 +139,7 @@ impl Log {
             LogEntry {
                 input: input.clone(),
                 snapshot: sys.snapshot,
+                pairs: physics_runner.store_pairs(world),
             },
         );
     }
@@ -155,16 +166,14 @@ impl Log {
             // If the tick data contains a snapshot, we can correct our prediction
             if let Some(auth_snapshot) = tick_data.snapshot.as_ref() {
                 // Calculate prediction error
-                let our_snapshot = &self.entries
-                    .get(&last_input_tick)
-                    .ok_or_else(|| {
-                        repl::Error::Replication(format!(
-                            "Received prediction correction for input num {}\
-                             but we have no log entry for that",
-                            last_input_tick,
-                        ))
-                    })?
-                    .snapshot;
+                let log_entry = self.entries.get(&last_input_tick).ok_or_else(|| {
+                    repl::Error::Replication(format!(
+                        "Received prediction correction for input num {}\
+                         but we have no log entry for that",
+                        last_input_tick,
+                    ))
+                })?;
+                let our_snapshot = &log_entry.snapshot;
 
                 let distance = our_snapshot.distance(&auth_snapshot)?;
 
@@ -176,6 +185,7 @@ impl Log {
                     // Reset to auth state of player entities
                     //debug!("resetting");
                     self.reset(world, auth_snapshot);
+                    physics_runner.load_pairs(world, &log_entry.pairs);
                     //self.smooth(world, our_snapshot, auth_snapshot);
 
                     // Now apply our recorded inputs again
@@ -187,6 +197,8 @@ impl Log {
 
                         //debug!("replaying {}", log_input_tick);
 
+                        // Kinematic bodies are extrapolated in the same way as in `run`
+                        physics_runner.run_kinematic(world);
                         input::auth::run_player_input(
                             world,
                             physics_runner,
@@ -223,6 +235,12 @@ impl Log {
         // TODO: This will need to be refined. Might want to predict only some events.
         let ignore = world.write_resource::<event::Sink>().set_ignore(true);
 
+        // The server moves `Kinematic` bodies once before running the inputs of a tick. Every
+        // input that we run corresponds to one server tick after the snapshot, so we extrapolate
+        // the bodies from the snapshot with their replicated velocity in the same way. Since
+        // `KinematicPath` is not replicated, this diverges only when the server changes the
+        // velocity.
+        physics_runner.run_kinematic(world);
         input::auth::run_player_input(
             world,
             physics_runner,
@@ -232,7 +250,7 @@ impl Log {
 
         world.write_resource::<event::Sink>().set_ignore(ignore);
 
-        self.record(world, tick, input);
+        self.record(world, physics_runner, tick, input);
 
         Ok(())
     }
@@ -0,0 +1,561 @@
+//! Entity classes that are defined in data files instead of code. A prefab file describes the
+//! collision shape, physical parameters, replicated components and interactions of one entity
+//! class. This makes it possible to add simple obstacles and props without touching the code.
+//!
+//! Prefabs are written in RON. Example:
+//!
+//! ```text
+//! Prefab(
+//!     name: "crate",
+//!     components: ["Position", "Orientation"],
+//!     shape: Some(Cuboid(size: (50.0, 50.0))),
+//!     physics: Some(Physics(mass: 10.0, angular_mass: 10.0, friction: 5.0, drag: 0.0)),
+//!     collision: Some(Collision(layer: "neutral", collides_with: ["player"])),
+//!     interactions: [
+//!         Interaction(
+//!             other: "player",
+//!             prevent_overlap: Some(PreventOverlap(rotate_self: true, rotate_other: false)),
+//!         ),
+//!     ],
+//! )
+//! ```
+//!
+//! Other shapes are given as `Ball(radius: 20.0)`, `Capsule(height: 80.0, radius: 20.0)` or
+//! `ConvexPolygon(points: [(0.0, 0.0), (100.0, 0.0), (0.0, 50.0)])`. Surface properties, e.g. for
+//! bouncy pads or icy floors, are given as `material: Some(Material(restitution: 0.8))`.
+//!
+//! Moving platforms are given as `kinematic: true` instead of `physics`. They need to replicate
+//! `Velocity` and `AngularVelocity`, so that predicting clients can move them like the server.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use nalgebra::{zero, Point2, Vector2};
+use ron;
+use specs::prelude::{EntityBuilder, World};
+
+use defs::GameInfo;
+use game::ComponentType;
+use physics::collision;
+use physics::{interaction, layer};
+use physics::{AngularVelocity, Ccd, Drag, Dynamic, Friction, InvAngularMass, InvMass, Kinematic,
+              Material, Orientation, Velocity};
+use plugin;
+use registry::Registry;
+use repl;
+use repl::snapshot::ComponentType as ComponentTypeTrait;
+
+/// Directory, relative to the data directory, from which server and clients load prefabs at
+/// startup. Both sides need to have the same prefabs.
+pub const DIR: &str = "prefabs";
+
+/// Extension of prefab files.
+pub const EXTENSION: &str = "ron";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, ron::de::Error),
+    UnknownComponent(String, String),
+    InvalidShape(String),
+    InvalidMass(String),
+    InvalidKinematic(String),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "Shape")]
+pub enum ShapeDef {
+    /// Box with the given total width and height.
+    Cuboid { size: (f32, f32) },
+
+    /// Circle.
+    Ball { radius: f32 },
+
+    /// Vertical capsule with the given total height, which includes the round caps.
+    Capsule { height: f32, radius: f32 },
+
+    /// Convex polygon with the given vertices. The polygon is moved so that its centroid is at the
+    /// entity's position.
+    ConvexPolygon { points: Vec<(f32, f32)> },
+}
+
+impl ShapeDef {
+    /// Create the collision shape, or `None` if the definition is invalid.
+    pub fn to_shape(&self) -> Option<collision::Shape> {
+        match *self {
+            ShapeDef::Cuboid { size } => if size.0 > 0.0 && size.1 > 0.0 {
+                Some(collision::Shape::cuboid(Vector2::new(size.0, size.1)))
+            } else {
+                None
+            },
+            ShapeDef::Ball { radius } => if radius > 0.0 {
+                Some(collision::Shape::ball(radius))
+            } else {
+                None
+            },
+            ShapeDef::Capsule { height, radius } => if radius > 0.0 && height >= 2.0 * radius {
+                Some(collision::Shape::capsule(height / 2.0 - radius, radius))
+            } else {
+                None
+            },
+            ShapeDef::ConvexPolygon { ref points } => {
+                let points = points
+                    .iter()
+                    .map(|&(x, y)| Point2::new(x, y))
+                    .collect::<Vec<_>>();
+                collision::Shape::convex_polygon(&points)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "Physics")]
+pub struct PhysicsDef {
+    pub mass: f32,
+    pub angular_mass: f32,
+
+    #[serde(default)]
+    pub friction: f32,
+
+    #[serde(default)]
+    pub drag: f32,
+
+    /// Enable continuous collision detection for fast-moving entities.
+    #[serde(default)]
+    pub ccd: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "Material")]
+pub struct MaterialDef {
+    #[serde(default)]
+    pub restitution: f32,
+
+    #[serde(default)]
+    pub static_friction: f32,
+
+    #[serde(default)]
+    pub dynamic_friction: f32,
+
+    #[serde(default)]
+    pub angular_drag: f32,
+}
+
+impl MaterialDef {
+    pub fn to_material(&self) -> Material {
+        Material {
+            restitution: self.restitution,
+            static_friction: self.static_friction,
+            dynamic_friction: self.dynamic_friction,
+            angular_drag: self.angular_drag,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "Collision")]
+pub struct CollisionDef {
+    /// Collision layer of the entity. The layer is declared if it does not exist yet.
+    pub layer: String,
+
+    /// Other layers that this layer collides with.
+    #[serde(default)]
+    pub collides_with: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "PreventOverlap")]
+pub struct PreventOverlapDef {
+    pub rotate_self: bool,
+    pub rotate_other: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename = "Interaction")]
+pub struct InteractionDef {
+    /// Name of the entity class that we interact with.
+    pub other: String,
+
+    #[serde(default)]
+    pub prevent_overlap: Option<PreventOverlapDef>,
+}
+
+/// Definition of an entity class as given in a prefab file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Prefab {
+    pub name: String,
+
+    /// Names of the replicated components, as given in the `snapshot!` of `game`. Prefabs
+    /// without any replicated components are not included in snapshots.
+    #[serde(default)]
+    pub components: Vec<String>,
+
+    #[serde(default)]
+    pub shape: Option<ShapeDef>,
+
+    /// If given, the entity is simulated by the physics.
+    #[serde(default)]
+    pub physics: Option<PhysicsDef>,
+
+    /// If set, the entity is `Kinematic`: it is moved by its velocity once per tick, on the server
+    /// as well as on predicting clients. Can not be combined with `physics`.
+    #[serde(default)]
+    pub kinematic: bool,
+
+    /// Surface properties for contacts with other entities.
+    #[serde(default)]
+    pub material: Option<MaterialDef>,
+
+    /// Collision layer. Needs to be given together with a shape.
+    #[serde(default)]
+    pub collision: Option<CollisionDef>,
+
+    #[serde(default)]
+    pub interactions: Vec<InteractionDef>,
+}
+
+/// Names of the entity classes that have been loaded from prefabs.
+#[derive(Default)]
+struct Names(BTreeSet<String>);
+
+/// Is the entity class defined by a prefab? Entities of these classes can be created with just a
+/// `Position`, since everything else is given by the prefab.
+pub fn is_prefab(world: &World, class: &str) -> bool {
+    world.read_resource::<Names>().0.contains(class)
+}
+
+/// Prefab where all names have been resolved, ready for building entities.
+#[derive(Clone)]
+struct Class {
+    shape: Option<collision::Shape>,
+    physics: Option<PhysicsDef>,
+    kinematic: bool,
+    material: Option<Material>,
+    layer: Option<String>,
+}
+
+/// Plugin that loads the prefabs in `DIR` on server and clients.
+pub struct Plugin {
+    dir: PathBuf,
+}
+
+impl Plugin {
+    pub fn new(data_dir: &Path) -> Plugin {
+        Plugin {
+            dir: data_dir.join(DIR),
+        }
+    }
+}
+
+impl plugin::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "prefab"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["repl::entity"]
+    }
+
+    fn register(&self, reg: &mut Registry, _: &GameInfo) {
+        register(reg, &self.dir);
+    }
+}
+
+/// Load all the prefabs in a directory. Panics if any of them is invalid, since server and
+/// clients would otherwise disagree on the entity classes.
+pub fn register(reg: &mut Registry, dir: &Path) {
+    reg.resource(Names::default());
+
+    if let Err(error) = load_dir(reg, dir) {
+        panic!("Failed to load prefabs from {}: {:?}", dir.display(), error);
+    }
+}
+
+/// Register an entity class for every prefab file in a directory. The files are loaded in order of
+/// their names, so that server and clients see the same order.
+pub fn load_dir(reg: &mut Registry, dir: &Path) -> Result<(), Error> {
+    let mut paths = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(|e| Error::Io(dir.to_path_buf(), e))? {
+        let path = entry.map_err(|e| Error::Io(dir.to_path_buf(), e))?.path();
+
+        if path.extension().map(|ext| ext == EXTENSION).unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+
+    for path in &paths {
+        load_file(reg, path)?;
+    }
+
+    Ok(())
+}
+
+/// Register an entity class from one prefab file.
+pub fn load_file(reg: &mut Registry, path: &Path) -> Result<(), Error> {
+    let prefab = read_file(path)?;
+
+    info!("Loaded prefab {} from {}", prefab.name, path.display());
+
+    load(reg, &prefab)
+}
+
+/// Parse a prefab file without registering anything.
+pub fn read_file(path: &Path) -> Result<Prefab, Error> {
+    let mut data = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut data))
+        .map_err(|e| Error::Io(path.to_path_buf(), e))?;
+
+    ron::de::from_str(&data).map_err(|e| Error::Parse(path.to_path_buf(), e))
+}
+
+impl Prefab {
+    /// Check the parts of the definition that can be checked without a `Registry`. Collision
+    /// layers and interactions refer to other names and are checked once registration is
+    /// finished.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.component_types()?;
+
+        if self.collision.is_some() && self.shape.is_none() {
+            return Err(Error::InvalidShape(self.name.clone()));
+        }
+
+        self.collision_shape()?;
+
+        if let Some(ref physics) = self.physics {
+            // Written this way to reject NaN as well
+            if !(physics.mass > 0.0 && physics.angular_mass > 0.0)
+                || !physics.mass.is_finite()
+                || !physics.angular_mass.is_finite()
+            {
+                return Err(Error::InvalidMass(self.name.clone()));
+            }
+        }
+
+        if self.kinematic {
+            let replicated = |name: &str| self.components.iter().any(|c| c == name);
+
+            // Clients need the velocity to move the entity in prediction
+            if self.physics.is_some() || !replicated("Velocity") || !replicated("AngularVelocity")
+            {
+                return Err(Error::InvalidKinematic(self.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn component_types(&self) -> Result<Vec<ComponentType>, Error> {
+        self.components
+            .iter()
+            .map(|name| {
+                ComponentType::from_name(name)
+                    .ok_or_else(|| Error::UnknownComponent(self.name.clone(), name.clone()))
+            })
+            .collect()
+    }
+
+    fn collision_shape(&self) -> Result<Option<collision::Shape>, Error> {
+        match self.shape {
+            Some(ref shape) => shape
+                .to_shape()
+                .map(Some)
+                .ok_or_else(|| Error::InvalidShape(self.name.clone())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Register an entity class from a prefab definition.
+pub fn load(reg: &mut Registry, prefab: &Prefab) -> Result<(), Error> {
+    prefab.validate()?;
+
+    let components = prefab.component_types()?;
+    let shape = prefab.collision_shape()?;
+
+    // Unknown layer names are reported once registration is finished
+    if let Some(ref collision) = prefab.collision {
+        layer::add(reg, &collision.layer);
+        layer::set_class(reg, &prefab.name, &collision.layer);
+
+        for other in &collision.collides_with {
+            layer::set_collide(reg, &collision.layer, other);
+        }
+    }
+
+    reg.world()
+        .write_resource::<Names>()
+        .0
+        .insert(prefab.name.clone());
+
+    let class = Class {
+        shape,
+        physics: prefab.physics.clone(),
+        kinematic: prefab.kinematic,
+        material: prefab.material.as_ref().map(MaterialDef::to_material),
+        layer: prefab.collision.as_ref().map(|collision| collision.layer.clone()),
+    };
+
+    if components.is_empty() {
+        repl::entity::register_class_nosync::<ComponentType, _>(reg, &prefab.name, move |builder| {
+            build(&class, builder)
+        });
+    } else {
+        repl::entity::register_class(reg, &prefab.name, &components, move |builder| {
+            build(&class, builder)
+        });
+    }
+
+    for interaction in &prefab.interactions {
+        let action = interaction
+            .prevent_overlap
+            .as_ref()
+            .map(|def| interaction::Action::PreventOverlap {
+                rotate_a: def.rotate_self,
+                rotate_b: def.rotate_other,
+            });
+
+        interaction::set(
+            reg,
+            &prefab.name,
+            &interaction.other,
+            action,
+            interaction::Callbacks::none(),
+        );
+    }
+
+    Ok(())
+}
+
+fn build<'a>(class: &Class, builder: EntityBuilder<'a>) -> EntityBuilder<'a> {
+    let mut builder = builder.with(Orientation(0.0));
+
+    if let Some(ref physics) = class.physics {
+        builder = builder
+            .with(Velocity(zero()))
+            .with(AngularVelocity(0.0))
+            .with(InvMass(1.0 / physics.mass))
+            .with(InvAngularMass(1.0 / physics.angular_mass))
+            .with(Dynamic);
+
+        if physics.friction > 0.0 {
+            builder = builder.with(Friction(physics.friction));
+        }
+        if physics.drag > 0.0 {
+            builder = builder.with(Drag(physics.drag));
+        }
+        if physics.ccd {
+            builder = builder.with(Ccd);
+        }
+    }
+
+    if class.kinematic {
+        builder = builder
+            .with(Velocity(zero()))
+            .with(AngularVelocity(0.0))
+            .with(Kinematic);
+    }
+
+    if let Some(material) = class.material {
+        builder = builder.with(material);
+    }
+
+    if let Some(ref shape) = class.shape {
+        builder = builder.with(shape.clone());
+
+        if let Some(ref layer_name) = class.layer {
+            let object = layer::object(builder.world, layer_name);
+
+            builder = builder.with(object);
+        }
+    }
+
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ron;
+
+    use super::{read_file, Error, Prefab, ShapeDef, DIR};
+
+    fn parse(data: &str) -> Prefab {
+        ron::de::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let prefab = parse(
+            r#"Prefab(
+                name: "crate",
+                components: ["Position", "Orientation"],
+                shape: Some(Capsule(height: 80.0, radius: 20.0)),
+                physics: Some(Physics(mass: 10.0, angular_mass: 5.0)),
+                collision: Some(Collision(layer: "neutral")),
+            )"#,
+        );
+
+        assert_eq!(prefab.name, "crate");
+        assert_eq!(prefab.components, vec!["Position", "Orientation"]);
+        match prefab.shape {
+            Some(ShapeDef::Capsule { height, radius }) => {
+                assert_eq!((height, radius), (80.0, 20.0));
+            }
+            ref shape => panic!("unexpected shape {:?}", shape),
+        }
+        assert_eq!(prefab.physics.as_ref().unwrap().friction, 0.0);
+        assert!(prefab.collision.as_ref().unwrap().collides_with.is_empty());
+        assert!(prefab.interactions.is_empty());
+        assert!(prefab.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = [
+            r#"Prefab(name: "a", components: ["Nope"])"#,
+            r#"Prefab(name: "a", collision: Some(Collision(layer: "neutral")))"#,
+            r#"Prefab(name: "a", shape: Some(Ball(radius: 0.0)))"#,
+            r#"Prefab(name: "a", physics: Some(Physics(mass: 0.0, angular_mass: 1.0)))"#,
+            r#"Prefab(name: "a", physics: Some(Physics(mass: 1.0, angular_mass: -1.0)))"#,
+            r#"Prefab(name: "a", components: ["Position", "Velocity"], kinematic: true)"#,
+        ];
+
+        for data in &invalid {
+            assert!(parse(data).validate().is_err(), "{} is valid", data);
+        }
+
+        match parse(invalid[3]).validate() {
+            Err(Error::InvalidMass(ref name)) => assert_eq!(name, "a"),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let kinematic = parse(
+            r#"Prefab(
+                name: "a",
+                components: ["Position", "Velocity", "AngularVelocity"],
+                kinematic: true,
+            )"#,
+        );
+        assert!(kinematic.validate().is_ok());
+    }
+
+    #[test]
+    fn test_data_prefabs() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../data").join(DIR);
+
+        for entry in dir.read_dir().unwrap() {
+            let path = entry.unwrap().path();
+            let prefab = read_file(&path).unwrap();
+
+            assert!(prefab.validate().is_ok(), "{} is invalid", path.display());
+        }
+    }
+}
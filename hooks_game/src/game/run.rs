@@ -8,7 +8,7 @@ use event::{self, Event};
 use game::state::State;
 use game::{self, input, predict};
 use physics;
-use repl::{self, tick};
+use repl::{self, resource, tick};
 
 struct CommonRunner {
     physics_runner: physics::sim::Runner,
@@ -24,13 +24,16 @@ impl CommonRunner {
     /// Execute the deferred removal of entities tagged with `Remove`. Right now, we try to call
     /// this function after every step of the tick, with the hope of avoiding any interaction with
     /// removed entities in subsequent steps.
-    fn perform_removals(&mut self, state: &mut State) {
+    fn perform_removals(&mut self, state: &mut State) -> Result<(), repl::Error> {
         // Here, systems have a chance to react to entities that will be removed, tagged with the
         // `Remove` component ...
-        state.removal_dispatcher.dispatch_seq(&state.world.res);
+        self.physics_runner.run_removals(&state.world)?;
+        state.dispatch_removals();
 
         // ... and now we go through with it.
         entity::perform_removals(&mut state.world);
+
+        Ok(())
     }
 
     fn run_pre_tick(&mut self, state: &mut State) -> Result<(), repl::Error> {
@@ -46,13 +49,13 @@ impl CommonRunner {
             }
         }
 
-        self.perform_removals(state);
+        self.perform_removals(state)?;
 
         for f in &state.pre_tick_fns {
             f(&mut state.world)?;
         }
 
-        self.perform_removals(state);
+        self.perform_removals(state)?;
 
         Ok(())
     }
@@ -65,7 +68,7 @@ impl CommonRunner {
             }
         }
 
-        self.perform_removals(state);
+        self.perform_removals(state)?;
 
         stats::record(
             "#players",
@@ -76,9 +79,9 @@ impl CommonRunner {
     }
 
     fn run_tick(&mut self, state: &mut State) -> Result<(), repl::Error> {
-        state.tick_dispatcher.dispatch_seq(&state.world.res);
+        state.dispatch_tick();
 
-        self.perform_removals(state);
+        self.perform_removals(state)?;
 
         Ok(())
     }
@@ -86,38 +89,118 @@ impl CommonRunner {
 
 pub struct AuthRunner {
     common: CommonRunner,
+    check: Option<Box<Check>>,
+}
+
+/// A second game state that runs the same ticks with sequential dispatch, so that we can assert
+/// that parallel dispatch does not change the outcome.
+struct Check {
+    common: CommonRunner,
+    state: State,
 }
 
 impl AuthRunner {
     pub fn new(world: &mut World) -> AuthRunner {
         AuthRunner {
             common: CommonRunner::new(world),
+            check: None,
         }
     }
 
+    /// Run every tick a second time in `check_state` with sequential dispatch, and panic if the
+    /// results differ. `check_state` needs to be initialized in exactly the same way as the state
+    /// that is given to `run_tick`. This is expensive and only meant for debugging.
+    pub fn check_determinism(&mut self, mut check_state: State) {
+        check_state.parallel = false;
+
+        self.check = Some(Box::new(Check {
+            common: CommonRunner::new(&mut check_state.world),
+            state: check_state,
+        }));
+    }
+
     /// Running a tick on the server side.
     pub fn run_tick(
         &mut self,
         state: &mut State,
         input_batches: Vec<Vec<(PlayerId, PlayerInput)>>,
     ) -> Result<Vec<Box<Event>>, repl::Error> {
-        self.common.run_pre_tick(state)?;
+        if let Some(check) = self.check.as_mut() {
+            // The check state receives the same events that have been pushed for this tick
+            let events = state.world.read_resource::<event::Sink>().clone().into_vec();
+            check.state.push_events(events);
+
+            let check_events =
+                Self::run_tick_common(&mut check.common, &mut check.state, input_batches.clone())?;
+            let events = Self::run_tick_common(&mut self.common, state, input_batches)?;
+
+            check.assert_same(state, &events, &check_events);
+
+            Ok(events)
+        } else {
+            Self::run_tick_common(&mut self.common, state, input_batches)
+        }
+    }
+
+    fn run_tick_common(
+        common: &mut CommonRunner,
+        state: &mut State,
+        input_batches: Vec<Vec<(PlayerId, PlayerInput)>>,
+    ) -> Result<Vec<Box<Event>>, repl::Error> {
+        common.run_pre_tick(state)?;
+
+        // Moving platforms and the like move once per tick, independently of player inputs
+        common.physics_runner.run_kinematic(&state.world);
 
         //debug!("num inputs: {}", inputs.len());
 
         for inputs in input_batches {
-            input::auth::run_player_input(
-                &mut state.world,
-                &mut self.common.physics_runner,
-                &inputs,
-            )?;
+            input::auth::run_player_input(&mut state.world, &mut common.physics_runner, &inputs)?;
         }
 
-        self.common.run_tick(state)?;
-        self.common.run_post_tick(state)
+        // Bodies fall asleep based on their state at the end of the tick
+        common.physics_runner.run_sleep(&state.world);
+
+        common.run_tick(state)?;
+        common.run_post_tick(state)
+    }
+}
+
+impl Check {
+    fn assert_same(&self, state: &State, events: &[Box<Event>], check_events: &[Box<Event>]) {
+        let snapshot = store_snapshot(state);
+        let check_snapshot = store_snapshot(&self.state);
+
+        if snapshot != check_snapshot {
+            panic!(
+                "Parallel dispatch is not deterministic, snapshot differs: {:?} vs {:?}",
+                snapshot, check_snapshot,
+            );
+        }
+
+        // Events do not implement `PartialEq`, so we resort to comparing their debug output
+        let events = format!("{:?}", events);
+        let check_events = format!("{:?}", check_events);
+
+        if events != check_events {
+            panic!(
+                "Parallel dispatch is not deterministic, events differ: {} vs {}",
+                events, check_events,
+            );
+        }
     }
 }
 
+fn store_snapshot(state: &State) -> (game::WorldSnapshot, resource::Snapshot) {
+    let mut sys = game::StoreSnapshotSys {
+        snapshot: game::WorldSnapshot::new(),
+        only_player: None,
+    };
+    sys.run_now(&state.world.res);
+
+    (sys.snapshot, resource::Snapshot::store(&state.world))
+}
+
 pub struct ViewRunner {
     common: CommonRunner,
     my_player_id: PlayerId,
@@ -172,6 +255,11 @@ impl ViewRunner {
             sys.run_now(&state.world.res);
         }
 
+        if let Some(ref resources) = tick_data.resources {
+            // Replicated resources are never predicted, so we can always load them
+            resources.load(&state.world);
+        }
+
         // Prediction
         // TODO: Should this happen before or after loading snapshots?
         // TODO: Consider input frequency > tick frequency?
@@ -195,3 +283,228 @@ impl ViewRunner {
         self.predict_log.is_some()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bit_manager::BitWriter;
+    use nalgebra::{norm, Point2};
+
+    use defs::{GameInfo, PlayerId, PlayerInfo, PlayerInput, TickNum};
+    use event;
+    use game::state::State;
+    use game::{init, testing, EntityClasses, WorldSnapshot};
+    use physics::{KinematicPath, Position};
+    use repl::player::JoinedEvent;
+    use repl::{self, resource, tick};
+
+    use super::{store_snapshot, AuthRunner, ViewRunner};
+
+    const PLAYERS: &[PlayerId] = &[1, 2];
+    const NUM_TICKS: TickNum = 300;
+
+    /// Create a game state with some players. If `shuffle` is set, the players' entities are given
+    /// lower entity indices than the entities of the map, so that iterating over storages gives a
+    /// different order than in an unshuffled state.
+    fn create_state(game_info: &GameInfo, shuffle: bool) -> State {
+        let mut state = testing::auth_state(game_info);
+
+        let padding = if shuffle {
+            (0..16)
+                .map(|_| state.world.create_entity().build())
+                .collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+
+        init::auth::create_state(&mut state.world);
+
+        // Free the padding's entity indices, so that they are reused for the players' entities
+        for entity in padding {
+            state.world.delete_entity(entity).unwrap();
+        }
+        state.world.maintain();
+
+        for &id in PLAYERS {
+            state.push_events(vec![
+                Box::new(JoinedEvent {
+                    id,
+                    info: PlayerInfo::new(format!("player{}", id)),
+                }),
+            ]);
+        }
+
+        state
+    }
+
+    /// Some input that keeps the players moving around, shooting and pulling hooks.
+    fn input(tick: TickNum, player_id: PlayerId) -> PlayerInput {
+        let phase = (tick + player_id * 37) % 120;
+
+        PlayerInput {
+            rot_angle: tick as f32 * 0.05 * player_id as f32,
+            move_forward: phase < 40,
+            move_backward: phase >= 80 && phase < 90,
+            move_left: phase >= 40 && phase < 60,
+            move_right: phase >= 100,
+            shoot_one: phase == 10 || phase == 70,
+            shoot_two: phase == 30,
+            pull_one: phase >= 15 && phase < 50,
+            pull_two: phase >= 35 && phase < 45,
+        }
+    }
+
+    /// Serialize the replicated state the same way as it is sent to clients. Unlike `PartialEq`,
+    /// comparing the bytes tells apart the sign of zero and considers equal NaNs to be equal.
+    fn snapshot_bytes(state: &State) -> Vec<u8> {
+        let (snapshot, resources) = store_snapshot(state);
+        let classes = state.world.read_resource::<EntityClasses>();
+        let resource_types = state.world.read_resource::<resource::Types>();
+
+        let mut writer = BitWriter::new(Vec::new());
+        WorldSnapshot::new()
+            .delta_write(&snapshot, &classes, 0, &mut writer)
+            .unwrap();
+        resource::Snapshot::new()
+            .delta_write(&resources, &resource_types, &mut writer)
+            .unwrap();
+
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_physics_replay() {
+        let game_info = testing::game_info();
+        let mut state_a = create_state(&game_info, false);
+        let mut state_b = create_state(&game_info, true);
+        let mut runner_a = AuthRunner::new(&mut state_a.world);
+        let mut runner_b = AuthRunner::new(&mut state_b.world);
+
+        for tick in 1..NUM_TICKS + 1 {
+            let inputs = PLAYERS
+                .iter()
+                .map(|&id| (id, input(tick, id)))
+                .collect::<Vec<_>>();
+
+            let events_a = runner_a.run_tick(&mut state_a, vec![inputs.clone()]).unwrap();
+            let events_b = runner_b.run_tick(&mut state_b, vec![inputs]).unwrap();
+
+            assert!(
+                snapshot_bytes(&state_a) == snapshot_bytes(&state_b),
+                "Snapshots differ in tick {}",
+                tick
+            );
+            assert_eq!(format!("{:?}", events_a), format!("{:?}", events_b));
+        }
+    }
+
+    #[test]
+    fn test_check_determinism() {
+        let game_info = testing::game_info();
+        let mut state = create_state(&game_info, false);
+        state.parallel = true;
+
+        // The check state receives the events of `state` in every tick, so it must not keep its
+        // own copy of the join events
+        let check_state = create_state(&game_info, false);
+        check_state.world.write_resource::<event::Sink>().clear();
+
+        let mut runner = AuthRunner::new(&mut state.world);
+        runner.check_determinism(check_state);
+
+        // Panics if the parallel dispatch gives a different result than the sequential one
+        for tick in 1..121 {
+            let inputs = PLAYERS
+                .iter()
+                .map(|&id| (id, input(tick, id)))
+                .collect::<Vec<_>>();
+
+            runner.run_tick(&mut state, vec![inputs]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_kinematic_prediction() {
+        // Client ticks receive the server's data of this many ticks ago, so that prediction
+        // replays inputs
+        const DELAY: TickNum = 2;
+
+        let game_info = testing::game_info();
+
+        let mut server = testing::auth_state(&game_info);
+        init::auth::create_state(&mut server.world);
+        server.push_events(vec![
+            Box::new(JoinedEvent {
+                id: 1,
+                info: PlayerInfo::new("player1".to_string()),
+            }),
+        ]);
+
+        // Stays on a straight segment of its path during the test, so its velocity is constant
+        let (platform_id, _) = repl::entity::auth::create(&mut server.world, 0, "test", |builder| {
+            let waypoints = vec![Point2::new(-400.0, 0.0), Point2::new(400.0, 0.0)];
+
+            builder
+                .with(Position(waypoints[0]))
+                .with(KinematicPath::new(waypoints, 300.0))
+        });
+
+        let mut client = testing::view_state(&game_info);
+        init::view::create_state(&mut client.world);
+
+        let mut server_runner = AuthRunner::new(&mut server.world);
+        let mut client_runner = ViewRunner::new(&mut client.world, 1, true);
+
+        let mut server_data = Vec::new();
+
+        for tick in 1..100 {
+            // The client starts sending inputs once it receives data from the server
+            let started = tick > DELAY;
+
+            let inputs = if started {
+                vec![vec![(1, input(tick, 1))]]
+            } else {
+                Vec::new()
+            };
+            let events = server_runner.run_tick(&mut server, inputs).unwrap();
+
+            let (snapshot, resources) = if started && tick % game_info.ticks_per_snapshot == 0 {
+                let (snapshot, resources) = store_snapshot(&server);
+                (Some(snapshot), Some(resources))
+            } else {
+                (None, None)
+            };
+
+            server_data.push(tick::Data {
+                events,
+                snapshot,
+                resources,
+                last_input_tick: if started { Some(tick) } else { None },
+            });
+
+            if !started {
+                continue;
+            }
+
+            client_runner
+                .run_tick(&mut client, tick, &server_data.remove(0), &input(tick, 1))
+                .unwrap();
+
+            // Wait for the first snapshot to arrive
+            if let Some(client_platform) = repl::get_id_to_entity(&client.world, platform_id) {
+                let server_platform = repl::get_id_to_entity(&server.world, platform_id).unwrap();
+                let client_positions = client.world.read::<Position>();
+                let server_positions = server.world.read::<Position>();
+                let client_position = client_positions.get(client_platform).unwrap().0;
+                let server_position = server_positions.get(server_platform).unwrap().0;
+
+                assert!(
+                    norm(&(client_position - server_position)) < 1e-3,
+                    "Platform is at {:?} on the client and at {:?} on the server in tick {}",
+                    client_position,
+                    server_position,
+                    tick
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Saving and loading of the complete authoritative game state. This is used on the server for
+//! restarting without losing the game, and for setting up reproducible scenarios.
+//!
+//! A save contains the players with their entity counters, the replicated resources, and every
+//! entity that has a `repl::Id`, together with all of its replicated components and the components
+//! that have been registered with `Registry::saved_component`. Other components are recreated by
+//! the entity class constructors when loading. Entity classes and component types are stored by
+//! name, so that saves stay valid when ids change. Resources of the game logic are only saved if
+//! they have been registered with `Registry::saved_resource`; all other resources, e.g. the random
+//! number generator for spawn positions, start out fresh when loading.
+//!
+//! The entities of the static map are not saved. Loading creates them with `init::create_map`,
+//! just like the clients do, since their shapes are not part of any saved component.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+use bit_manager::{self, BitRead, BitReader, BitWrite, BitWriter};
+use specs::prelude::{Join, RunNow, World};
+
+use defs::{EntityId, EntityIndex, PlayerId, PlayerInfo, TickNum, INVALID_ENTITY_ID};
+use entity;
+use event;
+use game::init;
+use game::{ComponentType, EntitySnapshot, LoadSnapshotSys, StoreFullSnapshotSys, WorldSnapshot};
+use physics::collision;
+use repl::snapshot::{self, ComponentType as ComponentTypeTrait,
+                     EntitySnapshot as EntitySnapshotTrait};
+use repl::{self, player, resource};
+use save;
+
+/// Identifies save files, so that we don't try to load some random file.
+const MAGIC: u32 = 0x686f_6f6b;
+
+/// Needs to be increased whenever the format changes.
+const VERSION: u32 = 3;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    BitManager(bit_manager::Error),
+    Repl(repl::Error),
+    Snapshot(snapshot::Error),
+    InvalidSave(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<bit_manager::Error> for Error {
+    fn from(error: bit_manager::Error) -> Error {
+        Error::BitManager(error)
+    }
+}
+
+impl From<repl::Error> for Error {
+    fn from(error: repl::Error) -> Error {
+        Error::Repl(error)
+    }
+}
+
+impl From<snapshot::Error> for Error {
+    fn from(error: snapshot::Error) -> Error {
+        Error::Snapshot(error)
+    }
+}
+
+/// Save the game state to a file. `tick` is the number of the next tick that is to be run.
+pub fn save_file(world: &World, tick: TickNum, path: &Path) -> Result<(), Error> {
+    let mut writer = BitWriter::new(Vec::new());
+    save(world, tick, &mut writer)?;
+
+    let data = writer.into_inner()?;
+    File::create(path)?.write_all(&data)?;
+
+    Ok(())
+}
+
+/// Load the game state from a file into a fresh `World` that does not contain any replicated
+/// entities yet. The static map is created as well. Returns the number of the next tick that is
+/// to be run.
+pub fn load_file(world: &mut World, path: &Path) -> Result<TickNum, Error> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut reader = BitReader::new(Cursor::new(data));
+    load(world, &mut reader)
+}
+
+pub fn save(world: &World, tick: TickNum, writer: &mut event::Writer) -> Result<(), Error> {
+    writer.write(&MAGIC)?;
+    writer.write(&VERSION)?;
+    writer.write(&tick)?;
+
+    let saved_components = world.read_resource::<save::Components>();
+    saved_components.write_names(writer)?;
+
+    let saved_resources = world.read_resource::<save::Resources>();
+    saved_resources.write_names(writer)?;
+
+    // Players and entity counters
+    {
+        let players = world.read_resource::<player::Players>();
+
+        writer.write(&(players.len() as u32))?;
+        for (&player_id, player) in players.iter() {
+            writer.write(&player_id)?;
+            writer.write(&player.info)?;
+            writer.write(&player.next_entity_index)?;
+        }
+    }
+
+    writer.write(&repl::entity::auth::next_index(world))?;
+
+    // Replicated resources, written as a delta against nothing
+    {
+        let resource_types = world.read_resource::<resource::Types>();
+        let resources = resource::Snapshot::store(world);
+        resource::Snapshot::new().delta_write(&resources, &resource_types, writer)?;
+    }
+
+    saved_resources.write(world, writer)?;
+
+    // Entities
+    let snapshot = {
+        let mut sys = StoreFullSnapshotSys {
+            snapshot: WorldSnapshot::new(),
+        };
+        sys.run_now(&world.res);
+        sys.snapshot
+    };
+
+    let class_names = {
+        let class_ids = world.read_resource::<entity::ClassIds>();
+        let mut class_names = class_ids
+            .0
+            .iter()
+            .map(|(name, &class_id)| (class_id, name.clone()))
+            .collect::<Vec<_>>();
+        class_names.sort();
+        class_names
+    };
+
+    for id in parents_first(&snapshot) {
+        let entity = repl::try_id_to_entity(world, id)?;
+
+        if world.read::<init::Map>().get(entity).is_some() {
+            continue;
+        }
+
+        let &(ref meta, ref entity_snapshot) = &snapshot.0[&id];
+        let class_name = &class_names[meta.class_id as usize].1;
+        let components = entity_snapshot.component_types();
+
+        writer.write(&id)?;
+        writer.write(class_name)?;
+        writer.write(&meta.parent)?;
+
+        writer.write(&(components.len() as u32))?;
+        for component in &components {
+            writer.write(&component.name().to_string())?;
+        }
+        EntitySnapshot::none().delta_write(entity_snapshot, &components, writer)?;
+
+        saved_components.write_entity(world, entity, writer)?;
+    }
+
+    writer.write(&INVALID_ENTITY_ID)?;
+
+    Ok(())
+}
+
+pub fn load(world: &mut World, reader: &mut event::Reader) -> Result<TickNum, Error> {
+    if reader.read::<u32>()? != MAGIC {
+        return Err(Error::InvalidSave("not a save file".to_string()));
+    }
+    let version = reader.read::<u32>()?;
+    if version != VERSION {
+        return Err(Error::InvalidSave(format!(
+            "save has version {}, but we need {}",
+            version, VERSION
+        )));
+    }
+
+    let tick = reader.read::<TickNum>()?;
+
+    if !world.read_resource::<save::Components>().read_names(reader)? {
+        return Err(Error::InvalidSave(
+            "saved components do not match".to_string(),
+        ));
+    }
+
+    if !world.read_resource::<save::Resources>().read_names(reader)? {
+        return Err(Error::InvalidSave(
+            "saved resources do not match".to_string(),
+        ));
+    }
+
+    if (&world.read::<repl::Id>()).join().next().is_some() {
+        return Err(Error::InvalidSave(
+            "can only load into a world without replicated entities".to_string(),
+        ));
+    }
+
+    // The map gets the same entity ids as in the saved game, since it is created first there too
+    init::create_map(world);
+
+    // Players and entity counters
+    {
+        let mut players = world.write_resource::<player::Players>();
+        players.0.clear();
+
+        let num_players = reader.read::<u32>()?;
+        for _ in 0..num_players {
+            let player_id = reader.read::<PlayerId>()?;
+            let info = reader.read::<PlayerInfo>()?;
+
+            let mut player = player::Player::new(info);
+            player.next_entity_index = reader.read::<EntityIndex>()?;
+
+            players.0.insert(player_id, player);
+        }
+    }
+
+    let next_index = reader.read::<EntityIndex>()?;
+    repl::entity::auth::set_next_index(world, next_index);
+
+    // Replicated resources
+    {
+        let resources = {
+            let resource_types = world.read_resource::<resource::Types>();
+            resource::Snapshot::new().delta_read(&resource_types, reader)?
+        };
+        resources.load(world);
+    }
+
+    world.read_resource::<save::Resources>().read(world, reader)?;
+
+    // Entities. Parents have been written before their children, so we can create the entities
+    // in order.
+    let mut snapshot = WorldSnapshot::new();
+
+    loop {
+        let id = reader.read::<EntityId>()?;
+        if id == INVALID_ENTITY_ID {
+            break;
+        }
+
+        let class_name = reader.read::<String>()?;
+        let parent = reader.read::<Option<EntityId>>()?;
+
+        let num_components = reader.read::<u32>()?;
+        let mut components = Vec::new();
+        for _ in 0..num_components {
+            let name = reader.read::<String>()?;
+            let component = ComponentType::from_name(&name).ok_or_else(|| {
+                Error::InvalidSave(format!("unknown component type {}", name))
+            })?;
+            components.push(component);
+        }
+        let entity_snapshot = EntitySnapshot::none().delta_read(&components, reader)?;
+
+        let class_id = entity::get_class_id(world, &class_name)
+            .ok_or_else(|| repl::Error::InvalidEntityClass(class_name.clone()))?;
+        let meta = entity::Meta { class_id, parent };
+
+        let entity = repl::entity::auth::restore(world, id, meta.clone(), |builder| builder)?;
+        world
+            .read_resource::<save::Components>()
+            .read_entity(world, entity, reader)?;
+
+        snapshot.0.insert(id, (meta, entity_snapshot));
+    }
+
+    {
+        let mut sys = LoadSnapshotSys {
+            snapshot: &snapshot,
+            exclude_player: None,
+            only_player: None,
+        };
+        sys.run_now(&world.res);
+    }
+
+    // Rebuild the collision world
+    collision::MaintainSys.run_now(&world.res);
+
+    world.maintain();
+
+    Ok(tick)
+}
+
+/// Order the entities of a snapshot so that every parent comes before its children.
+fn parents_first(snapshot: &WorldSnapshot) -> Vec<EntityId> {
+    let mut order = Vec::new();
+    let mut visited = BTreeSet::new();
+    let mut remaining = snapshot.0.keys().cloned().collect::<Vec<_>>();
+
+    while !remaining.is_empty() {
+        let (ready, rest): (Vec<_>, Vec<_>) = remaining.into_iter().partition(|id| {
+            match (snapshot.0[id].0).parent {
+                Some(parent) => visited.contains(&parent) || !snapshot.0.contains_key(&parent),
+                None => true,
+            }
+        });
+
+        assert!(!ready.is_empty(), "cyclic entity hierarchy");
+
+        visited.extend(ready.iter().cloned());
+        order.extend(ready);
+        remaining = rest;
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bit_manager::{BitReader, BitWriter};
+    use nalgebra::Point2;
+    use specs::prelude::{Join, World};
+
+    use entity;
+    use game::init;
+    use game::lifetime::Lifetime;
+    use game::testing;
+    use physics::collision::{Object, Shape};
+    use physics::query::{self, Filter};
+    use physics::Position;
+    use repl;
+
+    use super::{load, save};
+
+    fn num_walls(world: &World) -> usize {
+        let class_id = entity::get_class_id(world, "wall").unwrap();
+        let meta = world.read::<entity::Meta>();
+        let shape = world.read::<Shape>();
+        let object = world.read::<Object>();
+
+        (&meta, &shape, &object)
+            .join()
+            .filter(|&(meta, _, _)| meta.class_id == class_id)
+            .count()
+    }
+
+    #[test]
+    fn test_walls_after_load() {
+        let game_info = testing::game_info();
+
+        let mut state = testing::auth_state(&game_info);
+        init::auth::create_state(&mut state.world);
+
+        let mut writer = BitWriter::new(Vec::new());
+        save(&state.world, 42, &mut writer).unwrap();
+        let data = writer.into_inner().unwrap();
+
+        let mut loaded = testing::auth_state(&game_info);
+        let mut reader = BitReader::new(Cursor::new(data));
+        assert_eq!(load(&mut loaded.world, &mut reader).unwrap(), 42);
+
+        assert!(num_walls(&state.world) > 0);
+        assert_eq!(num_walls(&loaded.world), num_walls(&state.world));
+        assert_eq!(
+            (&loaded.world.read::<repl::Id>()).join().count(),
+            (&state.world.read::<repl::Id>()).join().count()
+        );
+
+        // One of the walls of the map is at this position
+        let filter = Filter::new().with_classes(&loaded.world, &["wall"]);
+        assert!(!query::point(&loaded.world, Point2::new(0.0, -500.0), &filter).is_empty());
+    }
+
+    #[test]
+    fn test_saved_components() {
+        let game_info = testing::game_info();
+
+        // The test class does not replicate its lifetime
+        let mut state = testing::auth_state(&game_info);
+        let (id, _) = repl::entity::auth::create(&mut state.world, 0, "test", |builder| {
+            builder
+                .with(Position(Point2::origin()))
+                .with(Lifetime::ticks(7))
+        });
+
+        let mut writer = BitWriter::new(Vec::new());
+        save(&state.world, 0, &mut writer).unwrap();
+        let data = writer.into_inner().unwrap();
+
+        let mut loaded = testing::auth_state(&game_info);
+        let mut reader = BitReader::new(Cursor::new(data));
+        load(&mut loaded.world, &mut reader).unwrap();
+
+        let entity = repl::get_id_to_entity(&loaded.world, id).unwrap();
+        assert_eq!(
+            loaded.world.read::<Lifetime>().get(entity),
+            Some(&Lifetime::ticks(7))
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Server-side scripting of game modes with Rhai. This makes it possible to prototype game rules
+//! without recompiling the server.
+//!
+//! Every `.rhai` file in the `scripts` directory of the data directory (see `data::dir`) is loaded
+//! at startup. The directory is optional, so it is not shipped with the game; example scripts can
+//! be found in `data/script_examples`. A script can define the following callbacks, all of which
+//! are optional:
+//!
+//! - `pre_tick()`: called at the start of every tick,
+//! - `on_player_joined(player)`, `on_player_left(player)`: called after a player has joined or
+//!   left,
+//! - `on_event(name)`: called after every event of the tick, with the name that the event's type
+//!   has been registered with, e.g. `JoinedEvent`.
+//!
+//! Scripts never touch the `World` directly. Instead, they see a copy of the state that is taken
+//! before a callback is run, and their commands are applied afterwards. The following functions
+//! are available:
+//!
+//! - `players()`: ids of all players,
+//! - `player_entity(player)`: id of the player's main entity, or `-1` if it does not exist,
+//! - `entities(class)`: ids of all entities of a class,
+//! - `position(entity)`: position of an entity as an array `[x, y]`, or `()`,
+//! - `spawn(class, x, y)`: create an entity without an owner at a position. Only classes that are
+//!   defined by prefabs can be spawned, since other classes need more than a position,
+//! - `spawn_player(player, x, y)`: create the main entity of a player,
+//! - `remove(entity)`: remove an entity,
+//! - `push_event(name, value)`: push a `ScriptEvent`, which is also sent to the clients,
+//! - `log(message)`: print a message to the server log.
+//!
+//! Entity ids are given as integers, with the owner in the upper 32 bits and the index in the
+//! lower 32 bits.
+//!
+//! Every callback runs with limits on the number of operations, the call depth and the size of
+//! strings, arrays and maps, so that a runaway script cannot block the tick. A script that exceeds
+//! a limit is disabled for the rest of the game.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use nalgebra::Point2;
+use rhai::{self, Array, Dynamic, Engine, EvalAltResult, Scope, AST, FLOAT, INT};
+use specs::prelude::{Join, World};
+
+use defs::{EntityId, GameInfo, PlayerId, INVALID_PLAYER_ID};
+use entity;
+use event::{self, Event};
+use game::entity::player;
+use game::prefab;
+use physics::Position;
+use plugin;
+use registry::Registry;
+use repl;
+
+/// Directory, relative to the data directory, from which the server loads scripts at startup.
+pub const DIR: &str = "scripts";
+
+/// Extension of script files.
+pub const EXTENSION: &str = "rhai";
+
+/// Maximum number of operations in one callback. This is generous for game logic, but still
+/// quick to run into with an endless loop.
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+/// Maximum depth of nested function calls.
+pub const MAX_CALL_LEVELS: usize = 32;
+
+/// Maximum size of strings, arrays and maps that a script can create.
+pub const MAX_STRING_SIZE: usize = 4096;
+pub const MAX_ARRAY_SIZE: usize = 1024;
+pub const MAX_MAP_SIZE: usize = 1024;
+
+/// Plugin that runs the scripts in `DIR` on the server.
+pub struct Plugin {
+    dir: PathBuf,
+}
+
+impl Plugin {
+    pub fn new(data_dir: &Path) -> Plugin {
+        Plugin {
+            dir: data_dir.join(DIR),
+        }
+    }
+}
+
+impl plugin::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["game::entity"]
+    }
+
+    fn register(&self, reg: &mut Registry, _: &GameInfo) {
+        reg.event::<ScriptEvent>("ScriptEvent");
+    }
+
+    fn register_auth(&self, reg: &mut Registry, _: &GameInfo) {
+        auth::register(reg, &self.dir);
+    }
+}
+
+/// Event that is pushed by scripts. Clients receive it as well, so that they can e.g. show
+/// something.
+#[derive(Debug, Clone, BitStore)]
+pub struct ScriptEvent {
+    pub name: String,
+    pub value: f32,
+}
+
+impl Event for ScriptEvent {
+    fn class(&self) -> event::Class {
+        event::Class::Order
+    }
+}
+
+fn encode_id(id: EntityId) -> INT {
+    (INT::from(id.0) << 32) | INT::from(id.1)
+}
+
+fn decode_id(id: INT) -> EntityId {
+    ((id >> 32) as PlayerId, id as u32)
+}
+
+/// Copy of the game state that scripts can read, together with the commands that they issue.
+#[derive(Default)]
+struct Context {
+    players: BTreeMap<PlayerId, Option<EntityId>>,
+    entities: BTreeMap<EntityId, (String, Point2<f32>)>,
+    commands: Vec<Command>,
+}
+
+enum Command {
+    Spawn(String, Point2<f32>),
+    SpawnPlayer(PlayerId, Point2<f32>),
+    Remove(EntityId),
+    PushEvent(ScriptEvent),
+}
+
+pub mod auth {
+    use super::*;
+
+    pub fn register(reg: &mut Registry, dir: &Path) {
+        let scripts = if dir.is_dir() {
+            Scripts::load_dir(dir)
+        } else {
+            Scripts::new()
+        };
+
+        reg.resource(scripts);
+        reg.pre_tick_fn(pre_tick);
+        reg.event_handler_post_tick(handle_event_post_tick);
+    }
+
+    struct Script {
+        name: String,
+        ast: AST,
+
+        /// Set once the script has exceeded one of the engine's limits.
+        disabled: bool,
+    }
+
+    /// The scripting engine and the loaded scripts.
+    pub struct Scripts {
+        engine: Engine,
+        scripts: Vec<Script>,
+        context: Arc<Mutex<Context>>,
+    }
+
+    impl Scripts {
+        fn new() -> Scripts {
+            let context = Arc::new(Mutex::new(Context::default()));
+            let engine = create_engine(&context);
+
+            Scripts {
+                engine,
+                scripts: Vec::new(),
+                context,
+            }
+        }
+
+        /// Load all the scripts in a directory, in order of their names. Scripts that fail to
+        /// compile are skipped, so that designers can fix them without taking down the server.
+        fn load_dir(dir: &Path) -> Scripts {
+            let mut scripts = Scripts::new();
+
+            let mut paths = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().map(|ext| ext == EXTENSION).unwrap_or(false))
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            for path in paths {
+                match scripts.engine.compile_file(path.clone()) {
+                    Ok(ast) => {
+                        info!("Loaded script {}", path.display());
+
+                        scripts.scripts.push(Script {
+                            name: path.display().to_string(),
+                            ast,
+                            disabled: false,
+                        });
+                    }
+                    Err(error) => warn!("Failed to compile script {}: {}", path.display(), error),
+                }
+            }
+
+            scripts
+        }
+
+        /// Call a function in all enabled scripts that define it.
+        fn call<A: rhai::FuncArgs + Clone>(&mut self, function: &str, args: A) {
+            for script in self.scripts.iter_mut().filter(|script| !script.disabled) {
+                let result: Result<Dynamic, Box<EvalAltResult>> = self.engine.call_fn(
+                    &mut Scope::new(),
+                    &script.ast,
+                    function,
+                    args.clone(),
+                );
+
+                match result {
+                    Ok(_) => {}
+                    Err(ref error) if is_missing_function(error, function) => {}
+                    Err(ref error) if is_limit_exceeded(error) => {
+                        error!(
+                            "Script {} exceeded a limit while calling {}, disabling it: {}",
+                            script.name, function, error
+                        );
+                        script.disabled = true;
+                    }
+                    Err(error) => warn!(
+                        "Error in script {} while calling {}: {}",
+                        script.name, function, error
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Is the error due to the script exceeding one of the limits of the engine, possibly in a
+    /// nested function call?
+    fn is_limit_exceeded(error: &EvalAltResult) -> bool {
+        match *error {
+            EvalAltResult::ErrorTooManyOperations(..) |
+            EvalAltResult::ErrorStackOverflow(..) |
+            EvalAltResult::ErrorDataTooLarge(..) => true,
+            EvalAltResult::ErrorInFunctionCall(_, ref inner, _) => is_limit_exceeded(inner),
+            _ => false,
+        }
+    }
+
+    /// Is the error due to `function` itself not being defined? Errors about other missing
+    /// functions, e.g. ones called by `function`, are real errors.
+    fn is_missing_function(error: &EvalAltResult, function: &str) -> bool {
+        match *error {
+            EvalAltResult::ErrorFunctionNotFound(ref signature, _) => {
+                // The signature may include the argument types, e.g. `on_event (&str)`
+                signature.split('(').next().map(str::trim) == Some(function)
+            }
+            _ => false,
+        }
+    }
+
+    fn create_engine(context: &Arc<Mutex<Context>>) -> Engine {
+        let mut engine = Engine::new();
+
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_ARRAY_SIZE);
+        engine.set_max_map_size(MAX_MAP_SIZE);
+
+        let c = context.clone();
+        engine.register_fn("players", move || -> Array {
+            c.lock()
+                .unwrap()
+                .players
+                .keys()
+                .map(|&id| Dynamic::from(INT::from(id)))
+                .collect()
+        });
+
+        let c = context.clone();
+        engine.register_fn("player_entity", move |player: INT| -> INT {
+            c.lock()
+                .unwrap()
+                .players
+                .get(&(player as PlayerId))
+                .and_then(|entity| *entity)
+                .map(encode_id)
+                .unwrap_or(-1)
+        });
+
+        let c = context.clone();
+        engine.register_fn("entities", move |class: &str| -> Array {
+            c.lock()
+                .unwrap()
+                .entities
+                .iter()
+                .filter(|&(_, &(ref entity_class, _))| entity_class == class)
+                .map(|(&id, _)| Dynamic::from(encode_id(id)))
+                .collect()
+        });
+
+        let c = context.clone();
+        engine.register_fn("position", move |id: INT| -> Dynamic {
+            c.lock()
+                .unwrap()
+                .entities
+                .get(&decode_id(id))
+                .map(|&(_, pos)| {
+                    let pos: Array =
+                        vec![Dynamic::from(pos.x as FLOAT), Dynamic::from(pos.y as FLOAT)];
+                    Dynamic::from(pos)
+                })
+                .unwrap_or_else(Dynamic::default)
+        });
+
+        let c = context.clone();
+        engine.register_fn("spawn", move |class: &str, x: FLOAT, y: FLOAT| {
+            let pos = Point2::new(x as f32, y as f32);
+            c.lock()
+                .unwrap()
+                .commands
+                .push(Command::Spawn(class.to_string(), pos));
+        });
+
+        let c = context.clone();
+        engine.register_fn("spawn_player", move |player: INT, x: FLOAT, y: FLOAT| {
+            let pos = Point2::new(x as f32, y as f32);
+            c.lock()
+                .unwrap()
+                .commands
+                .push(Command::SpawnPlayer(player as PlayerId, pos));
+        });
+
+        let c = context.clone();
+        engine.register_fn("remove", move |id: INT| {
+            c.lock().unwrap().commands.push(Command::Remove(decode_id(id)));
+        });
+
+        let c = context.clone();
+        engine.register_fn("push_event", move |name: &str, value: FLOAT| {
+            let event = ScriptEvent {
+                name: name.to_string(),
+                value: value as f32,
+            };
+            c.lock().unwrap().commands.push(Command::PushEvent(event));
+        });
+
+        engine.register_fn("log", |message: &str| {
+            info!("script: {}", message);
+        });
+
+        engine
+    }
+
+    /// Copy the state that scripts can read into the context.
+    fn update_context(world: &World) {
+        let scripts = world.read_resource::<Scripts>();
+        let mut context = scripts.context.lock().unwrap();
+
+        let class_names = world
+            .read_resource::<entity::ClassIds>()
+            .0
+            .iter()
+            .map(|(name, &class_id)| (class_id, name.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        let repl_id = world.read::<repl::Id>();
+        let meta = world.read::<entity::Meta>();
+        let position = world.read::<Position>();
+
+        context.entities = (&repl_id, &meta, &position)
+            .join()
+            .map(|(repl_id, meta, position)| {
+                (repl_id.0, (class_names[&meta.class_id].clone(), position.0))
+            })
+            .collect();
+
+        context.players = world
+            .read_resource::<repl::player::Players>()
+            .iter()
+            .map(|(&player_id, player)| {
+                let entity_id = player
+                    .entity
+                    .and_then(|entity| repl_id.get(entity))
+                    .map(|repl_id| repl_id.0);
+                (player_id, entity_id)
+            })
+            .collect();
+    }
+
+    /// Apply the commands that scripts have issued. Invalid commands are skipped with a warning.
+    fn apply_commands(world: &mut World) {
+        let commands = {
+            let scripts = world.read_resource::<Scripts>();
+            let mut context = scripts.context.lock().unwrap();
+            context.commands.drain(..).collect::<Vec<_>>()
+        };
+
+        for command in commands {
+            match command {
+                Command::Spawn(class, pos) => {
+                    if prefab::is_prefab(world, &class) {
+                        repl::entity::auth::create(world, INVALID_PLAYER_ID, &class, |builder| {
+                            builder.with(Position(pos))
+                        });
+                    } else {
+                        warn!("Script tried to spawn entity of non-prefab class {}", class);
+                    }
+                }
+                Command::SpawnPlayer(player_id, pos) => {
+                    let can_spawn = world
+                        .read_resource::<repl::player::Players>()
+                        .get(player_id)
+                        .map(|player| player.entity.is_none())
+                        .unwrap_or(false);
+
+                    if can_spawn {
+                        player::auth::create(world, player_id, pos);
+                    } else {
+                        warn!("Script tried to spawn player {}, which is not possible", player_id);
+                    }
+                }
+                Command::Remove(id) => {
+                    if let Some(entity) = repl::get_id_to_entity(world, id) {
+                        entity::deferred_remove(world, entity);
+                    } else {
+                        warn!("Script tried to remove nonexistent entity {:?}", id);
+                    }
+                }
+                Command::PushEvent(event) => {
+                    world.write_resource::<event::Sink>().push(event);
+                }
+            }
+        }
+    }
+
+    fn pre_tick(world: &mut World) -> Result<(), repl::Error> {
+        if world.read_resource::<Scripts>().scripts.is_empty() {
+            return Ok(());
+        }
+
+        update_context(world);
+        world.write_resource::<Scripts>().call("pre_tick", ());
+        apply_commands(world);
+
+        Ok(())
+    }
+
+    fn handle_event_post_tick(world: &mut World, event: &Event) -> Result<(), repl::Error> {
+        if world.read_resource::<Scripts>().scripts.is_empty() {
+            return Ok(());
+        }
+
+        update_context(world);
+
+        {
+            let mut scripts = world.write_resource::<Scripts>();
+
+            match_event!(event:
+                repl::player::JoinedEvent => {
+                    scripts.call("on_player_joined", (INT::from(event.id),));
+                },
+                repl::player::LeftEvent => {
+                    scripts.call("on_player_left", (INT::from(event.id),));
+                },
+            );
+
+            let name = world.read_resource::<event::Registry>().type_name(event);
+            match name {
+                Some(name) => scripts.call("on_event", (name.to_string(),)),
+                None => warn!("Event {:?} has not been registered", event),
+            }
+        }
+
+        apply_commands(world);
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use game::testing;
+
+        use super::*;
+
+        #[test]
+        fn test_is_missing_function() {
+            let engine = Engine::new();
+            let ast = engine
+                .compile("fn on_tick() { on_tick_foo(); }")
+                .unwrap();
+
+            let call = |function: &str| {
+                engine
+                    .call_fn::<_, Dynamic>(&mut Scope::new(), &ast, function, ())
+                    .unwrap_err()
+            };
+
+            assert!(is_missing_function(&call("pre_tick"), "pre_tick"));
+            assert!(!is_missing_function(&call("on_tick"), "on_tick"));
+        }
+
+        #[test]
+        fn test_limits() {
+            let mut scripts = Scripts::new();
+            let sources = [
+                ("loop", "fn pre_tick() { loop {} }"),
+                ("recursion", "fn f(x) { f(x + 1) } fn pre_tick() { f(0); }"),
+                ("string", "fn pre_tick() { let s = \"x\"; loop { s += s; } }"),
+                ("fine", "fn pre_tick() { let x = 0; x += 1; }"),
+            ];
+
+            for &(name, source) in &sources {
+                let ast = scripts.engine.compile(source).unwrap();
+                scripts.scripts.push(Script {
+                    name: name.to_string(),
+                    ast,
+                    disabled: false,
+                });
+            }
+
+            // This returns instead of hanging, and disables the offending scripts
+            scripts.call("pre_tick", ());
+
+            let disabled = scripts
+                .scripts
+                .iter()
+                .map(|script| script.disabled)
+                .collect::<Vec<_>>();
+            assert_eq!(disabled, vec![true, true, true, false]);
+        }
+
+        #[test]
+        fn test_spawn_only_prefabs() {
+            let game_info = testing::game_info();
+            let mut state = testing::auth_state(&game_info);
+
+            {
+                let scripts = state.world.read_resource::<Scripts>();
+                let mut context = scripts.context.lock().unwrap();
+                context
+                    .commands
+                    .push(Command::Spawn("wall".to_string(), Point2::origin()));
+                context
+                    .commands
+                    .push(Command::Spawn("test".to_string(), Point2::origin()));
+            }
+
+            apply_commands(&mut state.world);
+
+            let classes = {
+                let meta = state.world.read::<entity::Meta>();
+                (&meta)
+                    .join()
+                    .map(|meta| meta.class_id)
+                    .collect::<Vec<_>>()
+            };
+            let test_id = entity::get_class_id(&state.world, "test").unwrap();
+
+            assert_eq!(classes, vec![test_id]);
+        }
+    }
+}
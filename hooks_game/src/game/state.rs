@@ -12,10 +12,24 @@ pub struct State {
     pub(in game) tick_dispatcher: Dispatcher<'static, 'static>,
     pub(in game) post_tick_event_handlers: Vec<EventHandler>,
     pub(in game) removal_dispatcher: Dispatcher<'static, 'static>,
+
+    /// Run the tick and removal systems in parallel, as far as their declared dependencies and
+    /// data access allow. If this is `false`, systems are run one after another in the order of
+    /// registration.
+    pub parallel: bool,
 }
 
 impl State {
-    pub fn from_registry(reg: Registry) -> State {
+    pub fn from_registry(mut reg: Registry) -> State {
+        reg.event_reg.freeze();
+
+        // Game logic can look up event types, e.g. for their names
+        reg.world.add_resource(reg.event_reg.clone());
+
+        for freeze_fn in &reg.freeze_fns {
+            freeze_fn(&mut reg.world);
+        }
+
         State {
             world: reg.world,
             event_reg: reg.event_reg,
@@ -25,6 +39,23 @@ impl State {
             tick_dispatcher: reg.tick_systems.build(),
             post_tick_event_handlers: reg.post_tick_event_handlers,
             removal_dispatcher: reg.removal_systems.build(),
+            parallel: true,
+        }
+    }
+
+    pub(in game) fn dispatch_tick(&mut self) {
+        if self.parallel {
+            self.tick_dispatcher.dispatch(&self.world.res);
+        } else {
+            self.tick_dispatcher.dispatch_seq(&self.world.res);
+        }
+    }
+
+    pub(in game) fn dispatch_removals(&mut self) {
+        if self.parallel {
+            self.removal_dispatcher.dispatch(&self.world.res);
+        } else {
+            self.removal_dispatcher.dispatch_seq(&self.world.res);
         }
     }
 
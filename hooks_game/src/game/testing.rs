@@ -0,0 +1,41 @@
+//! Helpers for tests that run the game.
+
+use std::path::{Path, PathBuf};
+
+use defs::{GameInfo, MapInfo, PhysicsConfig};
+use game::state::State;
+use registry::Registry;
+
+/// Data directory of the repository. We do not rely on the working directory, since tests run in
+/// parallel in the same process.
+pub fn data_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("data")
+}
+
+pub fn game_info() -> GameInfo {
+    GameInfo {
+        ticks_per_second: 60,
+        ticks_per_snapshot: 2,
+        map_info: MapInfo,
+        player_entity_class: "player".to_string(),
+        server_target_lag_inputs: 1,
+        client_target_lag_snapshots: 2,
+        physics: PhysicsConfig::default(),
+    }
+}
+
+/// Server-side game state without any entities. Systems are dispatched sequentially.
+pub fn auth_state(game_info: &GameInfo) -> State {
+    let plugins = ::plugins_with_data_dir(&data_dir());
+    let mut state = State::from_registry(Registry::auth(&plugins, game_info));
+    state.parallel = false;
+    state
+}
+
+/// Client-side game state without any entities. Systems are dispatched sequentially.
+pub fn view_state(game_info: &GameInfo) -> State {
+    let plugins = ::plugins_with_data_dir(&data_dir());
+    let mut state = State::from_registry(Registry::view(&plugins, game_info));
+    state.parallel = false;
+    state
+}
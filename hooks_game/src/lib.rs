@@ -13,6 +13,11 @@ extern crate mopa;
 extern crate nalgebra;
 extern crate ncollide;
 extern crate rand;
+extern crate rhai;
+extern crate ron;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate shred;
 #[macro_use]
 extern crate shred_derive;
@@ -20,6 +25,7 @@ extern crate specs;
 #[macro_use]
 extern crate specs_derive;
 
+pub mod data;
 pub mod defs;
 pub mod entity;
 pub mod physics;
@@ -30,34 +36,45 @@ pub mod registry;
 pub mod repl;
 pub mod game;
 pub mod net;
+pub mod plugin;
+pub mod save;
+
+use std::path::Path;
 
 pub use defs::*;
 pub use event::Event;
+pub use plugin::{Plugin, Plugins};
 pub use registry::Registry;
 
-fn register(reg: &mut Registry, game_info: &GameInfo) {
-    reg.resource(game_info.clone());
-    reg.resource(event::Sink::new());
+/// Core modules that are needed by all game modes.
+pub struct CorePlugin;
 
-    entity::register(reg);
-    physics::register(reg);
-    repl::register(reg);
-}
+impl Plugin for CorePlugin {
+    fn name(&self) -> &'static str {
+        "core"
+    }
 
-pub mod auth {
-    use super::*;
+    fn register(&self, reg: &mut Registry, game_info: &GameInfo) {
+        reg.resource(game_info.clone());
+        reg.resource(event::Sink::new());
 
-    pub fn register(reg: &mut Registry, game_info: &GameInfo) {
-        super::register(reg, game_info);
-        game::auth::register(reg);
+        entity::register(reg);
+        save::register(reg);
+        physics::register(reg);
+        repl::register(reg);
     }
 }
 
-pub mod view {
-    use super::*;
+/// All the plugins that make up the game. Server and clients build their `Registry` from this
+/// list, possibly adding their own plugins.
+pub fn plugins() -> Plugins {
+    plugins_with_data_dir(&data::dir())
+}
 
-    pub fn register(reg: &mut Registry, game_info: &GameInfo) {
-        super::register(reg, game_info);
-        game::view::register(reg);
-    }
+/// All the plugins that make up the game, loading data files from the given directory.
+pub fn plugins_with_data_dir(data_dir: &Path) -> Plugins {
+    let mut plugins = Plugins::new();
+    plugins.add(CorePlugin);
+    plugins.extend(game::plugins(data_dir));
+    plugins
 }
@@ -1,58 +1,174 @@
+//! Collision detection. We keep our own collision world instead of using ncollide's, so that
+//! finding contacts can be tailored to the game: a uniform grid serves as the broadphase, and only
+//! the entities that are being simulated in a physics run look for contacts. ncollide is used for
+//! the shapes and the narrow phase.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::f32;
 use std::marker::PhantomData;
 
 use specs::prelude::*;
-use specs::storage::{BTreeStorage, VecStorage};
+use specs::storage::{BTreeStorage, NullStorage, VecStorage};
 
-use nalgebra::{self, Isometry2};
+use nalgebra::{self, Isometry2, Point2, Vector2};
+use ncollide::bounding_volume::AABB;
 use ncollide::math::{Isometry, Point};
 use ncollide::narrow_phase::{BallBallContactGenerator, CompositeShapeShapeContactGenerator,
-                             ContactAlgorithm, ContactDispatcher, DefaultNarrowPhase,
-                             DefaultProximityDispatcher, OneShotContactManifoldGenerator,
-                             PlaneSupportMapContactGenerator, ShapeCompositeShapeContactGenerator,
+                             ContactAlgorithm, ContactDispatcher,
+                             OneShotContactManifoldGenerator, PlaneSupportMapContactGenerator,
+                             ShapeCompositeShapeContactGenerator,
                              SupportMapPlaneContactGenerator, SupportMapSupportMapContactGenerator};
 use ncollide::query::algorithms::{JohnsonSimplex, VoronoiSimplex2, VoronoiSimplex3};
-use ncollide::shape::{self, Ball, Plane, ShapeHandle2};
-use ncollide::world::{CollisionGroupsPairFilter, CollisionObjectHandle, CollisionWorld2};
+use ncollide::query::{Contact, Ray, RayIntersection};
+use ncollide::shape::{self, Plane, ShapeHandle2};
 
 use hooks_util::profile;
 
 use entity;
-use physics::{Orientation, Position};
+use physics::grid::Grid;
+use physics::{order_key, OrderKey, Orientation, Position};
 use registry::Registry;
+use repl;
 
-pub use ncollide::shape::{Cuboid, ShapeHandle};
+pub use ncollide::shape::{Ball, Capsule, ConvexHull, Cuboid, ShapeHandle};
 pub use ncollide::world::{CollisionGroups, GeometricQueryType};
 
 pub fn register(reg: &mut Registry) {
     reg.component::<Shape>();
     reg.component::<Object>();
-    reg.component::<ObjectHandle>();
+    reg.component::<InWorld>();
 
-    let contact_dispatcher = StatelessContactDispatcher::default();
-    let proximity_dispatcher = DefaultProximityDispatcher::new();
-    let narrow_phase =
-        DefaultNarrowPhase::new(Box::new(contact_dispatcher), Box::new(proximity_dispatcher));
-    let mut collision_world = CollisionWorld2::<f32, Entity>::new(0.02);
-    collision_world.set_narrow_phase(Box::new(narrow_phase));
-    reg.resource(collision_world);
+    reg.resource(CollisionWorld::new());
 
     reg.removal_system(RemovalSys, "collision");
 }
 
-pub type CollisionWorld = CollisionWorld2<f32, Entity>;
-
-// Collision groups. Put them here for now.
-pub const GROUP_WALL: usize = 0;
-pub const GROUP_PLAYER: usize = 1;
-pub const GROUP_PLAYER_ENTITY: usize = 2;
-pub const GROUP_NEUTRAL: usize = 3;
+/// Side length of the cells of the broadphase grid. Hook segments and players fit into a few
+/// cells, while walls span many of them.
+pub const CELL_SIZE: f32 = 64.0;
 
 /// Collision shape.
 /// For now, we assume that an object's shape will not change in its lifetime.
+///
+/// The game uses the following ncollide shapes, all of them centered at the origin of the
+/// object space:
+/// - `Cuboid`: box given by its half extents,
+/// - `Ball`: circle given by its radius,
+/// - `Capsule`: vertical line segment of half height `half_height`, extended by `radius`,
+/// - `ConvexHull`: convex polygon with counter-clockwise vertices.
 #[derive(Clone, Component)]
 #[storage(VecStorage)]
 pub struct Shape(pub ShapeHandle2<f32>);
 
+impl Shape {
+    /// Box with the given total width and height.
+    pub fn cuboid(size: Vector2<f32>) -> Shape {
+        assert!(size.x > 0.0 && size.y > 0.0);
+
+        Shape(ShapeHandle::new(Cuboid::new(size / 2.0)))
+    }
+
+    pub fn ball(radius: f32) -> Shape {
+        assert!(radius > 0.0);
+
+        Shape(ShapeHandle::new(Ball::new(radius)))
+    }
+
+    /// Capsule along the object's y axis. `half_height` is the half length of the inner line
+    /// segment, so the total height is `2 * (half_height + radius)`.
+    pub fn capsule(half_height: f32, radius: f32) -> Shape {
+        assert!(half_height >= 0.0 && radius > 0.0);
+
+        Shape(ShapeHandle::new(Capsule::new(half_height, radius)))
+    }
+
+    /// Convex polygon with the given vertices. The vertices are moved so that the polygon's
+    /// centroid is at the origin, since bodies rotate around their position. They may be given in
+    /// either winding order. Returns `None` if the polygon is not strictly convex.
+    pub fn convex_polygon(points: &[Point2<f32>]) -> Option<Shape> {
+        let mut points = points.to_vec();
+
+        match winding(&points) {
+            Some(Winding::CounterClockwise) => {}
+            Some(Winding::Clockwise) => points.reverse(),
+            None => return None,
+        }
+
+        let centroid = centroid(&points);
+        for point in &mut points {
+            *point = Point2::from_coordinates(*point - centroid);
+        }
+
+        Some(Shape(ShapeHandle::new(ConvexHull::new(points))))
+    }
+}
+
+/// Centroid of the area of a polygon with counter-clockwise vertices.
+fn centroid(points: &[Point2<f32>]) -> Point2<f32> {
+    let mut area = 0.0;
+    let mut sum = Vector2::new(0.0, 0.0);
+
+    for i in 0..points.len() {
+        let a = points[i].coords;
+        let b = points[(i + 1) % points.len()].coords;
+        let cross = a.perp(&b);
+
+        area += cross / 2.0;
+        sum += (a + b) * cross;
+    }
+
+    Point2::from_coordinates(sum / (6.0 * area))
+}
+
+enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Determine the winding order of a polygon, or `None` if it is degenerate or not strictly
+/// convex.
+fn winding(points: &[Point2<f32>]) -> Option<Winding> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    let mut sign = 0.0;
+
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        let c = points[(i + 2) % points.len()];
+
+        let cross = (b - a).perp(&(c - b));
+
+        if cross.abs() < 1e-6 || cross * sign < 0.0 {
+            return None;
+        }
+
+        sign = cross.signum();
+    }
+
+    // Convex turns alone still allow for star-shaped polygons that wind around multiple times
+    let mut angle_sum = 0.0;
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        let c = points[(i + 2) % points.len()];
+
+        angle_sum += (b - a).perp(&(c - b)).atan2((b - a).dot(&(c - b)));
+    }
+
+    if (angle_sum.abs() - 2.0 * f32::consts::PI).abs() > 1e-3 {
+        return None;
+    }
+
+    if sign > 0.0 {
+        Some(Winding::CounterClockwise)
+    } else {
+        Some(Winding::Clockwise)
+    }
+}
+
 /// Component which indicates that we should inform the collision world of this entity.
 /// Note that only `entity::Active` entities are kept in the collision world.
 #[derive(Component)]
@@ -62,10 +178,251 @@ pub struct Object {
     pub query_type: GeometricQueryType<f32>,
 }
 
-/// Handle of an ncollide CollisionObject.
-#[derive(Component)]
-#[storage(VecStorage)]
-pub struct ObjectHandle(CollisionObjectHandle);
+/// Tag component for entities that are present in the collision world.
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct InWorld;
+
+/// An object in the collision world, as of the last time its entity's pose was updated.
+pub struct CollisionObject {
+    entity: Entity,
+    order_key: OrderKey,
+    position: Isometry2<f32>,
+    shape: ShapeHandle2<f32>,
+    groups: CollisionGroups,
+    query_type: GeometricQueryType<f32>,
+}
+
+impl CollisionObject {
+    pub fn data(&self) -> &Entity {
+        &self.entity
+    }
+
+    pub fn position(&self) -> &Isometry2<f32> {
+        &self.position
+    }
+
+    pub fn shape(&self) -> &ShapeHandle2<f32> {
+        &self.shape
+    }
+
+    pub fn groups(&self) -> &CollisionGroups {
+        &self.groups
+    }
+
+    /// Distance at which contacts are already reported.
+    fn prediction(&self) -> f32 {
+        match self.query_type {
+            GeometricQueryType::Contacts(prediction, _) => prediction,
+            GeometricQueryType::Proximity(margin) => margin,
+        }
+    }
+}
+
+/// Resource that stores the collision objects of all `entity::Active` entities with an `Object`.
+///
+/// Queries return objects in `physics::order_key` order, so that game logic that e.g. picks the
+/// first hit behaves the same on the server and on the clients.
+pub struct CollisionWorld {
+    objects: BTreeMap<Entity, CollisionObject>,
+    grid: Grid,
+    contact_dispatcher: StatelessContactDispatcher<Point2<f32>, Isometry2<f32>>,
+}
+
+impl CollisionWorld {
+    pub fn new() -> CollisionWorld {
+        CollisionWorld {
+            objects: BTreeMap::new(),
+            grid: Grid::new(CELL_SIZE),
+            contact_dispatcher: StatelessContactDispatcher::default(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        entity: Entity,
+        order_key: OrderKey,
+        position: Isometry2<f32>,
+        shape: ShapeHandle2<f32>,
+        groups: CollisionGroups,
+        query_type: GeometricQueryType<f32>,
+    ) {
+        self.grid.set(entity, shape.aabb(&position));
+        self.objects.insert(
+            entity,
+            CollisionObject {
+                entity,
+                order_key,
+                position,
+                shape,
+                groups,
+                query_type,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        self.grid.remove(entity);
+        self.objects.remove(&entity);
+    }
+
+    pub fn set_position(&mut self, entity: Entity, position: Isometry2<f32>) {
+        if let Some(object) = self.objects.get_mut(&entity) {
+            self.grid.set(entity, object.shape.aabb(&position));
+            object.position = position;
+        }
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&CollisionObject> {
+        self.objects.get(&entity)
+    }
+
+    /// Objects whose bounding box intersects the given box and that can interact with `groups`.
+    pub fn interferences_with_aabb(
+        &self,
+        aabb: &AABB<Point2<f32>>,
+        groups: &CollisionGroups,
+    ) -> Vec<&CollisionObject> {
+        let mut objects = self.grid
+            .query(aabb)
+            .into_iter()
+            .map(|entity| &self.objects[&entity])
+            .filter(|object| object.groups.can_interact_with_groups(groups))
+            .collect::<Vec<_>>();
+
+        // The grid returns entities in the order of their indices, which differs between the
+        // server and the clients
+        objects.sort_by_key(|object| object.order_key);
+        objects
+    }
+
+    /// Objects whose shape contains the given point.
+    pub fn interferences_with_point(
+        &self,
+        point: &Point2<f32>,
+        groups: &CollisionGroups,
+    ) -> Vec<&CollisionObject> {
+        let aabb = AABB::new(*point, *point);
+
+        self.interferences_with_aabb(&aabb, groups)
+            .into_iter()
+            .filter(|object| {
+                object
+                    .shape
+                    .as_point_query()
+                    .map_or(false, |query| query.contains_point(&object.position, point))
+            })
+            .collect()
+    }
+
+    /// Objects that are hit by a ray within `max_toi`, together with the intersection.
+    pub fn interferences_with_ray(
+        &self,
+        ray: &Ray<Point2<f32>>,
+        max_toi: f32,
+        groups: &CollisionGroups,
+    ) -> Vec<(&CollisionObject, RayIntersection<Vector2<f32>>)> {
+        // There is nothing to hit beyond the bounds of all objects
+        let max_toi = match self.grid.bounds() {
+            Some(bounds) => {
+                let corners = [
+                    *bounds.mins(),
+                    *bounds.maxs(),
+                    Point2::new(bounds.mins().x, bounds.maxs().y),
+                    Point2::new(bounds.maxs().x, bounds.mins().y),
+                ];
+                let ray_dir_length = nalgebra::norm(&ray.dir);
+                let distance = corners
+                    .iter()
+                    .map(|corner| nalgebra::distance(&ray.origin, corner))
+                    .fold(0.0, f32::max);
+
+                max_toi.min(distance / ray_dir_length)
+            }
+            None => return Vec::new(),
+        };
+
+        let end = ray.origin + ray.dir * max_toi;
+        let aabb = AABB::new(
+            Point2::new(ray.origin.x.min(end.x), ray.origin.y.min(end.y)),
+            Point2::new(ray.origin.x.max(end.x), ray.origin.y.max(end.y)),
+        );
+
+        self.interferences_with_aabb(&aabb, groups)
+            .into_iter()
+            .filter_map(|object| {
+                let intersection = object
+                    .shape
+                    .as_ray_cast()?
+                    .toi_and_normal_with_ray(&object.position, ray, true)?;
+
+                if intersection.toi <= max_toi {
+                    Some((object, intersection))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Pairs of objects whose bounding boxes overlap and whose groups can interact, where at least
+    /// one of the objects belongs to the given entities. The pairs are ordered by entity, and
+    /// each pair is ordered as well.
+    pub fn candidate_pairs(&self, entities: &[Entity]) -> Vec<(Entity, Entity)> {
+        let mut pairs = BTreeSet::new();
+
+        for &entity in entities {
+            let object = match self.objects.get(&entity) {
+                Some(object) => object,
+                None => continue,
+            };
+            let aabb = match self.grid.aabb(entity) {
+                Some(aabb) => aabb,
+                None => continue,
+            };
+
+            for other in self.interferences_with_aabb(aabb, &object.groups) {
+                if other.entity != entity {
+                    pairs.insert((entity.min(other.entity), entity.max(other.entity)));
+                }
+            }
+        }
+
+        pairs.into_iter().collect()
+    }
+
+    /// Run the narrow phase for a pair of objects, adding the contacts to `out`. The contacts'
+    /// normals point from `entity_a` to `entity_b`.
+    pub fn contacts(
+        &self,
+        entity_a: Entity,
+        entity_b: Entity,
+        out: &mut Vec<Contact<Point2<f32>>>,
+    ) {
+        let (a, b) = match (self.objects.get(&entity_a), self.objects.get(&entity_b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+
+        let algorithm = self.contact_dispatcher
+            .get_contact_algorithm(&*a.shape, &*b.shape);
+
+        if let Some(mut algorithm) = algorithm {
+            let updated = algorithm.update(
+                &self.contact_dispatcher,
+                &a.position,
+                &*a.shape,
+                &b.position,
+                &*b.shape,
+                a.prediction() + b.prediction(),
+            );
+
+            if updated {
+                algorithm.contacts(out);
+            }
+        }
+    }
+}
 
 /// System for running the collision pipeline.
 pub struct UpdateSys {
@@ -95,21 +452,18 @@ impl UpdateSys {
 impl<'a> System<'a> for UpdateSys {
     type SystemData = (
         FetchMut<'a, CollisionWorld>,
+        Entities<'a>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, Orientation>,
-        ReadStorage<'a, ObjectHandle>,
+        ReadStorage<'a, InWorld>,
     );
 
     fn run(
         &mut self,
-        (mut collision_world, position, orientation, object_handle): Self::SystemData,
+        (mut collision_world, entities, position, orientation, in_world): Self::SystemData,
     ) {
         profile!("collision update");
 
-        // HACK: Force update of proximites (needed only for client)
-        collision_world.register_broad_phase_pair_filter("lol", CollisionGroupsPairFilter::new());
-        collision_world.unregister_broad_phase_pair_filter("lol");
-
         // Update isometry of entities that have moved or rotated
         position.populate_modified(&mut self.modified_position_id, &mut self.modified_position);
         orientation.populate_modified(
@@ -120,15 +474,13 @@ impl<'a> System<'a> for UpdateSys {
         {
             let modified = &self.modified_position | &self.modified_orientation;
 
-            for (_, position, orientation, object_handle) in
-                (&modified, &position, &orientation, &object_handle).join()
+            for (entity, _, position, orientation, _) in
+                (&*entities, &modified, &position, &orientation, &in_world).join()
             {
                 let isometry = Isometry2::new(position.0.coords, orientation.0);
-                collision_world.set_position(object_handle.0, isometry);
+                collision_world.set_position(entity, isometry);
             }
         }
-
-        collision_world.update();
     }
 }
 
@@ -140,13 +492,14 @@ pub struct MaintainSys;
 pub struct MaintainData<'a> {
     entities: Entities<'a>,
 
+    repl_id: ReadStorage<'a, repl::Id>,
     active: ReadStorage<'a, entity::Active>,
     position: ReadStorage<'a, Position>,
     orientation: ReadStorage<'a, Orientation>,
     shape: ReadStorage<'a, Shape>,
     object: ReadStorage<'a, Object>,
 
-    object_handle: WriteStorage<'a, ObjectHandle>,
+    in_world: WriteStorage<'a, InWorld>,
 }
 
 impl<'a> System<'a> for MaintainSys {
@@ -157,40 +510,41 @@ impl<'a> System<'a> for MaintainSys {
         profile!("collision maintain");
 
         // Create newly active entities in collision world
-        let new_handles = (
+        let new_entities = (
             &*data.entities,
             &data.active,
             &data.position,
             &data.orientation,
             &data.shape,
             &data.object,
-            !&data.object_handle,
+            !&data.in_world,
         ).join()
             .map(
                 |(entity, _active, position, orientation, shape, object, _)| {
                     let isometry = Isometry2::new(position.0.coords, orientation.0);
-                    let handle = collision_world.add(
+                    collision_world.add(
+                        entity,
+                        order_key(&data.repl_id, entity),
                         isometry,
                         shape.0.clone(),
                         object.groups,
                         object.query_type,
-                        entity,
                     );
 
-                    (entity, handle)
+                    entity
                 },
             )
             .collect::<Vec<_>>();
 
-        for &(entity, handle) in &new_handles {
-            data.object_handle.insert(entity, ObjectHandle(handle));
+        for entity in new_entities {
+            data.in_world.insert(entity, InWorld);
         }
 
         for (entity, _active, _, _) in (
             &*data.entities,
             &data.active,
             &data.object,
-            !&data.object_handle,
+            !&data.in_world,
         ).join()
         {
             panic!(
@@ -200,16 +554,16 @@ impl<'a> System<'a> for MaintainSys {
         }
 
         // Remove newly inactive entities from collision world
-        let removed_handles = (&*data.entities, !&data.active, &data.object_handle)
+        let removed_entities = (&*data.entities, !&data.active, &data.in_world)
             .join()
-            .map(|(entity, _, object_handle)| {
-                collision_world.remove(&[object_handle.0]);
+            .map(|(entity, _, _)| {
+                collision_world.remove(entity);
                 entity
             })
             .collect::<Vec<_>>();
 
-        for entity in removed_handles {
-            data.object_handle.remove(entity);
+        for entity in removed_entities {
+            data.in_world.remove(entity);
         }
     }
 }
@@ -222,23 +576,20 @@ impl<'a> System<'a> for RemovalSys {
         Entities<'a>,
         FetchMut<'a, CollisionWorld>,
         ReadStorage<'a, entity::Remove>,
-        WriteStorage<'a, ObjectHandle>,
+        WriteStorage<'a, InWorld>,
     );
 
-    fn run(
-        &mut self,
-        (entities, mut collision_world, remove, mut object_handle): Self::SystemData,
-    ) {
-        let removed_entities = (&*entities, &remove, &mut object_handle)
+    fn run(&mut self, (entities, mut collision_world, remove, mut in_world): Self::SystemData) {
+        let removed_entities = (&*entities, &remove, &in_world)
             .join()
-            .map(|(entity, _, object_handle)| {
-                collision_world.remove(&[object_handle.0]);
+            .map(|(entity, _, _)| {
+                collision_world.remove(entity);
                 entity
             })
             .collect::<Vec<_>>();
 
         for entity in removed_entities {
-            object_handle.remove(entity);
+            in_world.remove(entity);
         }
     }
 }
@@ -267,15 +618,20 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
         a: &shape::Shape<P, M>,
         b: &shape::Shape<P, M>,
     ) -> Option<ContactAlgorithm<P, M>> {
-        let a_is_ball = a.is_shape::<Ball<P::Real>>();
-        let b_is_ball = b.is_shape::<Ball<P::Real>>();
+        let a_is_ball = a.is_shape::<shape::Ball<P::Real>>();
+        let b_is_ball = b.is_shape::<shape::Ball<P::Real>>();
+
+        // Contact manifolds are generated by perturbing the shapes, which only makes sense for
+        // shapes with flat sides. Round shapes touch in a single point.
+        let a_is_round = a_is_ball || a.is_shape::<shape::Capsule<P::Real>>();
+        let b_is_round = b_is_ball || b.is_shape::<shape::Capsule<P::Real>>();
 
         if a_is_ball && b_is_ball {
             Some(Box::new(BallBallContactGenerator::<P, M>::new()))
         } else if a.is_shape::<Plane<P::Vector>>() && b.is_support_map() {
             let wo_manifold = PlaneSupportMapContactGenerator::<P, M>::new();
 
-            if !b_is_ball {
+            if !b_is_round {
                 let mut manifold = OneShotContactManifoldGenerator::new(wo_manifold);
                 manifold.set_always_one_shot(true);
                 Some(Box::new(manifold))
@@ -285,7 +641,7 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
         } else if b.is_shape::<Plane<P::Vector>>() && a.is_support_map() {
             let wo_manifold = SupportMapPlaneContactGenerator::<P, M>::new();
 
-            if !a_is_ball {
+            if !a_is_round {
                 let mut manifold = OneShotContactManifoldGenerator::new(wo_manifold);
                 manifold.set_always_one_shot(true);
                 Some(Box::new(manifold))
@@ -298,7 +654,7 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
                     let simplex = VoronoiSimplex2::new();
                     let wo_manifold = SupportMapSupportMapContactGenerator::new(simplex);
 
-                    if !a_is_ball && !b_is_ball {
+                    if !a_is_round && !b_is_round {
                         let mut manifold = OneShotContactManifoldGenerator::new(wo_manifold);
                         manifold.set_always_one_shot(true);
                         Some(Box::new(manifold))
@@ -310,7 +666,7 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
                     let simplex = VoronoiSimplex3::new();
                     let wo_manifold = SupportMapSupportMapContactGenerator::new(simplex);
 
-                    if !a_is_ball && !b_is_ball {
+                    if !a_is_round && !b_is_round {
                         let mut manifold = OneShotContactManifoldGenerator::new(wo_manifold);
                         manifold.set_always_one_shot(true);
                         Some(Box::new(manifold))
@@ -323,7 +679,7 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
                     let wo_manifold = SupportMapSupportMapContactGenerator::new(simplex);
 
                     if false {
-                        // !a_is_ball && !b_is_ball {
+                        // !a_is_round && !b_is_round {
                         let mut manifold = OneShotContactManifoldGenerator::new(wo_manifold);
                         manifold.set_always_one_shot(true);
                         Some(Box::new(manifold))
@@ -341,3 +697,62 @@ impl<P: Point, M: Isometry<P>> ContactDispatcher<P, M> for StatelessContactDispa
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Isometry2, Point2, Vector2};
+
+    use super::Shape;
+
+    #[test]
+    fn test_convex_polygon() {
+        let square = [
+            Point2::new(0.0, 0.0),
+            Point2::new(1.0, 0.0),
+            Point2::new(1.0, 1.0),
+            Point2::new(0.0, 1.0),
+        ];
+        let mut clockwise = square.to_vec();
+        clockwise.reverse();
+        let concave = [
+            Point2::new(0.0, 0.0),
+            Point2::new(1.0, 0.0),
+            Point2::new(0.5, 0.5),
+            Point2::new(1.0, 1.0),
+            Point2::new(0.0, 1.0),
+        ];
+        let pentagram = (0..5)
+            .map(|i| {
+                let angle = i as f32 * 4.0 * ::std::f32::consts::PI / 5.0;
+                Point2::new(angle.cos(), angle.sin())
+            })
+            .collect::<Vec<_>>();
+
+        assert!(Shape::convex_polygon(&square).is_some());
+        assert!(Shape::convex_polygon(&clockwise).is_some());
+        assert!(Shape::convex_polygon(&concave).is_none());
+        assert!(Shape::convex_polygon(&pentagram).is_none());
+        assert!(Shape::convex_polygon(&square[..2]).is_none());
+
+        // Shapes are centered on the centroid of their area
+        let aabb = |points: &[Point2<f32>]| {
+            let shape = Shape::convex_polygon(points).unwrap();
+            let aabb = shape.0.aabb(&Isometry2::identity());
+            (*aabb.mins(), *aabb.maxs())
+        };
+        let offset = square
+            .iter()
+            .map(|&p| p + Vector2::new(10.0, -5.0))
+            .collect::<Vec<_>>();
+        let triangle = [
+            Point2::new(0.0, 0.0),
+            Point2::new(3.0, 0.0),
+            Point2::new(0.0, 6.0),
+        ];
+
+        assert_eq!(aabb(&square), (Point2::new(-0.5, -0.5), Point2::new(0.5, 0.5)));
+        assert_eq!(aabb(&offset), aabb(&square));
+        assert_eq!(aabb(&clockwise), aabb(&square));
+        assert_eq!(aabb(&triangle), (Point2::new(-1.0, -2.0), Point2::new(2.0, 4.0)));
+    }
+}
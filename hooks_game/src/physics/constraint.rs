@@ -9,9 +9,9 @@ use std::ops::Deref;
 use specs::prelude::*;
 use specs::storage::MaskedStorage;
 
-use nalgebra::{dot, norm, Matrix2x6, Point2, Rotation2, RowVector6, Vector2};
+use nalgebra::{dot, norm, zero, Matrix2x6, Point2, Rotation2, RowVector6, Vector2};
 
-use physics::{Orientation, Position};
+use physics::{Material, Orientation, Position};
 use repl;
 
 /// A `Pose` described the physical state of one entity relevant to constraint solving.
@@ -70,6 +70,33 @@ pub enum Def {
     Angle {
         angle: f32,
     },
+    /// Like a `Joint`, but only keeps the two points from being further apart than
+    /// `max_distance`.
+    Rope {
+        max_distance: f32,
+
+        /// Object-space coordinates.
+        object_pos_a: Point2<f32>,
+
+        /// Object-space coordinates.
+        object_pos_b: Point2<f32>,
+    },
+    /// Pulls two points towards a rest distance. Springs should be given a positive compliance in
+    /// their `Constraint`, otherwise they behave like a rigid `Joint`.
+    Spring {
+        rest_length: f32,
+
+        /// Object-space coordinates.
+        object_pos_a: Point2<f32>,
+
+        /// Object-space coordinates.
+        object_pos_b: Point2<f32>,
+    },
+    /// Keeps the angle of `a` relative to `b` within `[min, max]`.
+    AngleLimit {
+        min: f32,
+        max: f32,
+    },
     Sum(Box<Def>, Box<Def>),
 }
 
@@ -114,7 +141,16 @@ impl Vars {
 #[derive(Clone, Debug)]
 pub struct Constraint {
     pub def: Def,
-    pub stiffness: f32,
+
+    /// Inverse stiffness, as used in XPBD. Zero gives a rigid constraint. Since compliance is
+    /// scaled by the time step, the resulting stiffness does not depend on the tick rate or on
+    /// the number of solver iterations.
+    pub compliance: f32,
+
+    /// Damping of the velocity along the constraint. Only has an effect for compliant
+    /// constraints.
+    pub damping: f32,
+
     pub entity_a: Entity,
     pub entity_b: Entity,
     pub vars_a: Vars,
@@ -122,6 +158,19 @@ pub struct Constraint {
 }
 
 impl Mass {
+    /// Mass of a body that is not moved by constraints, e.g. a wall or an entity that is not
+    /// being simulated.
+    pub fn kinematic() -> Mass {
+        Mass {
+            inv: 0.0,
+            inv_angular: 0.0,
+        }
+    }
+
+    pub fn is_kinematic(&self) -> bool {
+        self.inv == 0.0 && self.inv_angular == 0.0
+    }
+
     /// Set inverse mass to zero for elements that should not change.
     pub fn zero_out_constants(&self, vars: &Vars) -> Mass {
         Mass {
@@ -248,6 +297,41 @@ impl Def {
 
                 (-value, -jacobian)
             }
+            Def::Rope {
+                max_distance,
+                object_pos_a,
+                object_pos_b,
+            } => {
+                let joint = Def::Joint {
+                    distance: max_distance,
+                    object_pos_a,
+                    object_pos_b,
+                };
+                let (value, jacobian) = joint.calculate(x_a, x_b);
+
+                (-value, -jacobian)
+            }
+            Def::Spring {
+                rest_length,
+                object_pos_a,
+                object_pos_b,
+            } => Def::Joint {
+                distance: rest_length,
+                object_pos_a,
+                object_pos_b,
+            }.calculate(x_a, x_b),
+            Def::AngleLimit { min, max } => {
+                let angle = normalize_angle(x_a.angle - x_b.angle);
+                let jacobian = RowVector6::new(0.0, 0.0, 1.0, 0.0, 0.0, -1.0);
+
+                if angle < min {
+                    (angle - min, jacobian)
+                } else if angle > max {
+                    (max - angle, -jacobian)
+                } else {
+                    (0.0, jacobian)
+                }
+            }
             Def::Sum(ref k1, ref k2) => {
                 let (value_1, jacobian_1) = k1.calculate(x_a, x_b);
                 let (value_2, jacobian_2) = k2.calculate(x_a, x_b);
@@ -263,32 +347,65 @@ impl Def {
             Def::Joint { .. } => false,
             Def::Angle { .. } => false,
             Def::Contact { .. } => true,
+            Def::Rope { .. } => true,
+            Def::Spring { .. } => false,
+            Def::AngleLimit { .. } => true,
             Def::Sum(_, _) => false,
         }
     }
+
+    /// Name of the kind of constraint, for debugging output.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Def::Joint { .. } => "Joint",
+            Def::Contact { .. } => "Contact",
+            Def::Angle { .. } => "Angle",
+            Def::Rope { .. } => "Rope",
+            Def::Spring { .. } => "Spring",
+            Def::AngleLimit { .. } => "AngleLimit",
+            Def::Sum(_, _) => "Sum",
+        }
+    }
+}
+
+/// The state of one of the two bodies of a constraint, as given to the solver.
+#[derive(Clone, Debug)]
+pub struct Body {
+    pub pose: Pose,
+
+    /// Pose at the start of the time step. This is used for damping.
+    pub prev_pose: Pose,
+
+    pub mass: Mass,
 }
 
-/// Solve for the velocity update of one constraint.
+/// Solve for the position update of one constraint in one solver iteration. The correction is
+/// distributed among the two bodies according to their inverse masses, so a body with zero
+/// inverse mass does not move.
+///
+/// `lambda` is the constraint's accumulated Lagrange multiplier. It needs to start at zero in
+/// every time step and is then updated in each iteration.
 pub fn solve_for_position(
     constraint: &Def,
-    stiffness: f32,
-    x_a: &Pose,
-    x_b: &Pose,
-    m_a: &Mass,
-    m_b: &Mass,
+    compliance: f32,
+    damping: f32,
+    dt: f32,
+    lambda: &mut f32,
+    a: &Body,
+    b: &Body,
 ) -> (Pose, Pose) {
     let inv_m = RowVector6::new(
-        m_a.inv,
-        m_a.inv,
-        m_a.inv_angular,
-        m_b.inv,
-        m_b.inv,
-        m_b.inv_angular,
+        a.mass.inv,
+        a.mass.inv,
+        a.mass.inv_angular,
+        b.mass.inv,
+        b.mass.inv,
+        b.mass.inv_angular,
     );
-    let (value, jacobian) = constraint.calculate(x_a, x_b);
+    let (value, jacobian) = constraint.calculate(&a.pose, &b.pose);
 
     if (constraint.is_inequality() && value >= 0.0) || value.abs() <= 1e-9 {
-        return (x_a.clone(), x_b.clone());
+        return (a.pose.clone(), b.pose.clone());
     }
 
     let denom = dot(&jacobian.component_mul(&inv_m), &jacobian);
@@ -296,21 +413,399 @@ pub fn solve_for_position(
     if denom <= 1e-9 {
         // TODO: No idea
         debug!("small denom");
-        return (x_a.clone(), x_b.clone());
+        return (a.pose.clone(), b.pose.clone());
     }
 
-    let lambda = value / denom;
+    // Time step scaled compliance and damping, see "XPBD: Position-Based Simulation of Compliant
+    // Constrained Dynamics" by Macklin et al.
+    let alpha = compliance / (dt * dt);
+    let gamma = compliance * damping / dt;
+
+    let motion = RowVector6::new(
+        a.pose.pos.x - a.prev_pose.pos.x,
+        a.pose.pos.y - a.prev_pose.pos.y,
+        normalize_angle(a.pose.angle - a.prev_pose.angle),
+        b.pose.pos.x - b.prev_pose.pos.x,
+        b.pose.pos.y - b.prev_pose.pos.y,
+        normalize_angle(b.pose.angle - b.prev_pose.angle),
+    );
 
-    let delta = -lambda * stiffness * jacobian.component_mul(&inv_m).transpose();
+    let delta_lambda = (-value - alpha * *lambda - gamma * dot(&jacobian, &motion)) /
+        ((1.0 + gamma) * denom + alpha);
+    *lambda += delta_lambda;
+
+    let delta = delta_lambda * jacobian.component_mul(&inv_m).transpose();
 
     (
         Pose {
-            pos: x_a.pos + Vector2::new(delta.x, delta.y),
-            angle: x_a.angle + delta.z,
+            pos: a.pose.pos + Vector2::new(delta.x, delta.y),
+            angle: a.pose.angle + delta.z,
         },
         Pose {
-            pos: x_b.pos + Vector2::new(delta.w, delta.a),
-            angle: x_b.angle + delta.b,
+            pos: b.pose.pos + Vector2::new(delta.w, delta.a),
+            angle: b.pose.angle + delta.b,
         },
     )
 }
+
+/// Contacts that approach slower than this are not bounced, so that resting bodies do not jitter.
+const MIN_RESTITUTION_SPEED: f32 = 10.0;
+
+/// Surface properties of a contact between two entities.
+#[derive(Clone, Debug)]
+pub struct ContactMaterial {
+    pub restitution: f32,
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+}
+
+impl ContactMaterial {
+    /// Combine the materials of two entities, taking the larger restitution and the geometric mean
+    /// of the frictions. An entity without a material takes on the material of the other one.
+    /// Returns `None` if neither entity has a material.
+    pub fn combine(a: Option<&Material>, b: Option<&Material>) -> Option<ContactMaterial> {
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(a), None) => (a, a),
+            (None, Some(b)) => (b, b),
+            (None, None) => return None,
+        };
+
+        Some(ContactMaterial {
+            restitution: a.restitution.max(b.restitution),
+            static_friction: (a.static_friction * b.static_friction).sqrt(),
+            dynamic_friction: (a.dynamic_friction * b.dynamic_friction).sqrt(),
+        })
+    }
+}
+
+/// Linear and angular velocity of one of the two bodies of a contact.
+#[derive(Clone, Debug)]
+pub struct BodyVelocity {
+    pub linear: Vector2<f32>,
+    pub angular: f32,
+}
+
+impl BodyVelocity {
+    /// Velocity of a point at world-space offset `r` from the body's center.
+    fn at(&self, r: &Vector2<f32>) -> Vector2<f32> {
+        self.linear + Vector2::new(-r.y, r.x) * self.angular
+    }
+}
+
+/// Inverse mass of a body as seen when pushing at world-space offset `r` in direction `dir`.
+fn generalized_inv_mass(mass: &Mass, r: &Vector2<f32>, dir: &Vector2<f32>) -> f32 {
+    mass.inv + mass.inv_angular * r.perp(dir).powi(2)
+}
+
+fn contact_points(def: &Def) -> Option<(Vector2<f32>, Point2<f32>, Point2<f32>)> {
+    match *def {
+        Def::Contact {
+            normal,
+            object_pos_a,
+            object_pos_b,
+            ..
+        } => Some((normal, object_pos_a, object_pos_b)),
+        _ => None,
+    }
+}
+
+/// Keep the contact points of two touching bodies from sliding against each other in this time
+/// step, if static friction is strong enough to hold them. `lambda` is the contact's accumulated
+/// Lagrange multiplier, as given by `solve_for_position`. Returns `None` if `def` is not a
+/// contact or if the bodies are sliding, leaving them to `solve_contact_velocity`.
+///
+/// See "Detailed Rigid Body Simulation with Extended Position Based Dynamics" by Müller et al.
+pub fn solve_static_friction(
+    def: &Def,
+    static_friction: f32,
+    lambda: f32,
+    a: &Body,
+    b: &Body,
+) -> Option<(Pose, Pose)> {
+    let (normal, object_pos_a, object_pos_b) = contact_points(def)?;
+
+    let r_a = Rotation2::new(a.pose.angle) * object_pos_a.coords;
+    let r_b = Rotation2::new(b.pose.angle) * object_pos_b.coords;
+    let prev_r_a = Rotation2::new(a.prev_pose.angle) * object_pos_a.coords;
+    let prev_r_b = Rotation2::new(b.prev_pose.angle) * object_pos_b.coords;
+
+    // Relative motion of the contact points in this time step
+    let motion = (a.pose.pos.coords + r_a - a.prev_pose.pos.coords - prev_r_a) -
+        (b.pose.pos.coords + r_b - b.prev_pose.pos.coords - prev_r_b);
+    let tangential = motion - normal * dot(&motion, &normal);
+    let sliding = norm(&tangential);
+
+    if sliding <= 1e-9 {
+        return None;
+    }
+
+    let dir = tangential / sliding;
+    let denom =
+        generalized_inv_mass(&a.mass, &r_a, &dir) + generalized_inv_mass(&b.mass, &r_b, &dir);
+
+    if denom <= 1e-9 {
+        return None;
+    }
+
+    let delta_lambda = sliding / denom;
+
+    if delta_lambda >= static_friction * lambda.abs() {
+        return None;
+    }
+
+    let p = -dir * delta_lambda;
+
+    Some((
+        Pose {
+            pos: a.pose.pos + p * a.mass.inv,
+            angle: a.pose.angle + a.mass.inv_angular * r_a.perp(&p),
+        },
+        Pose {
+            pos: b.pose.pos - p * b.mass.inv,
+            angle: b.pose.angle - b.mass.inv_angular * r_b.perp(&p),
+        },
+    ))
+}
+
+/// Apply dynamic friction and restitution to the velocities of two touching bodies, after their
+/// velocities have been derived from the solved positions. `old_vel_a` and `old_vel_b` are the
+/// velocities at the start of the time step, which determine how strongly the bodies bounce.
+/// Returns `None` if `def` is not a contact.
+pub fn solve_contact_velocity(
+    def: &Def,
+    material: &ContactMaterial,
+    lambda: f32,
+    dt: f32,
+    a: (&Pose, &Mass, &BodyVelocity, &BodyVelocity),
+    b: (&Pose, &Mass, &BodyVelocity, &BodyVelocity),
+) -> Option<(BodyVelocity, BodyVelocity)> {
+    let (normal, object_pos_a, object_pos_b) = contact_points(def)?;
+    let (pose_a, mass_a, vel_a, old_vel_a) = a;
+    let (pose_b, mass_b, vel_b, old_vel_b) = b;
+
+    let r_a = Rotation2::new(pose_a.angle) * object_pos_a.coords;
+    let r_b = Rotation2::new(pose_b.angle) * object_pos_b.coords;
+
+    // The normal points from `a` to `b`, so positive normal velocities are approaching
+    let v = vel_a.at(&r_a) - vel_b.at(&r_b);
+    let v_n = dot(&v, &normal);
+    let v_t = v - normal * v_n;
+    let speed_t = norm(&v_t);
+
+    let mut delta_v: Vector2<f32> = zero();
+
+    // Dynamic friction is bounded by the normal force `lambda / dt^2`
+    if speed_t > 1e-9 {
+        delta_v -= v_t / speed_t * (material.dynamic_friction * lambda.abs() / dt).min(speed_t);
+    }
+
+    let old_v_n = dot(&(old_vel_a.at(&r_a) - old_vel_b.at(&r_b)), &normal);
+    if old_v_n > MIN_RESTITUTION_SPEED {
+        let target = -material.restitution * old_v_n;
+
+        if v_n > target {
+            delta_v += normal * (target - v_n);
+        }
+    }
+
+    let change = norm(&delta_v);
+    if change <= 1e-9 {
+        return None;
+    }
+
+    let dir = delta_v / change;
+    let denom =
+        generalized_inv_mass(mass_a, &r_a, &dir) + generalized_inv_mass(mass_b, &r_b, &dir);
+
+    if denom <= 1e-9 {
+        return None;
+    }
+
+    let p = delta_v / denom;
+
+    Some((
+        BodyVelocity {
+            linear: vel_a.linear + p * mass_a.inv,
+            angular: vel_a.angular + mass_a.inv_angular * r_a.perp(&p),
+        },
+        BodyVelocity {
+            linear: vel_b.linear - p * mass_b.inv,
+            angular: vel_b.angular - mass_b.inv_angular * r_b.perp(&p),
+        },
+    ))
+}
+
+/// Map an angle into `[-pi, pi]`.
+fn normalize_angle(angle: f32) -> f32 {
+    angle.sin().atan2(angle.cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point2, Vector2};
+
+    use physics::Material;
+
+    use super::{solve_contact_velocity, solve_for_position, Body, BodyVelocity, ContactMaterial,
+                Def, Mass, Pose};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn body(y: f32, mass: &Mass) -> Body {
+        let pose = Pose {
+            pos: Point2::new(0.0, y),
+            angle: 0.0,
+        };
+
+        Body {
+            pose: pose.clone(),
+            prev_pose: pose,
+            mass: mass.clone(),
+        }
+    }
+
+    fn solve(def: &Def, compliance: f32, a: &Body, b: &Body) -> (Pose, Pose) {
+        let mut lambda = 0.0;
+        solve_for_position(def, compliance, 0.0, DT, &mut lambda, a, b)
+    }
+
+    fn overlap(m_a: &Mass, m_b: &Mass) -> (Pose, Pose) {
+        // Two bodies at the same position, where the top of `a` penetrates the bottom of `b` by
+        // 10 units
+        let def = Def::Contact {
+            normal: Vector2::new(0.0, 1.0),
+            margin: 0.0,
+            object_pos_a: Point2::new(0.0, 5.0),
+            object_pos_b: Point2::new(0.0, -5.0),
+        };
+
+        solve(&def, 0.0, &body(0.0, m_a), &body(0.0, m_b))
+    }
+
+    fn light() -> Mass {
+        Mass {
+            inv: 1.0,
+            inv_angular: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_contact_mass_split() {
+        let light = light();
+        let heavy = Mass {
+            inv: 1.0 / 3.0,
+            inv_angular: 0.0,
+        };
+
+        let (a, b) = overlap(&light, &light);
+        assert!((a.pos.y + 5.0).abs() < 1e-4);
+        assert!((b.pos.y - 5.0).abs() < 1e-4);
+
+        let (a, b) = overlap(&light, &heavy);
+        assert!((a.pos.y + 7.5).abs() < 1e-4);
+        assert!((b.pos.y - 2.5).abs() < 1e-4);
+
+        let (a, b) = overlap(&light, &Mass::kinematic());
+        assert!((a.pos.y + 10.0).abs() < 1e-4);
+        assert!(b.pos.y == 0.0);
+    }
+
+    #[test]
+    fn test_rope() {
+        let def = Def::Rope {
+            max_distance: 10.0,
+            object_pos_a: Point2::origin(),
+            object_pos_b: Point2::origin(),
+        };
+
+        // Slack ropes do nothing
+        let (a, b) = solve(&def, 0.0, &body(0.0, &light()), &body(5.0, &light()));
+        assert!(a.pos.y == 0.0 && b.pos.y == 5.0);
+
+        let (a, b) = solve(&def, 0.0, &body(0.0, &light()), &body(20.0, &light()));
+        assert!((a.pos.y - 5.0).abs() < 1e-4);
+        assert!((b.pos.y - 15.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_compliance() {
+        let def = Def::Spring {
+            rest_length: 10.0,
+            object_pos_a: Point2::origin(),
+            object_pos_b: Point2::origin(),
+        };
+        let a = body(0.0, &light());
+        let b = body(20.0, &Mass::kinematic());
+
+        // One iteration with compliance `alpha / dt^2 = inv_mass` only goes halfway
+        let (rigid, _) = solve(&def, 0.0, &a, &b);
+        let (soft, _) = solve(&def, DT * DT, &a, &b);
+
+        assert!((rigid.pos.y - 10.0).abs() < 1e-4);
+        assert!((soft.pos.y - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_angle_limit() {
+        let def = Def::AngleLimit {
+            min: -0.5,
+            max: 0.5,
+        };
+        let mass = Mass {
+            inv: 0.0,
+            inv_angular: 1.0,
+        };
+        let mut a = body(0.0, &mass);
+        let b = body(0.0, &Mass::kinematic());
+
+        a.pose.angle = 0.25;
+        assert!(solve(&def, 0.0, &a, &b).0.angle == 0.25);
+
+        a.pose.angle = 1.0;
+        assert!((solve(&def, 0.0, &a, &b).0.angle - 0.5).abs() < 1e-4);
+
+        a.pose.angle = -1.0;
+        assert!((solve(&def, 0.0, &a, &b).0.angle + 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_restitution() {
+        let bouncy = Material {
+            restitution: 0.5,
+            ..Material::default()
+        };
+        let material = ContactMaterial::combine(Some(&bouncy), None).unwrap();
+        assert!(material.restitution == 0.5);
+
+        let def = Def::Contact {
+            normal: Vector2::new(0.0, 1.0),
+            margin: 0.0,
+            object_pos_a: Point2::new(0.0, 5.0),
+            object_pos_b: Point2::new(0.0, -5.0),
+        };
+        let a = body(0.0, &light());
+        let b = body(10.0, &Mass::kinematic());
+        let still = BodyVelocity {
+            linear: Vector2::new(0.0, 0.0),
+            angular: 0.0,
+        };
+        let approaching = BodyVelocity {
+            linear: Vector2::new(0.0, 100.0),
+            angular: 0.0,
+        };
+
+        // `a` ran into `b` and was stopped by the contact, now it bounces back at half the speed
+        let (vel_a, vel_b) = solve_contact_velocity(
+            &def,
+            &material,
+            1.0,
+            DT,
+            (&a.pose, &a.mass, &still, &approaching),
+            (&b.pose, &b.mass, &still, &still),
+        ).unwrap();
+
+        assert!((vel_a.linear.y + 50.0).abs() < 1e-4);
+        assert!(vel_b.linear.y == 0.0);
+    }
+}
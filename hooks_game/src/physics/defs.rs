@@ -4,15 +4,20 @@ use bit_manager::{BitRead, BitWrite, Result};
 use nalgebra::{Point2, Rotation2, Vector2};
 
 use specs::prelude::{Component, DenseVecStorage, FlaggedStorage, VecStorage};
-use specs::storage::NullStorage;
+use specs::storage::{BTreeStorage, NullStorage};
 
 use registry::Registry;
 use repl;
 use repl::interp::Interp;
+use save;
 
 pub fn register(reg: &mut Registry) {
     reg.component::<Update>();
     reg.component::<Dynamic>();
+    reg.component::<Ccd>();
+    reg.component::<Kinematic>();
+    reg.component::<KinematicPath>();
+    reg.saved_component::<KinematicPath>();
     reg.component::<InvMass>();
     reg.component::<InvAngularMass>();
     reg.component::<Position>();
@@ -21,6 +26,7 @@ pub fn register(reg: &mut Registry) {
     reg.component::<AngularVelocity>();
     reg.component::<Friction>();
     reg.component::<Drag>();
+    reg.component::<Material>();
     //reg.component::<Joints>();
 }
 
@@ -34,6 +40,42 @@ pub struct Update;
 #[storage(NullStorage)]
 pub struct Dynamic;
 
+/// Fast-moving entities that need continuous collision detection, so that they do not tunnel
+/// through thin objects in a single time step.
+#[derive(Component, PartialEq, Clone, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Ccd;
+
+/// Bodies that are moved by game logic rather than by forces and contacts, e.g. moving platforms.
+/// Their `Velocity` and `AngularVelocity` are integrated once per tick by
+/// `sim::Runner::run_kinematic`, both on the server and on predicting clients. They push dynamic
+/// bodies out of the way, but are never pushed themselves.
+#[derive(Component, PartialEq, Clone, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Kinematic;
+
+/// Waypoints that a `Kinematic` body follows with constant speed by setting its `Velocity`. After
+/// the last waypoint, the body heads back to the first one.
+#[derive(Component, PartialEq, Clone, Debug)]
+#[storage(BTreeStorage)]
+pub struct KinematicPath {
+    pub waypoints: Vec<Point2<f32>>,
+    pub speed: f32,
+
+    /// Index of the waypoint that the body is currently heading to.
+    pub next: usize,
+}
+
+impl KinematicPath {
+    pub fn new(waypoints: Vec<Point2<f32>>, speed: f32) -> KinematicPath {
+        KinematicPath {
+            waypoints,
+            speed,
+            next: 0,
+        }
+    }
+}
+
 /// Physical mass.
 #[derive(Component, PartialEq, Clone, Debug)]
 #[storage(VecStorage)]
@@ -108,6 +150,25 @@ pub struct Friction(pub f32);
 #[storage(DenseVecStorage)]
 pub struct Drag(pub f32);
 
+/// Surface properties of an entity. Contacts between two entities are affected only if at least
+/// one of them has a material; see `constraint::ContactMaterial` for how they are combined.
+#[derive(Component, PartialEq, Clone, Copy, Debug, Default)]
+#[storage(DenseVecStorage)]
+pub struct Material {
+    /// Bounciness, where zero stops the entity and one reflects it at the same speed.
+    pub restitution: f32,
+
+    /// Friction that keeps touching entities from starting to slide against each other.
+    pub static_friction: f32,
+
+    /// Friction that slows down entities that slide against each other.
+    pub dynamic_friction: f32,
+
+    /// Drag that slows down the rotation of this entity. Entities without a `Material` have their
+    /// rotation slowed down at a constant rate instead.
+    pub angular_drag: f32,
+}
+
 /// Transform from object-space to world-space.
 pub fn to_world_pos(
     position: &Position,
@@ -151,6 +212,39 @@ impl BitStore for Position {
     }
 }
 
+impl BitStore for KinematicPath {
+    fn read_from<R: BitRead>(reader: &mut R) -> Result<Self> {
+        let num_waypoints: u32 = reader.read()?;
+
+        let mut waypoints = Vec::new();
+        for _ in 0..num_waypoints {
+            waypoints.push(Point2::new(reader.read()?, reader.read()?));
+        }
+
+        let speed = reader.read()?;
+        let next: u32 = reader.read()?;
+
+        Ok(KinematicPath {
+            waypoints,
+            speed,
+            next: next as usize,
+        })
+    }
+
+    fn write_to<W: BitWrite>(&self, writer: &mut W) -> Result<()> {
+        writer.write(&(self.waypoints.len() as u32))?;
+        for waypoint in &self.waypoints {
+            writer.write(&waypoint.x)?;
+            writer.write(&waypoint.y)?;
+        }
+
+        writer.write(&self.speed)?;
+        writer.write(&(self.next as u32))
+    }
+}
+
+impl save::Component for KinematicPath {}
+
 impl repl::Component for Position {
     fn distance(&self, other: &Position) -> f32 {
         let d = self.0 - other.0;
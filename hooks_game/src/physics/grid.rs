@@ -0,0 +1,226 @@
+//! Uniform grid for finding the objects whose bounding boxes overlap some box. Every object is
+//! stored in all the cells that its bounding box touches, so that a query only needs to look at
+//! the cells that the query box touches.
+//!
+//! Objects that would cover a huge number of cells are kept in a separate list instead, which
+//! every query goes through. This keeps the grid from exploding if something is sent flying.
+
+use std::collections::{BTreeMap, HashMap};
+
+use nalgebra::Point2;
+use ncollide::bounding_volume::{BoundingVolume, AABB};
+use specs::prelude::Entity;
+
+/// Objects that cover more cells than this are not stored in cells.
+const MAX_CELLS_PER_OBJECT: i64 = 4096;
+
+/// Cell coordinates are clamped to this, so that they fit into an `i32`.
+const MAX_CELL_COORD: f32 = 1e9;
+
+type Cell = (i32, i32);
+
+/// The cells that a bounding box touches, inclusively.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+struct CellRange {
+    mins: Cell,
+    maxs: Cell,
+}
+
+impl CellRange {
+    fn num_cells(&self) -> i64 {
+        let width = self.maxs.0 as i64 - self.mins.0 as i64 + 1;
+        let height = self.maxs.1 as i64 - self.mins.1 as i64 + 1;
+        width * height
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        for x in self.mins.0..self.maxs.0 + 1 {
+            for y in self.mins.1..self.maxs.1 + 1 {
+                cells.push((x, y));
+            }
+        }
+        cells
+    }
+}
+
+struct Entry {
+    aabb: AABB<Point2<f32>>,
+
+    /// `None` if the object is too large to be stored in cells.
+    range: Option<CellRange>,
+}
+
+pub struct Grid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<Entity>>,
+    large: Vec<Entity>,
+    entries: BTreeMap<Entity, Entry>,
+}
+
+impl Grid {
+    pub fn new(cell_size: f32) -> Grid {
+        assert!(cell_size > 0.0);
+
+        Grid {
+            cell_size,
+            cells: HashMap::new(),
+            large: Vec::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    fn cell_coord(&self, x: f32) -> i32 {
+        let coord = (x / self.cell_size).floor();
+
+        if coord.is_nan() {
+            0
+        } else {
+            coord.max(-MAX_CELL_COORD).min(MAX_CELL_COORD) as i32
+        }
+    }
+
+    fn cell(&self, p: &Point2<f32>) -> Cell {
+        (self.cell_coord(p.x), self.cell_coord(p.y))
+    }
+
+    fn cell_range(&self, aabb: &AABB<Point2<f32>>) -> Option<CellRange> {
+        let range = CellRange {
+            mins: self.cell(aabb.mins()),
+            maxs: self.cell(aabb.maxs()),
+        };
+
+        if range.num_cells() <= MAX_CELLS_PER_OBJECT {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    /// Insert an object, or move it if it is already present.
+    pub fn set(&mut self, entity: Entity, aabb: AABB<Point2<f32>>) {
+        let range = self.cell_range(&aabb);
+
+        let unchanged = self.entries
+            .get(&entity)
+            .map_or(false, |entry| entry.range == range && range.is_some());
+
+        if !unchanged {
+            self.remove(entity);
+
+            match range {
+                Some(range) => {
+                    for cell in range.cells() {
+                        self.cells.entry(cell).or_insert_with(Vec::new).push(entity);
+                    }
+                }
+                None => self.large.push(entity),
+            }
+        }
+
+        self.entries.insert(entity, Entry { aabb, range });
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        let entry = match self.entries.remove(&entity) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        match entry.range {
+            Some(range) => {
+                for cell in range.cells() {
+                    let is_empty = match self.cells.get_mut(&cell) {
+                        Some(entities) => {
+                            entities.retain(|&e| e != entity);
+                            entities.is_empty()
+                        }
+                        None => false,
+                    };
+
+                    if is_empty {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+            None => self.large.retain(|&e| e != entity),
+        }
+    }
+
+    pub fn aabb(&self, entity: Entity) -> Option<&AABB<Point2<f32>>> {
+        self.entries.get(&entity).map(|entry| &entry.aabb)
+    }
+
+    /// Bounding box of all objects, if there are any.
+    pub fn bounds(&self) -> Option<AABB<Point2<f32>>> {
+        let mut entries = self.entries.values();
+        let first = entries.next()?.aabb.clone();
+
+        Some(entries.fold(first, |bounds, entry| bounds.merged(&entry.aabb)))
+    }
+
+    /// Return the objects whose bounding box intersects the given box, ordered by entity.
+    pub fn query(&self, aabb: &AABB<Point2<f32>>) -> Vec<Entity> {
+        let mut result = self.large.clone();
+
+        match self.cell_range(aabb) {
+            Some(range) => {
+                for cell in range.cells() {
+                    if let Some(entities) = self.cells.get(&cell) {
+                        result.extend(entities);
+                    }
+                }
+            }
+            None => {
+                // Huge query, so it is faster to just look at every object
+                result.extend(self.entries.keys());
+            }
+        }
+
+        result.sort();
+        result.dedup();
+        result.retain(|entity| self.entries[entity].aabb.intersects(aabb));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point2;
+    use ncollide::bounding_volume::AABB;
+    use specs::prelude::World;
+
+    use super::Grid;
+
+    fn aabb(x: f32, y: f32, w: f32, h: f32) -> AABB<Point2<f32>> {
+        AABB::new(Point2::new(x, y), Point2::new(x + w, y + h))
+    }
+
+    #[test]
+    fn test_query() {
+        let mut world = World::new();
+        let a = world.create_entity().build();
+        let b = world.create_entity().build();
+        let c = world.create_entity().build();
+
+        let mut grid = Grid::new(10.0);
+        grid.set(a, aabb(0.0, 0.0, 5.0, 5.0));
+        grid.set(b, aabb(-100.0, -5.0, 200.0, 10.0));
+        grid.set(c, aabb(1e9, 1e9, 1e9, 1e9));
+
+        assert_eq!(grid.query(&aabb(1.0, 1.0, 1.0, 1.0)), vec![a, b]);
+        assert_eq!(grid.query(&aabb(50.0, 50.0, 1.0, 1.0)), vec![]);
+        assert_eq!(grid.query(&aabb(1.5e9, 1.5e9, 1.0, 1.0)), vec![c]);
+
+        // Moving within the same cells and to other cells
+        grid.set(a, aabb(1.0, 1.0, 5.0, 5.0));
+        grid.set(b, aabb(40.0, 40.0, 20.0, 20.0));
+        assert_eq!(grid.query(&aabb(1.0, 1.0, 1.0, 1.0)), vec![a]);
+        assert_eq!(grid.query(&aabb(50.0, 50.0, 1.0, 1.0)), vec![b]);
+
+        grid.remove(a);
+        grid.remove(c);
+        assert_eq!(grid.query(&aabb(1.0, 1.0, 1.0, 1.0)), vec![]);
+        assert_eq!(grid.query(&aabb(1.5e9, 1.5e9, 1.0, 1.0)), vec![]);
+    }
+}
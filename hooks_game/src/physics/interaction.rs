@@ -17,20 +17,76 @@ pub fn register(reg: &mut Registry) {
     reg.resource(Handlers(BTreeMap::new()));
 }
 
-type Handler =
+pub type Handler =
     fn(&World, &EntityInfo, &EntityInfo, Point2<f32>, Vector2<f32>) -> Result<(), repl::Error>;
 
+pub type EndHandler = fn(&World, Entity, Entity) -> Result<(), repl::Error>;
+
+pub type SensorHandler = fn(&World, Entity, Entity, SensorPhase) -> Result<(), repl::Error>;
+
 /// An action that should be taken when two entities overlap in a physics prediction step.
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum Action {
-    /// Prevent these entities from overlapping.
+    /// Prevent these entities from overlapping. The correction is split between the entities by
+    /// their `InvMass`; entities that are not being simulated are not moved. The flags determine
+    /// whether the entities may also be rotated.
     PreventOverlap { rotate_a: bool, rotate_b: bool },
+
+    /// Only report when these entities start overlapping, keep overlapping and stop overlapping,
+    /// without resolving the overlap. Useful for e.g. capture zones and pickups.
+    Sensor,
+}
+
+/// Functions that are called for contacts between two entity classes. The entities are always
+/// given in the order of the classes in `set`.
+#[derive(Clone, Default)]
+pub struct Callbacks {
+    /// Called for every contact point in every physics step.
+    pub contact: Option<Handler>,
+
+    /// Called once when two entities start touching.
+    pub begin: Option<Handler>,
+
+    /// Called in every following physics step in which the entities are still touching.
+    pub persist: Option<Handler>,
+
+    /// Called once when the entities stop touching, or when one of them becomes inactive.
+    pub end: Option<EndHandler>,
+}
+
+impl Callbacks {
+    pub fn none() -> Callbacks {
+        Default::default()
+    }
+
+    pub fn contact(handler: Handler) -> Callbacks {
+        Callbacks {
+            contact: Some(handler),
+            ..Default::default()
+        }
+    }
+
+    /// Call the handler when two entities start touching and in every following step in which
+    /// they are still touching. Unlike `contact`, this is called once per step, not for every
+    /// contact point.
+    pub fn touching(handler: Handler) -> Callbacks {
+        Callbacks {
+            begin: Some(handler),
+            persist: Some(handler),
+            ..Default::default()
+        }
+    }
+
+    fn is_lifecycle(&self) -> bool {
+        self.begin.is_some() || self.persist.is_some() || self.end.is_some()
+    }
 }
 
 #[derive(Clone)]
 struct Def {
     action: Option<Action>,
-    handler: Option<Handler>,
+    callbacks: Callbacks,
+    sensor_handler: Option<SensorHandler>,
 }
 
 /// The information that is given for one entity when resolving interactions.
@@ -58,6 +114,40 @@ pub struct Event {
     pub normal: Vector2<f32>,
 }
 
+/// A change in the contact between two entities, as seen over multiple physics steps.
+#[derive(Clone, Debug)]
+pub enum ContactEvent {
+    /// The entities started touching. Contains the first contact of this step.
+    Begin(Event),
+
+    /// The entities are still touching. Contains the first contact of this step.
+    Persist(Event),
+
+    /// The entities stopped touching.
+    End(Entity, Entity),
+}
+
+/// Phase of an overlap between two entities that interact as `Action::Sensor`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SensorPhase {
+    /// The entities started to overlap in this physics step.
+    Enter,
+
+    /// The entities were overlapping before and still are.
+    Stay,
+
+    /// The entities stopped overlapping, or one of them became inactive.
+    Exit,
+}
+
+/// A change in the overlap of two sensor entities.
+#[derive(Clone, Debug)]
+pub struct SensorEvent {
+    pub a: Entity,
+    pub b: Entity,
+    pub phase: SensorPhase,
+}
+
 /// In a module's `register` function, it can happen that another entity class that it wants to
 /// interact with has not been registered yet. Thus, we store class names while registering, and
 /// only resolve to ids later in `setup`.
@@ -66,24 +156,62 @@ struct HandlersSetup(Vec<(String, String, Def)>);
 /// Resource to store the entity handlers for pairs of entity classes.
 pub struct Handlers(BTreeMap<OrderedPair<EntityClassId>, (EntityClassId, EntityClassId, Def)>);
 
+/// Define how two entity classes interact when they touch.
 pub fn set(
     reg: &mut Registry,
     entity_class_a: &str,
     entity_class_b: &str,
     action: Option<Action>,
-    handler: Option<Handler>,
+    callbacks: Callbacks,
+) {
+    let mut setup = reg.world().write_resource::<HandlersSetup>();
+    setup.0.push((
+        entity_class_a.to_string(),
+        entity_class_b.to_string(),
+        Def {
+            action,
+            callbacks,
+            sensor_handler: None,
+        },
+    ));
+}
+
+/// Make two entity classes interact as sensors. The handler is called with the entities in the
+/// order of the given classes.
+pub fn set_sensor(
+    reg: &mut Registry,
+    entity_class_a: &str,
+    entity_class_b: &str,
+    handler: SensorHandler,
 ) {
     let mut setup = reg.world().write_resource::<HandlersSetup>();
     setup.0.push((
         entity_class_a.to_string(),
         entity_class_b.to_string(),
-        Def { action, handler },
+        Def {
+            action: Some(Action::Sensor),
+            callbacks: Callbacks::none(),
+            sensor_handler: Some(handler),
+        },
     ));
 }
 
+/// Names of the entity class pairs that have been given an interaction. This only sees the
+/// interactions that have not been set up yet, so it is meant to be used while freezing the
+/// `Registry`.
+pub fn class_pairs(world: &World) -> Vec<(String, String)> {
+    world
+        .read_resource::<HandlersSetup>()
+        .0
+        .iter()
+        .map(|&(ref a, ref b, _)| (a.clone(), b.clone()))
+        .collect()
+}
+
 /// Create `Handlers` from `HandlersSetup` by mapping class names to ids. This should have an
-/// effect only once at the start of the game.
-fn setup(world: &World) {
+/// effect only once at the start of the game. `sim::Runner::new` calls this, so that the handlers
+/// are known in the first physics step already.
+pub fn setup(world: &World) {
     let mut setup = world.write_resource::<HandlersSetup>();
     let mut handlers = world.write_resource::<Handlers>();
 
@@ -113,9 +241,6 @@ pub fn get_action<D>(
 where
     D: Deref<Target = MaskedStorage<entity::Meta>>,
 {
-    // TODO: In a bit of an extreme edge case, we might have an interaction here, but not have
-    //       called `setup` yet.
-
     let id_a = meta.get(entity_a).unwrap().class_id;
     let id_b = meta.get(entity_b).unwrap().class_id;
     let id_pair = OrderedPair::new(id_a, id_b);
@@ -133,13 +258,35 @@ where
                         rotate_a: rotate_b,
                         rotate_b: rotate_a,
                     },
+                    Action::Sensor => Action::Sensor,
                 })
             }
         })
         .and_then(|x| x)
 }
 
-pub fn run(world: &World, event: &Event) -> Result<(), repl::Error> {
+/// Do we need to track contacts between these two entities across physics steps?
+pub fn has_lifecycle<D>(
+    handlers: &Fetch<Handlers>,
+    meta: &Storage<entity::Meta, D>,
+    entity_a: Entity,
+    entity_b: Entity,
+) -> bool
+where
+    D: Deref<Target = MaskedStorage<entity::Meta>>,
+{
+    let id_a = meta.get(entity_a).unwrap().class_id;
+    let id_b = meta.get(entity_b).unwrap().class_id;
+
+    handlers
+        .0
+        .get(&OrderedPair::new(id_a, id_b))
+        .map_or(false, |&(_, _, ref def)| def.callbacks.is_lifecycle())
+}
+
+/// Look up the interaction of two entities. The flag is true if the entities are given in the
+/// order that the interaction's handlers expect.
+fn get_def(world: &World, entity_a: Entity, entity_b: Entity) -> Option<(Def, bool)> {
     setup(world);
 
     let (id_a, id_b) = {
@@ -148,28 +295,88 @@ pub fn run(world: &World, event: &Event) -> Result<(), repl::Error> {
         // We assume here that every entity has an `entity::Meta` component, i.e. that it was
         // constructed by `entity::create`.
         (
-            meta.get(event.a.entity).unwrap().class_id,
-            meta.get(event.b.entity).unwrap().class_id,
+            meta.get(entity_a).unwrap().class_id,
+            meta.get(entity_b).unwrap().class_id,
         )
     };
     let id_pair = OrderedPair::new(id_a, id_b);
 
-    let def = world.read_resource::<Handlers>().0.get(&id_pair).cloned();
+    let handlers = world.read_resource::<Handlers>();
+
+    handlers
+        .0
+        .get(&id_pair)
+        .map(|&(handler_id_a, _, ref def)| (def.clone(), id_a == handler_id_a))
+}
 
-    if let Some((handler_id_a, handler_id_b, def)) = def {
-        assert!(id_pair == OrderedPair::new(handler_id_a, handler_id_b));
+fn call(world: &World, handler: Handler, event: &Event, in_order: bool) -> Result<(), repl::Error> {
+    // Make sure to pass the entities in the order in which the handler expects them
+    if in_order {
+        handler(world, &event.a, &event.b, event.pos, event.normal)
+    } else {
+        handler(world, &event.b, &event.a, event.pos, event.normal)
+    }
+}
 
-        if let Some(handler) = def.handler {
+pub fn run(world: &World, event: &Event) -> Result<(), repl::Error> {
+    match get_def(world, event.a.entity, event.b.entity) {
+        Some((
+            Def {
+                callbacks: Callbacks {
+                    contact: Some(handler),
+                    ..
+                },
+                ..
+            },
+            in_order,
+        )) => call(world, handler, event, in_order),
+        _ => Ok(()),
+    }
+}
+
+pub fn run_contact_event(world: &World, event: &ContactEvent) -> Result<(), repl::Error> {
+    match *event {
+        ContactEvent::Begin(ref event) => match get_def(world, event.a.entity, event.b.entity) {
+            Some((def, in_order)) => match def.callbacks.begin {
+                Some(handler) => call(world, handler, event, in_order),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        },
+        ContactEvent::Persist(ref event) => match get_def(world, event.a.entity, event.b.entity) {
+            Some((def, in_order)) => match def.callbacks.persist {
+                Some(handler) => call(world, handler, event, in_order),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        },
+        ContactEvent::End(a, b) => match get_def(world, a, b) {
+            Some((def, in_order)) => match def.callbacks.end {
+                Some(handler) if in_order => handler(world, a, b),
+                Some(handler) => handler(world, b, a),
+                None => Ok(()),
+            },
+            None => Ok(()),
+        },
+    }
+}
+
+pub fn run_sensor(world: &World, event: &SensorEvent) -> Result<(), repl::Error> {
+    match get_def(world, event.a, event.b) {
+        Some((
+            Def {
+                sensor_handler: Some(handler),
+                ..
+            },
+            in_order,
+        )) => {
             // Make sure to pass the entities in the order in which the handler expects them
-            if id_a == handler_id_a {
-                handler(world, &event.a, &event.b, event.pos, event.normal)
+            if in_order {
+                handler(world, event.a, event.b, event.phase)
             } else {
-                handler(world, &event.b, &event.a, event.pos, event.normal)
+                handler(world, event.b, event.a, event.phase)
             }
-        } else {
-            Ok(())
         }
-    } else {
-        Ok(())
+        _ => Ok(()),
     }
 }
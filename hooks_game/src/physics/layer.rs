@@ -0,0 +1,161 @@
+//! Named collision layers. Every collision object is in one layer, and a declarative matrix of
+//! layer pairs determines which objects can collide. The `CollisionGroups` of a layer are
+//! generated from this matrix, so that modules can add new kinds of entities without touching
+//! `collision`.
+//!
+//! Layers are declared during registration, and are turned into ncollide collision groups once
+//! registration is finished. Group ids are assigned in the order of the sorted layer names.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use specs::prelude::World;
+
+use physics::collision::{CollisionGroups, GeometricQueryType, Object};
+use physics::interaction;
+use registry::Registry;
+
+/// Maximal number of layers, as supported by ncollide's `CollisionGroups`.
+pub const MAX_LAYERS: usize = 30;
+
+pub fn register(reg: &mut Registry) {
+    reg.resource(LayersSetup::default());
+    reg.resource(Layers::default());
+
+    reg.freeze_fn(freeze);
+}
+
+/// Layer declarations, before `freeze` has assigned group ids.
+#[derive(Default)]
+struct LayersSetup {
+    names: BTreeSet<String>,
+    collide: Vec<(String, String)>,
+
+    /// Layer of each entity class that declared one, for checking interactions.
+    classes: BTreeMap<String, String>,
+}
+
+/// Resource that maps layer names to their collision groups.
+#[derive(Default)]
+pub struct Layers {
+    ids: BTreeMap<String, usize>,
+    groups: Vec<CollisionGroups>,
+}
+
+impl Layers {
+    /// Collision group id of a layer.
+    pub fn id(&self, layer: &str) -> Option<usize> {
+        self.ids.get(layer).cloned()
+    }
+
+    /// Collision groups for objects in a layer.
+    pub fn groups(&self, layer: &str) -> Option<CollisionGroups> {
+        self.id(layer).map(|id| self.groups[id])
+    }
+}
+
+/// Declare a collision layer. Declaring the same layer multiple times has no further effect.
+pub fn add(reg: &mut Registry, layer: &str) {
+    let mut setup = reg.world().write_resource::<LayersSetup>();
+
+    setup.names.insert(layer.to_string());
+
+    assert!(
+        setup.names.len() <= MAX_LAYERS,
+        "too many collision layers, adding {}",
+        layer
+    );
+}
+
+/// Let objects in these two layers collide with each other. The layers may be declared later on.
+pub fn set_collide(reg: &mut Registry, layer_a: &str, layer_b: &str) {
+    let mut setup = reg.world().write_resource::<LayersSetup>();
+
+    setup
+        .collide
+        .push((layer_a.to_string(), layer_b.to_string()));
+}
+
+/// Declare the layer of an entity class. This is only used to check that the class's
+/// interactions can actually happen.
+pub fn set_class(reg: &mut Registry, entity_class: &str, layer: &str) {
+    let mut setup = reg.world().write_resource::<LayersSetup>();
+
+    let previous = setup
+        .classes
+        .insert(entity_class.to_string(), layer.to_string());
+
+    assert!(
+        previous.is_none(),
+        "layer of entity class {} was set twice",
+        entity_class
+    );
+}
+
+/// Collision groups for objects in a layer. Panics if the layer does not exist.
+pub fn groups(world: &World, layer: &str) -> CollisionGroups {
+    world
+        .read_resource::<Layers>()
+        .groups(layer)
+        .unwrap_or_else(|| panic!("unknown collision layer {}", layer))
+}
+
+/// Collision object for an entity in a layer. Panics if the layer does not exist.
+pub fn object(world: &World, layer: &str) -> Object {
+    Object {
+        groups: groups(world, layer),
+        query_type: GeometricQueryType::Contacts(0.0, 0.0),
+    }
+}
+
+/// Assign group ids to the declared layers and build their collision groups.
+fn freeze(world: &mut World) {
+    let setup = mem::replace(&mut *world.write_resource::<LayersSetup>(), Default::default());
+    let mut layers = world.write_resource::<Layers>();
+
+    for (id, name) in setup.names.iter().enumerate() {
+        info!("Collision layer {} has id {}", name, id);
+
+        let mut groups = CollisionGroups::new();
+        groups.set_membership(&[id]);
+        groups.set_whitelist(&[]);
+
+        layers.ids.insert(name.clone(), id);
+        layers.groups.push(groups);
+    }
+
+    let mut whitelists = vec![Vec::new(); layers.groups.len()];
+
+    for &(ref a, ref b) in &setup.collide {
+        let id = |name: &str| {
+            layers
+                .id(name)
+                .unwrap_or_else(|| panic!("unknown collision layer {}", name))
+        };
+        let (id_a, id_b) = (id(a), id(b));
+
+        whitelists[id_a].push(id_b);
+        whitelists[id_b].push(id_a);
+    }
+
+    for (groups, whitelist) in layers.groups.iter_mut().zip(whitelists) {
+        groups.set_whitelist(&whitelist);
+    }
+
+    // Interactions between classes whose layers do not collide will never happen
+    for (class_a, class_b) in interaction::class_pairs(world) {
+        if let (Some(layer_a), Some(layer_b)) =
+            (setup.classes.get(&class_a), setup.classes.get(&class_b))
+        {
+            if !layers.groups[layers.ids[layer_a]].can_interact_with_groups(
+                &layers.groups[layers.ids[layer_b]],
+            ) {
+                warn!(
+                    "Interaction between {} and {} is set, but their layers {} and {} do not \
+                     collide",
+                    class_a, class_b, layer_a, layer_b,
+                );
+            }
+        }
+    }
+}
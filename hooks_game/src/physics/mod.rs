@@ -1,10 +1,21 @@
 pub mod collision;
 pub mod constraint;
 mod defs;
+mod grid;
 pub mod interaction;
+pub mod layer;
+pub mod query;
+pub mod record;
 pub mod sim;
 
+#[cfg(test)]
+mod testing;
+
+use specs::prelude::{Entity, ReadStorage};
+
+use defs::EntityId;
 use registry::Registry;
+use repl;
 
 pub use self::defs::*;
 
@@ -12,5 +23,20 @@ pub fn register(reg: &mut Registry) {
     defs::register(reg);
     collision::register(reg);
     interaction::register(reg);
+    layer::register(reg);
+    record::register(reg);
     sim::register(reg);
 }
+
+/// Key for processing entities in a canonical order, see `order_key`.
+pub type OrderKey = (Option<EntityId>, Entity);
+
+/// Key for processing entities in a canonical order. Entity indices depend on the order in which
+/// entities happened to be created, which differs between the server and the clients, while
+/// `repl::Id`s are the same everywhere. Since the solver is sequential, processing contacts and
+/// constraints in a different order gives slightly different results, so this is what makes
+/// prediction replay exact. Entities without a `repl::Id` only exist locally, so they are ordered
+/// by entity before all replicated entities.
+pub fn order_key(repl_id: &ReadStorage<repl::Id>, entity: Entity) -> OrderKey {
+    (repl_id.get(entity).map(|id| id.0), entity)
+}
@@ -0,0 +1,395 @@
+//! Geometric queries against the collision world, e.g. for line-of-sight checks, bots or checking
+//! whether a spawn point is free.
+//!
+//! Queries see the entities at the positions they had when the collision world was last updated,
+//! i.e. at the start of the last physics run. Only entities that are `entity::Active` and have a
+//! `collision::Object` are present in the collision world.
+//!
+//! Hits are ordered by `physics::order_key` unless stated otherwise, and ties are broken in that
+//! order, so that queries give the same result on the server and on the clients.
+
+use std::cmp::Ordering;
+
+use nalgebra::{norm, zero, Isometry2, Point2, Vector2};
+use ncollide::bounding_volume::{BoundingVolume, AABB};
+use ncollide::query::{self, Ray};
+use specs::prelude::{Entity, World};
+
+use defs::{EntityClassId, EntityId};
+use entity;
+use physics::collision::{CollisionGroups, CollisionObject, CollisionWorld, Shape};
+use repl;
+
+/// Restricts the entities that a query can return.
+#[derive(Clone)]
+pub struct Filter {
+    /// Only entities whose collision groups can interact with these groups are returned.
+    pub groups: CollisionGroups,
+
+    /// If given, only entities of these classes are returned.
+    pub classes: Option<Vec<EntityClassId>>,
+
+    /// Entities that are ignored, e.g. the entity that is doing the query.
+    pub exclude: Vec<Entity>,
+}
+
+impl Filter {
+    /// Filter that returns all entities in the collision world.
+    pub fn new() -> Filter {
+        Filter {
+            groups: CollisionGroups::new(),
+            classes: None,
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn groups(groups: CollisionGroups) -> Filter {
+        Filter {
+            groups,
+            ..Filter::new()
+        }
+    }
+
+    /// Only return entities of the given classes. Panics if a class does not exist.
+    pub fn with_classes(mut self, world: &World, class_names: &[&str]) -> Filter {
+        let classes = class_names
+            .iter()
+            .map(|name| {
+                entity::get_class_id(world, name)
+                    .unwrap_or_else(|| panic!("unknown entity class {}", name))
+            })
+            .collect();
+
+        self.classes = Some(classes);
+        self
+    }
+
+    pub fn exclude(mut self, entity: Entity) -> Filter {
+        self.exclude.push(entity);
+        self
+    }
+
+    fn accepts(&self, world: &World, entity: Entity) -> bool {
+        if self.exclude.contains(&entity) {
+            return false;
+        }
+
+        if let Some(ref classes) = self.classes {
+            let meta = world.read::<entity::Meta>();
+
+            match meta.get(entity) {
+                Some(meta) if classes.contains(&meta.class_id) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new()
+    }
+}
+
+/// An entity that was found by a query.
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub entity: Entity,
+
+    /// Id of the entity, if it is replicated.
+    pub id: Option<EntityId>,
+
+    /// Point of impact in world-space coordinates. For point and AABB queries, this is the
+    /// entity's position.
+    pub pos: Point2<f32>,
+
+    /// Surface normal at the point of impact, pointing outwards from the entity that was hit.
+    /// Zero for point and AABB queries.
+    pub normal: Vector2<f32>,
+
+    /// Distance travelled until the impact, measured in units of the cast's direction vector.
+    /// Zero for point and AABB queries.
+    pub toi: f32,
+}
+
+fn hit(
+    world: &World,
+    object: &CollisionObject,
+    pos: Point2<f32>,
+    normal: Vector2<f32>,
+    toi: f32,
+) -> Hit {
+    let entity = *object.data();
+    let id = world.read::<repl::Id>().get(entity).map(|id| id.0);
+
+    Hit {
+        entity,
+        id,
+        pos,
+        normal,
+        toi,
+    }
+}
+
+/// Cast a ray from `origin` in direction `dir`, returning the first entity that is hit within
+/// `max_distance`.
+pub fn ray_cast(
+    world: &World,
+    origin: Point2<f32>,
+    dir: Vector2<f32>,
+    max_distance: f32,
+    filter: &Filter,
+) -> Option<Hit> {
+    ray_cast_all(world, origin, dir, max_distance, filter)
+        .into_iter()
+        .next()
+}
+
+/// Cast a ray from `origin` in direction `dir`, returning all entities that are hit within
+/// `max_distance`, ordered by distance. `Hit::toi` is the distance from the origin.
+pub fn ray_cast_all(
+    world: &World,
+    origin: Point2<f32>,
+    dir: Vector2<f32>,
+    max_distance: f32,
+    filter: &Filter,
+) -> Vec<Hit> {
+    let length = norm(&dir);
+    if length == 0.0 {
+        return Vec::new();
+    }
+
+    let ray = Ray::new(origin, dir / length);
+    let collision_world = world.read_resource::<CollisionWorld>();
+
+    let mut hits = collision_world
+        .interferences_with_ray(&ray, max_distance, &filter.groups)
+        .into_iter()
+        .filter(|&(object, _)| filter.accepts(world, *object.data()))
+        .map(|(object, intersection)| {
+            let pos = ray.origin + ray.dir * intersection.toi;
+            hit(world, object, pos, intersection.normal, intersection.toi)
+        })
+        .collect::<Vec<_>>();
+
+    // Degenerate shapes can give a NaN time of impact, which must not make us panic
+    hits.sort_by(|a, b| a.toi.partial_cmp(&b.toi).unwrap_or(Ordering::Equal));
+    hits
+}
+
+/// Is there a free line of sight between two points?
+pub fn line_of_sight(world: &World, a: Point2<f32>, b: Point2<f32>, filter: &Filter) -> bool {
+    ray_cast(world, a, b - a, norm(&(b - a)), filter).is_none()
+}
+
+/// Move a shape from `isometry` by `delta`, returning the first entity that it hits. `Hit::toi`
+/// is the fraction of `delta` at which the impact happens. Entities that already overlap the
+/// shape at its start are hit at zero.
+pub fn shape_cast(
+    world: &World,
+    shape: &Shape,
+    isometry: &Isometry2<f32>,
+    delta: Vector2<f32>,
+    filter: &Filter,
+) -> Option<Hit> {
+    let collision_world = world.read_resource::<CollisionWorld>();
+
+    let angle = isometry.rotation.angle();
+    let end = Isometry2::new(isometry.translation.vector + delta, angle);
+    let swept_aabb = shape.0.aabb(isometry).merged(&shape.0.aabb(&end));
+
+    let mut first: Option<(f32, &CollisionObject)> = None;
+
+    for object in collision_world.interferences_with_aabb(&swept_aabb, &filter.groups) {
+        if !filter.accepts(world, *object.data()) {
+            continue;
+        }
+
+        let toi = query::time_of_impact(
+            isometry,
+            &delta,
+            &*shape.0,
+            object.position(),
+            &zero(),
+            &**object.shape(),
+        );
+
+        if let Some(toi) = toi {
+            if toi <= 1.0 && first.map_or(true, |(first_toi, _)| toi < first_toi) {
+                first = Some((toi, object));
+            }
+        }
+    }
+
+    first.map(|(toi, object)| {
+        let impact = Isometry2::new(isometry.translation.vector + delta * toi, angle);
+
+        // The shapes are touching at the impact, so this should give us a contact point
+        let contact = query::contact(
+            &impact,
+            &*shape.0,
+            object.position(),
+            &**object.shape(),
+            1.0,
+        );
+
+        match contact {
+            Some(contact) => hit(world, object, contact.world2, -contact.normal.unwrap(), toi),
+            None => {
+                let pos = Point2::from_coordinates(impact.translation.vector);
+                hit(world, object, pos, zero(), toi)
+            }
+        }
+    })
+}
+
+/// Return all entities whose shape contains the given point.
+pub fn point(world: &World, point: Point2<f32>, filter: &Filter) -> Vec<Hit> {
+    let collision_world = world.read_resource::<CollisionWorld>();
+
+    collision_world
+        .interferences_with_point(&point, &filter.groups)
+        .into_iter()
+        .filter(|object| filter.accepts(world, *object.data()))
+        .map(|object| {
+            let pos = Point2::from_coordinates(object.position().translation.vector);
+            hit(world, object, pos, zero(), 0.0)
+        })
+        .collect()
+}
+
+/// Return all entities whose bounding box intersects the given box.
+pub fn aabb(world: &World, mins: Point2<f32>, maxs: Point2<f32>, filter: &Filter) -> Vec<Hit> {
+    let collision_world = world.read_resource::<CollisionWorld>();
+    let aabb = AABB::new(mins, maxs);
+
+    collision_world
+        .interferences_with_aabb(&aabb, &filter.groups)
+        .into_iter()
+        .filter(|object| filter.accepts(world, *object.data()))
+        .map(|object| {
+            let pos = Point2::from_coordinates(object.position().translation.vector);
+            hit(world, object, pos, zero(), 0.0)
+        })
+        .collect()
+}
+
+/// Is there no entity overlapping the shape at the given isometry? This can be used for checking
+/// that a spawn point is free.
+pub fn is_free(world: &World, shape: &Shape, isometry: &Isometry2<f32>, filter: &Filter) -> bool {
+    let collision_world = world.read_resource::<CollisionWorld>();
+    let aabb = shape.0.aabb(isometry);
+
+    !collision_world
+        .interferences_with_aabb(&aabb, &filter.groups)
+        .into_iter()
+        .filter(|object| filter.accepts(world, *object.data()))
+        .any(|object| {
+            query::contact(isometry, &*shape.0, object.position(), &**object.shape(), 0.0)
+                .is_some()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{norm, Isometry2, Point2, Vector2};
+    use specs::prelude::World;
+
+    use game::testing::game_info;
+    use physics::collision::Shape;
+    use physics::layer;
+    use physics::sim::Runner;
+    use physics::testing::{self, BODY, WALL};
+
+    use super::{is_free, ray_cast, ray_cast_all, Filter};
+
+    fn world() -> World {
+        testing::world(testing::registry(&game_info()))
+    }
+
+    /// Put the entities into the collision world.
+    fn update(world: &mut World) {
+        let mut runner = Runner::new(world);
+        testing::run_tick(world, &mut runner);
+    }
+
+    #[test]
+    fn test_ray_cast_order() {
+        let mut world = world();
+        let size = Vector2::new(20.0, 20.0);
+
+        // Create the walls out of order, so that the order of the hits is not the creation order
+        let far = testing::create(&mut world, WALL, Point2::new(300.0, 0.0), size);
+        let near = testing::create(&mut world, WALL, Point2::new(100.0, 0.0), size);
+        let middle = testing::create(&mut world, WALL, Point2::new(200.0, 0.0), size);
+        update(&mut world);
+
+        let origin = Point2::origin();
+        let dir = Vector2::new(2.0, 0.0);
+        let hits = ray_cast_all(&world, origin, dir, 1000.0, &Filter::new());
+
+        assert_eq!(
+            hits.iter().map(|hit| hit.entity).collect::<Vec<_>>(),
+            vec![near, middle, far]
+        );
+        for (hit, &toi) in hits.iter().zip(&[90.0, 190.0, 290.0]) {
+            assert!((hit.toi - toi).abs() < 1e-3);
+            assert!(norm(&(hit.pos - Point2::new(toi, 0.0))) < 1e-3);
+            assert!(norm(&(hit.normal - Vector2::new(-1.0, 0.0))) < 1e-3);
+        }
+
+        let hits = ray_cast_all(&world, origin, dir, 150.0, &Filter::new());
+        assert_eq!(hits.iter().map(|hit| hit.entity).collect::<Vec<_>>(), vec![near]);
+
+        let hit = ray_cast(&world, origin, dir, 1000.0, &Filter::new().exclude(near));
+        assert_eq!(hit.map(|hit| hit.entity), Some(middle));
+
+        assert!(ray_cast(&world, origin, -dir, 1000.0, &Filter::new()).is_none());
+    }
+
+    #[test]
+    fn test_ray_cast_filter() {
+        let mut world = world();
+        let size = Vector2::new(20.0, 20.0);
+
+        let wall = testing::create(&mut world, WALL, Point2::new(100.0, 0.0), size);
+        let body = testing::create(&mut world, BODY, Point2::new(200.0, 0.0), size);
+        update(&mut world);
+
+        let origin = Point2::origin();
+        let dir = Vector2::new(1.0, 0.0);
+        let first = |filter: &Filter| {
+            ray_cast(&world, origin, dir, 1000.0, filter).map(|hit| hit.entity)
+        };
+
+        assert_eq!(first(&Filter::new()), Some(wall));
+        assert_eq!(first(&Filter::new().exclude(wall)), Some(body));
+        assert_eq!(first(&Filter::new().with_classes(&world, &[BODY])), Some(body));
+        assert_eq!(first(&Filter::new().with_classes(&world, &[WALL]).exclude(wall)), None);
+
+        // Walls do not collide with walls
+        assert_eq!(first(&Filter::groups(layer::groups(&world, WALL))), Some(body));
+        assert_eq!(first(&Filter::groups(layer::groups(&world, BODY))), Some(wall));
+    }
+
+    #[test]
+    fn test_is_free() {
+        let mut world = world();
+
+        let wall = testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        update(&mut world);
+
+        let shape = Shape::cuboid(Vector2::new(20.0, 20.0));
+        let free = |x: f32, filter: &Filter| {
+            is_free(&world, &shape, &Isometry2::new(Vector2::new(x, 0.0), 0.0), filter)
+        };
+
+        assert!(!free(0.0, &Filter::new()));
+        assert!(!free(55.0, &Filter::new()));
+        assert!(free(65.0, &Filter::new()));
+        assert!(free(0.0, &Filter::new().exclude(wall)));
+        assert!(free(0.0, &Filter::new().with_classes(&world, &[BODY])));
+    }
+}
@@ -0,0 +1,430 @@
+//! Opt-in recording of what the physics simulation does in each step, for debugging jitter and
+//! explosions after the fact. When enabled, `sim::Runner` appends one `Step` per physics step to a
+//! file, containing the body poses at the end of the step, the contacts and constraints with their
+//! values, jacobians and multipliers, and the interaction events that were detected.
+//!
+//! Each line of a recording is one `Step` in RON format, so that recordings can be attached to bug
+//! reports and inspected with the `physics_trace` tool of the server.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ron;
+use specs::prelude::{Entity, ReadStorage, World};
+
+use defs::{EntityId, TickNum};
+use registry::Registry;
+use repl;
+
+pub fn register(reg: &mut Registry) {
+    reg.resource(Recorder::default());
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serialize(ron::ser::Error),
+    Parse(PathBuf, usize, ron::de::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<ron::ser::Error> for Error {
+    fn from(error: ron::ser::Error) -> Error {
+        Error::Serialize(error)
+    }
+}
+
+/// Resource that writes the recorded steps. Recording is disabled by default.
+#[derive(Default)]
+pub struct Recorder {
+    writer: Option<BufWriter<File>>,
+
+    /// Tick that is currently being run, as given by the game.
+    tick: TickNum,
+
+    /// Number of `sim::Runner::run` calls in the current tick. There is one run per batch of
+    /// player inputs.
+    run: u32,
+}
+
+impl Recorder {
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Write one step. If writing fails, recording is stopped, so that a full disk does not take
+    /// the game down with it.
+    pub fn write(&mut self, step: &Step) {
+        let result = match self.writer {
+            Some(ref mut writer) => write_step(writer, step),
+            None => return,
+        };
+
+        if let Err(error) = result {
+            warn!("Stopping physics recording, failed to write step: {:?}", error);
+            self.writer = None;
+        }
+    }
+
+    /// Called by `sim::Runner` before each run. Returns the index of the run in the current tick.
+    pub fn begin_run(&mut self) -> u32 {
+        self.run += 1;
+        self.run - 1
+    }
+
+    /// Called by `sim::Runner` after each run, so that recordings survive the server being killed.
+    pub fn end_run(&mut self) {
+        let result = match self.writer {
+            Some(ref mut writer) => writer.flush(),
+            None => return,
+        };
+
+        if let Err(error) = result {
+            warn!("Stopping physics recording, failed to flush: {:?}", error);
+            self.writer = None;
+        }
+    }
+
+    pub fn tick(&self) -> TickNum {
+        self.tick
+    }
+}
+
+fn write_step<W: Write>(writer: &mut W, step: &Step) -> Result<(), Error> {
+    let line = ron::ser::to_string(step)?;
+    writeln!(writer, "{}", line)?;
+    Ok(())
+}
+
+/// Start recording every physics step of the world to a file.
+pub fn start(world: &World, path: &Path) -> Result<(), Error> {
+    let file = File::create(path)?;
+    world.write_resource::<Recorder>().writer = Some(BufWriter::new(file));
+
+    Ok(())
+}
+
+/// Set the number of the tick that is about to be run, so that steps can be found by tick in the
+/// recording.
+pub fn set_tick(world: &World, tick: TickNum) {
+    let mut recorder = world.write_resource::<Recorder>();
+    recorder.tick = tick;
+    recorder.run = 0;
+}
+
+/// Read all steps of a recording.
+pub fn read_file(path: &Path) -> Result<Vec<Step>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut steps = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let step =
+            ron::de::from_str(&line).map_err(|e| Error::Parse(path.to_path_buf(), i + 1, e))?;
+        steps.push(step);
+    }
+
+    Ok(steps)
+}
+
+/// Identifies an entity in a recording. Replicated entities are given by their `repl::Id`, which
+/// stays the same when a recorded scenario is run again. Other entities are given by their index
+/// in the `World`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum EntityRef {
+    Repl(EntityId),
+    Local(u32),
+}
+
+impl EntityRef {
+    pub fn new(repl_id: &ReadStorage<repl::Id>, entity: Entity) -> EntityRef {
+        repl_id
+            .get(entity)
+            .map(|id| EntityRef::Repl(id.0))
+            .unwrap_or(EntityRef::Local(entity.id()))
+    }
+}
+
+impl fmt::Display for EntityRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntityRef::Repl((owner, index)) => write!(f, "{}.{}", owner, index),
+            EntityRef::Local(index) => write!(f, "#{}", index),
+        }
+    }
+}
+
+/// State of a simulated body at the end of a step.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Body {
+    pub entity: EntityRef,
+    pub pos: [f32; 2],
+    pub angle: f32,
+    pub vel: Option<[f32; 2]>,
+    pub angular_vel: Option<f32>,
+    pub sleeping: bool,
+}
+
+/// A contact constraint that was generated in a step.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Contact {
+    pub a: EntityRef,
+    pub b: EntityRef,
+    pub normal: [f32; 2],
+    pub margin: f32,
+}
+
+/// A constraint that was solved in a step. The value and jacobian are evaluated at the poses at
+/// the end of the step, so a non-zero value of an equality constraint is the remaining error.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Constraint {
+    pub a: EntityRef,
+    pub b: EntityRef,
+
+    /// Name of the constraint's `Def` variant.
+    pub kind: String,
+
+    pub value: Option<f32>,
+    pub jacobian: Option<[f32; 6]>,
+
+    /// Accumulated Lagrange multiplier.
+    pub lambda: f32,
+}
+
+/// An interaction event that was detected in a step.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Event {
+    Interaction {
+        a: EntityRef,
+        b: EntityRef,
+        pos: [f32; 2],
+        normal: [f32; 2],
+    },
+    ContactBegin(EntityRef, EntityRef),
+    ContactPersist(EntityRef, EntityRef),
+    ContactEnd(EntityRef, EntityRef),
+    Sensor(EntityRef, EntityRef, String),
+}
+
+/// Everything recorded for one physics step.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Step {
+    pub tick: TickNum,
+    pub run: u32,
+    pub substep: u32,
+
+    pub bodies: Vec<Body>,
+    pub contacts: Vec<Contact>,
+    pub constraints: Vec<Constraint>,
+    pub events: Vec<Event>,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tick {} run {} substep {}", self.tick, self.run, self.substep)?;
+
+        for body in &self.bodies {
+            write!(
+                f,
+                "  body {} pos ({:.3}, {:.3}) angle {:.4}",
+                body.entity, body.pos[0], body.pos[1], body.angle
+            )?;
+            if let Some(vel) = body.vel {
+                write!(f, " vel ({:.3}, {:.3})", vel[0], vel[1])?;
+            }
+            if let Some(angular_vel) = body.angular_vel {
+                write!(f, " angular_vel {:.4}", angular_vel)?;
+            }
+            if body.sleeping {
+                write!(f, " sleeping")?;
+            }
+            writeln!(f)?;
+        }
+
+        for contact in &self.contacts {
+            writeln!(
+                f,
+                "  contact {} {} normal ({:.3}, {:.3}) margin {:.3}",
+                contact.a, contact.b, contact.normal[0], contact.normal[1], contact.margin
+            )?;
+        }
+
+        for c in &self.constraints {
+            write!(f, "  constraint {} {} {} lambda {:.4}", c.kind, c.a, c.b, c.lambda)?;
+            if let Some(value) = c.value {
+                write!(f, " value {:.4}", value)?;
+            }
+            if let Some(j) = c.jacobian {
+                write!(
+                    f,
+                    " jacobian [{:.3}, {:.3}, {:.3}, {:.3}, {:.3}, {:.3}]",
+                    j[0], j[1], j[2], j[3], j[4], j[5]
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        for event in &self.events {
+            writeln!(f, "  event {:?}", event)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn differs(a: f32, b: f32, tolerance: f32) -> bool {
+    !((a - b).abs() <= tolerance || (a.is_nan() && b.is_nan()))
+}
+
+fn differs_slice(a: &[f32], b: &[f32], tolerance: f32) -> bool {
+    a.len() != b.len() || a.iter().zip(b).any(|(&a, &b)| differs(a, b, tolerance))
+}
+
+fn differs_option(a: Option<f32>, b: Option<f32>, tolerance: f32) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => differs(a, b, tolerance),
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+fn differs_option_slice(a: Option<&[f32]>, b: Option<&[f32]>, tolerance: f32) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => differs_slice(a, b, tolerance),
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Describe the differences between two recorded steps. Numbers are compared with the given
+/// absolute tolerance.
+pub fn diff(a: &Step, b: &Step, tolerance: f32) -> Vec<String> {
+    let mut diffs = Vec::new();
+
+    if (a.tick, a.run, a.substep) != (b.tick, b.run, b.substep) {
+        diffs.push(format!(
+            "step: tick {} run {} substep {} vs tick {} run {} substep {}",
+            a.tick, a.run, a.substep, b.tick, b.run, b.substep
+        ));
+    }
+
+    if a.bodies.len() != b.bodies.len() {
+        diffs.push(format!("bodies: {} vs {}", a.bodies.len(), b.bodies.len()));
+    }
+    for (body_a, body_b) in a.bodies.iter().zip(&b.bodies) {
+        let vel_a = body_a.vel.as_ref().map(|v| &v[..]);
+        let vel_b = body_b.vel.as_ref().map(|v| &v[..]);
+
+        if body_a.entity != body_b.entity || body_a.sleeping != body_b.sleeping ||
+            differs_slice(&body_a.pos, &body_b.pos, tolerance) ||
+            differs(body_a.angle, body_b.angle, tolerance) ||
+            differs_option_slice(vel_a, vel_b, tolerance) ||
+            differs_option(body_a.angular_vel, body_b.angular_vel, tolerance)
+        {
+            diffs.push(format!("body: {:?} vs {:?}", body_a, body_b));
+        }
+    }
+
+    if a.contacts.len() != b.contacts.len() {
+        diffs.push(format!("contacts: {} vs {}", a.contacts.len(), b.contacts.len()));
+    }
+    for (contact_a, contact_b) in a.contacts.iter().zip(&b.contacts) {
+        if (contact_a.a, contact_a.b) != (contact_b.a, contact_b.b) ||
+            differs_slice(&contact_a.normal, &contact_b.normal, tolerance) ||
+            differs(contact_a.margin, contact_b.margin, tolerance)
+        {
+            diffs.push(format!("contact: {:?} vs {:?}", contact_a, contact_b));
+        }
+    }
+
+    if a.constraints.len() != b.constraints.len() {
+        diffs.push(format!(
+            "constraints: {} vs {}",
+            a.constraints.len(),
+            b.constraints.len()
+        ));
+    }
+    for (c_a, c_b) in a.constraints.iter().zip(&b.constraints) {
+        let jacobian_a = c_a.jacobian.as_ref().map(|j| &j[..]);
+        let jacobian_b = c_b.jacobian.as_ref().map(|j| &j[..]);
+
+        if (c_a.a, c_a.b) != (c_b.a, c_b.b) || c_a.kind != c_b.kind ||
+            differs(c_a.lambda, c_b.lambda, tolerance) ||
+            differs_option(c_a.value, c_b.value, tolerance) ||
+            differs_option_slice(jacobian_a, jacobian_b, tolerance)
+        {
+            diffs.push(format!("constraint: {:?} vs {:?}", c_a, c_b));
+        }
+    }
+
+    // Events contain positions, but these are already covered by the contacts
+    if a.events != b.events {
+        diffs.push(format!("events: {:?} vs {:?}", a.events, b.events));
+    }
+
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(x: f32) -> Step {
+        Step {
+            tick: 3,
+            run: 0,
+            substep: 1,
+            bodies: vec![
+                Body {
+                    entity: EntityRef::Repl((1, 2)),
+                    pos: [x, 2.0],
+                    angle: 0.5,
+                    vel: Some([0.0, -1.0]),
+                    angular_vel: None,
+                    sleeping: false,
+                },
+            ],
+            contacts: Vec::new(),
+            constraints: vec![
+                Constraint {
+                    a: EntityRef::Repl((1, 2)),
+                    b: EntityRef::Local(7),
+                    kind: "Joint".to_string(),
+                    value: Some(0.01),
+                    jacobian: Some([1.0, 0.0, 0.0, -1.0, 0.0, 0.0]),
+                    lambda: -0.2,
+                },
+            ],
+            events: vec![Event::ContactEnd(EntityRef::Local(7), EntityRef::Repl((1, 2)))],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut data = Vec::new();
+        write_step(&mut data, &step(1.0)).unwrap();
+
+        let line = String::from_utf8(data).unwrap();
+        let read: Step = ron::de::from_str(line.trim()).unwrap();
+
+        assert_eq!(read, step(1.0));
+    }
+
+    #[test]
+    fn test_diff() {
+        assert!(diff(&step(1.0), &step(1.0), 0.0).is_empty());
+        assert!(diff(&step(1.0), &step(1.0005), 0.001).is_empty());
+        assert_eq!(diff(&step(1.0), &step(1.1), 0.001).len(), 1);
+    }
+}
@@ -1,31 +1,53 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::f32;
+use std::mem;
 
-use nalgebra::{dot, norm, zero, Point2, Vector2};
+use nalgebra::{dot, norm, zero, Isometry2, Point2, Vector2};
+use ncollide::bounding_volume::BoundingVolume;
+use ncollide::query::{self, Contact};
 use specs::prelude::*;
+use specs::storage::{AntiStorage, BTreeStorage};
 
 use hooks_util::{profile, stats};
 
-use defs::GameInfo;
+use defs::{GameInfo, INVALID_PLAYER_ID};
 use entity::{self, Active};
 use registry::Registry;
 use repl;
 
 use physics::collision::CollisionWorld;
 use physics::constraint::Constraint;
-use physics::{collision, constraint, interaction, AngularVelocity, Drag, Dynamic, Friction,
-              InvAngularMass, InvMass, Orientation, Position, Update, Velocity};
+use physics::{collision, constraint, interaction, order_key, record, AngularVelocity, Ccd, Drag,
+              Dynamic, Friction, InvAngularMass, InvMass, Kinematic, KinematicPath, Material,
+              OrderKey, Orientation, Position, Update, Velocity};
 
 pub fn register(reg: &mut Registry) {
     reg.component::<OldPosition>();
     reg.component::<OldOrientation>();
     reg.component::<Force>();
+    reg.component::<Sleeping>();
+    reg.component::<RestTicks>();
 
     reg.resource(InteractionEvents(Vec::new()));
+    reg.resource(ContactEvents(Vec::new()));
+    reg.resource(ContactPairs(BTreeSet::new()));
+    reg.resource(SensorEvents(Vec::new()));
+    reg.resource(SensorOverlaps(BTreeSet::new()));
     reg.resource(Constraints(Vec::new()));
+    reg.resource(Lambdas(Vec::new()));
+    reg.resource(IslandPairs(BTreeSet::new()));
+    reg.resource(NextIsland(0));
 }
 
-pub const NUM_ITERATIONS: usize = 20;
-pub const CONTACT_MARGIN: f32 = 1.0;
+/// `Ccd` entities that move less than this distance in a tick are not swept.
+const MIN_SWEEP_DISTANCE: f32 = 1.0;
+
+/// Bodies that are slower than this are considered to be resting.
+pub const SLEEP_SPEED: f32 = 2.0;
+pub const SLEEP_ANGULAR_SPEED: f32 = 0.05;
+
+/// Number of seconds that all bodies of an island need to be resting before it falls asleep.
+pub const SLEEP_SECS: f32 = 0.5;
 
 /// Tag components that all need to be given for entities that want to be simulated.
 #[derive(SystemData)]
@@ -39,6 +61,10 @@ struct Filter<'a> {
     /// The entity is to be simulated in the next run call. This makes it possible to e.g. simulate
     /// only one player's entities.
     update: ReadStorage<'a, Update>,
+
+    /// The entity has come to rest. Sleeping entities are treated like static entities until
+    /// they are woken up.
+    sleeping: ReadStorage<'a, Sleeping>,
 }
 
 impl<'a> Filter<'a> {
@@ -48,18 +74,71 @@ impl<'a> Filter<'a> {
         &ReadStorage<'a, Dynamic>,
         &ReadStorage<'a, Active>,
         &ReadStorage<'a, Update>,
+        AntiStorage,
     ) {
-        (&self.dynamic, &self.active, &self.update)
+        (&self.dynamic, &self.active, &self.update, !&self.sleeping)
     }
 
     pub fn filter(&self, entity: Entity) -> bool {
         self.dynamic.get(entity).is_some() && self.active.get(entity).is_some() &&
-            self.update.get(entity).is_some()
+            self.update.get(entity).is_some() && self.sleeping.get(entity).is_none()
     }
 }
 
+/// Mass of a body as seen by the constraint solver. Bodies that are not simulated in this run are
+/// treated as kinematic: they push the simulated bodies out of the way, but are never moved
+/// themselves. Otherwise, running one player's input would displace the entities of other
+/// players.
+fn solver_mass(
+    filter: &Filter,
+    inv_mass: &ReadStorage<InvMass>,
+    inv_angular_mass: &ReadStorage<InvAngularMass>,
+    entity: Entity,
+    vars: &constraint::Vars,
+) -> constraint::Mass {
+    let mass = if filter.filter(entity) {
+        constraint::Mass {
+            inv: inv_mass.get(entity).map(|m| m.0).unwrap_or(0.0),
+            inv_angular: inv_angular_mass.get(entity).map(|m| m.0).unwrap_or(0.0),
+        }
+    } else {
+        constraint::Mass::kinematic()
+    };
+
+    mass.zero_out_constants(vars)
+}
+
+/// Bodies that have been resting for a while are put to sleep, so that we do not need to
+/// simulate them. Bodies that touch each other via constraints form an island, which only falls
+/// asleep as a whole, and is also woken up as a whole.
+///
+/// Only the server puts bodies to sleep, and this component is not replicated. Clients predict
+/// the entities of their own player without knowing whether they are asleep, so entities that
+/// are owned by a player never fall asleep.
+#[derive(Component, PartialEq, Clone, Debug)]
+#[storage(BTreeStorage)]
+pub struct Sleeping {
+    pub island: u32,
+}
+
+/// Number of consecutive ticks at the end of which a body has been resting.
+#[derive(Component, PartialEq, Clone, Debug, Default)]
+#[storage(VecStorage)]
+struct RestTicks(u32);
+
+/// Resource to remember which pairs of entities were constrained in any step of the current tick,
+/// so that `SleepSys` can find the islands once the tick is over.
+struct IslandPairs(BTreeSet<(Entity, Entity)>);
+
+/// Resource for assigning ids to sleeping islands.
+struct NextIsland(u32);
+
 //const JOINT_MIN_DISTANCE: f32 = 0.001;
 const MIN_SPEED: f32 = 0.01;
+const MIN_ANGULAR_SPEED: f32 = 0.01;
+
+/// Deceleration of the rotation of entities that do not have a `Material`.
+const DEFAULT_ANGULAR_DECELERATION: f32 = 100.0;
 
 #[derive(Component, PartialEq, Clone, Debug)]
 #[storage(VecStorage)]
@@ -72,6 +151,29 @@ struct OldOrientation(f32);
 /// Resource to store the interactions that were detected in a time step.
 struct InteractionEvents(Vec<interaction::Event>);
 
+/// Resource to store the begin, persist and end events of contacts in a time step.
+struct ContactEvents(Vec<interaction::ContactEvent>);
+
+/// Resource to remember which pairs of entities with contact lifecycle callbacks are touching, so
+/// that we can tell when they start and stop touching. Pairs are ordered by entity.
+struct ContactPairs(BTreeSet<(Entity, Entity)>);
+
+/// Resource to store the sensor events that were detected in a time step.
+struct SensorEvents(Vec<interaction::SensorEvent>);
+
+/// Resource to remember which pairs of sensor entities are overlapping, so that we can tell when
+/// they start and stop overlapping. Pairs are ordered by entity.
+struct SensorOverlaps(BTreeSet<(Entity, Entity)>);
+
+/// The contact pairs and sensor overlaps that the simulation remembers between runs. Prediction
+/// keeps a copy after every input, so that replaying inputs starts from the pairs that were
+/// touching back then, instead of from the ones that the latest prediction left behind.
+#[derive(Clone, Default)]
+pub struct Pairs {
+    contacts: BTreeSet<(Entity, Entity)>,
+    sensors: BTreeSet<(Entity, Entity)>,
+}
+
 /// Resource to store the constraints that are to be applied in the current time step.
 pub struct Constraints(Vec<Constraint>);
 
@@ -81,6 +183,11 @@ impl Constraints {
     }
 }
 
+/// Resource to store the accumulated Lagrange multipliers of the `Constraints` after solving, in
+/// the same order. For contacts, this tells us how strongly the bodies were pressed together,
+/// which bounds their friction.
+struct Lambdas(Vec<f32>);
+
 /// Stores the state necessary to run a simulation.
 pub struct Runner {
     collision_update_sys: collision::UpdateSys,
@@ -88,17 +195,73 @@ pub struct Runner {
 
 impl Runner {
     pub fn new(world: &mut World) -> Runner {
+        // Contact tracking and actions need to know the interactions before the first step
+        interaction::setup(world);
+
         Runner {
             collision_update_sys: collision::UpdateSys::new(world),
         }
     }
 
+    /// Simulate one tick, split into `PhysicsConfig::substeps` steps. The constraints that game
+    /// logic has added for this tick are applied in every step, and are cleared at the end.
     pub fn run(&mut self, world: &World) {
         profile!("physics");
 
+        let substeps = world.read_resource::<GameInfo>().physics_substeps();
+        let tick_constraints = world.read_resource::<Constraints>().0.clone();
+
+        let record_run = {
+            let mut recorder = world.write_resource::<record::Recorder>();
+            if recorder.is_enabled() {
+                Some(recorder.begin_run())
+            } else {
+                None
+            }
+        };
+
+        for i in 0..substeps {
+            if i > 0 {
+                // Drop the contacts of the previous step, they are found anew
+                world.write_resource::<Constraints>().0 = tick_constraints.clone();
+            }
+
+            let num_events = NumEvents::new(world);
+
+            self.run_step(world);
+
+            {
+                let constraints = world.read_resource::<Constraints>();
+                let mut island_pairs = world.write_resource::<IslandPairs>();
+
+                island_pairs
+                    .0
+                    .extend(constraints.0.iter().map(|c| (c.entity_a, c.entity_b)));
+            }
+
+            if let Some(run) = record_run {
+                let step = record_step(world, run, i, &num_events);
+                world.write_resource::<record::Recorder>().write(&step);
+            }
+        }
+
+        if record_run.is_some() {
+            world.write_resource::<record::Recorder>().end_run();
+        }
+
+        world.write_resource::<Constraints>().0.clear();
+    }
+
+    fn run_step(&mut self, world: &World) {
+        // Every step depends on the results of the previous one, so there is nothing to be gained
+        // from dispatching these systems in parallel.
+
         collision::MaintainSys.run_now(&world.res);
         self.collision_update_sys.run_now(&world.res);
 
+        // Wake up bodies that were given a velocity or that are constrained by game logic
+        WakeSys.run_now(&world.res);
+
         PrepareSys.run_now(&world.res);
         FrictionForceSys.run_now(&world.res);
         DragForceSys.run_now(&world.res);
@@ -107,10 +270,104 @@ impl Runner {
         SavePositionSys.run_now(&world.res);
         IntegrateVelocitySys.run_now(&world.res);
         HandleContactsSys.run_now(&world.res);
+
+        // Wake up bodies that are touched by moving bodies
+        WakeSys.run_now(&world.res);
+
         SolveConstraintsSys.run_now(&world.res);
         CorrectVelocitySys.run_now(&world.res);
+    }
 
-        world.write_resource::<Constraints>().0.clear();
+    /// Move `Kinematic` bodies by their velocity. This needs to happen once per tick, before any
+    /// input is run, so that the contacts in `run` see the bodies' motion instead of teleports.
+    /// Predicting clients call this before every input that they run, see `game::predict`.
+    pub fn run_kinematic(&mut self, world: &World) {
+        profile!("kinematic");
+
+        KinematicPathSys.run_now(&world.res);
+        MoveKinematicSys.run_now(&world.res);
+    }
+
+    /// Put islands of resting bodies to sleep. This needs to happen once per tick, after all
+    /// input has been run, so that the time that bodies have been resting does not depend on how
+    /// many runs there were in the tick. Only the server calls this, see `Sleeping`.
+    pub fn run_sleep(&mut self, world: &World) {
+        profile!("sleep");
+
+        SleepSys.run_now(&world.res);
+
+        world.write_resource::<IslandPairs>().0.clear();
+    }
+
+    /// Copy the pairs that are currently touching or overlapping, see `Pairs`.
+    pub fn store_pairs(&self, world: &World) -> Pairs {
+        Pairs {
+            contacts: world.read_resource::<ContactPairs>().0.clone(),
+            sensors: world.read_resource::<SensorOverlaps>().0.clone(),
+        }
+    }
+
+    /// Replace the pairs that are currently touching or overlapping, see `Pairs`.
+    pub fn load_pairs(&self, world: &World, pairs: &Pairs) {
+        world.write_resource::<ContactPairs>().0 = pairs.contacts.clone();
+        world.write_resource::<SensorOverlaps>().0 = pairs.sensors.clone();
+    }
+
+    /// End the contacts and sensor overlaps of entities that are about to be removed, i.e. that
+    /// are tagged with `entity::Remove`, and run the callbacks. This needs to happen before the
+    /// entities are deleted, so that the callbacks can still look at them.
+    pub fn run_removals(&mut self, world: &World) -> Result<(), repl::Error> {
+        {
+            let repl_id = world.read::<repl::Id>();
+            let remove = world.read::<entity::Remove>();
+
+            let is_removed =
+                |&(a, b): &(Entity, Entity)| remove.get(a).is_some() || remove.get(b).is_some();
+            let pair_key = |&(a, b): &(Entity, Entity)| {
+                let (key_a, key_b) = (order_key(&repl_id, a), order_key(&repl_id, b));
+                (key_a.min(key_b), key_a.max(key_b))
+            };
+
+            let mut contact_pairs = world.write_resource::<ContactPairs>();
+            let mut ended = contact_pairs
+                .0
+                .iter()
+                .cloned()
+                .filter(&is_removed)
+                .collect::<Vec<_>>();
+            ended.sort_by_key(&pair_key);
+
+            for pair in ended {
+                contact_pairs.0.remove(&pair);
+                world
+                    .write_resource::<ContactEvents>()
+                    .0
+                    .push(interaction::ContactEvent::End(pair.0, pair.1));
+            }
+
+            let mut sensor_overlaps = world.write_resource::<SensorOverlaps>();
+            let mut exited = sensor_overlaps
+                .0
+                .iter()
+                .cloned()
+                .filter(&is_removed)
+                .collect::<Vec<_>>();
+            exited.sort_by_key(&pair_key);
+
+            for pair in exited {
+                sensor_overlaps.0.remove(&pair);
+                world
+                    .write_resource::<SensorEvents>()
+                    .0
+                    .push(interaction::SensorEvent {
+                        a: pair.0,
+                        b: pair.1,
+                        phase: interaction::SensorPhase::Exit,
+                    });
+            }
+        }
+
+        self.run_interaction_events(world)
     }
 
     pub fn run_interaction_events(&self, world: &World) -> Result<(), repl::Error> {
@@ -118,10 +375,150 @@ impl Runner {
         for event in interactions.0.drain(..) {
             interaction::run(world, &event)?;
         }
+
+        let mut contact_events = world.write_resource::<ContactEvents>();
+        for event in contact_events.0.drain(..) {
+            interaction::run_contact_event(world, &event)?;
+        }
+
+        let mut sensor_events = world.write_resource::<SensorEvents>();
+        for event in sensor_events.0.drain(..) {
+            interaction::run_sensor(world, &event)?;
+        }
+
         Ok(())
     }
 }
 
+/// Number of events that were waiting to be run before a step, so that we can tell which events
+/// were detected in the step.
+struct NumEvents {
+    interactions: usize,
+    contact_events: usize,
+    sensor_events: usize,
+}
+
+impl NumEvents {
+    fn new(world: &World) -> NumEvents {
+        NumEvents {
+            interactions: world.read_resource::<InteractionEvents>().0.len(),
+            contact_events: world.read_resource::<ContactEvents>().0.len(),
+            sensor_events: world.read_resource::<SensorEvents>().0.len(),
+        }
+    }
+}
+
+/// Describe the state of the simulation at the end of a step for the `record::Recorder`.
+fn record_step(world: &World, run: u32, substep: u32, num_events: &NumEvents) -> record::Step {
+    let entities = world.entities();
+    let repl_id = world.read_storage::<repl::Id>();
+    let dynamic = world.read_storage::<Dynamic>();
+    let active = world.read_storage::<Active>();
+    let sleeping = world.read_storage::<Sleeping>();
+    let position = world.read_storage::<Position>();
+    let orientation = world.read_storage::<Orientation>();
+    let velocity = world.read_storage::<Velocity>();
+    let angular_velocity = world.read_storage::<AngularVelocity>();
+    let constraints = world.read_resource::<Constraints>();
+    let lambdas = world.read_resource::<Lambdas>();
+
+    let entity_ref = |entity| record::EntityRef::new(&repl_id, entity);
+    let point = |p: &Point2<f32>| [p.x, p.y];
+    let vector = |v: &Vector2<f32>| [v.x, v.y];
+
+    let mut bodies = Vec::new();
+    for (entity, _, _, position, orientation) in
+        (&*entities, &dynamic, &active, &position, &orientation).join()
+    {
+        bodies.push(record::Body {
+            entity: entity_ref(entity),
+            pos: point(&position.0),
+            angle: orientation.0,
+            vel: velocity.get(entity).map(|v| vector(&v.0)),
+            angular_vel: angular_velocity.get(entity).map(|v| v.0),
+            sleeping: sleeping.get(entity).is_some(),
+        });
+    }
+
+    // Entity indices depend on the order of creation, so sort by the recorded ids instead
+    bodies.sort_by_key(|body| body.entity);
+
+    let mut contacts = Vec::new();
+    let mut constraint_records = Vec::new();
+
+    for (c, &lambda) in constraints.0.iter().zip(&lambdas.0) {
+        if let constraint::Def::Contact { normal, margin, .. } = c.def {
+            contacts.push(record::Contact {
+                a: entity_ref(c.entity_a),
+                b: entity_ref(c.entity_b),
+                normal: vector(&normal),
+                margin,
+            });
+        }
+
+        let pose_a = constraint::Pose::from_entity(&position, &orientation, c.entity_a);
+        let pose_b = constraint::Pose::from_entity(&position, &orientation, c.entity_b);
+        let (value, jacobian) = match (pose_a, pose_b) {
+            (Ok(pose_a), Ok(pose_b)) => {
+                let (value, j) = c.def.calculate(&pose_a, &pose_b);
+                (Some(value), Some([j[0], j[1], j[2], j[3], j[4], j[5]]))
+            }
+            _ => (None, None),
+        };
+
+        constraint_records.push(record::Constraint {
+            a: entity_ref(c.entity_a),
+            b: entity_ref(c.entity_b),
+            kind: c.def.name().to_string(),
+            value,
+            jacobian,
+            lambda,
+        });
+    }
+
+    let mut events = Vec::new();
+
+    for event in &world.read_resource::<InteractionEvents>().0[num_events.interactions..] {
+        events.push(record::Event::Interaction {
+            a: entity_ref(event.a.entity),
+            b: entity_ref(event.b.entity),
+            pos: point(&event.pos),
+            normal: vector(&event.normal),
+        });
+    }
+    for event in &world.read_resource::<ContactEvents>().0[num_events.contact_events..] {
+        events.push(match *event {
+            interaction::ContactEvent::Begin(ref event) => {
+                record::Event::ContactBegin(entity_ref(event.a.entity), entity_ref(event.b.entity))
+            }
+            interaction::ContactEvent::Persist(ref event) => record::Event::ContactPersist(
+                entity_ref(event.a.entity),
+                entity_ref(event.b.entity),
+            ),
+            interaction::ContactEvent::End(a, b) => {
+                record::Event::ContactEnd(entity_ref(a), entity_ref(b))
+            }
+        });
+    }
+    for event in &world.read_resource::<SensorEvents>().0[num_events.sensor_events..] {
+        events.push(record::Event::Sensor(
+            entity_ref(event.a),
+            entity_ref(event.b),
+            format!("{:?}", event.phase),
+        ));
+    }
+
+    record::Step {
+        tick: world.read_resource::<record::Recorder>().tick(),
+        run,
+        substep,
+        bodies,
+        contacts,
+        constraints: constraint_records,
+        events,
+    }
+}
+
 pub fn normalize_angle(angle: f32) -> f32 {
     angle % (2.0 * f32::consts::PI)
     //angle
@@ -156,7 +553,7 @@ impl<'a> System<'a> for FrictionForceSys {
     );
 
     fn run(&mut self, (game_info, filter, inv_mass, friction, mut velocity): Self::SystemData) {
-        let dt = game_info.tick_duration_secs();
+        let dt = game_info.physics_step_secs();
 
         for (_, inv_mass, friction, velocity) in
             (filter.join(), &inv_mass, &friction, &mut velocity).join()
@@ -188,7 +585,7 @@ impl<'a> System<'a> for DragForceSys {
     );
 
     fn run(&mut self, (game_info, filter, drag, mut velocity): Self::SystemData) {
-        let dt = game_info.tick_duration_secs();
+        let dt = game_info.physics_step_secs();
 
         for (_, drag, velocity) in (filter.join(), &drag, &mut velocity).join() {
             let speed = norm(&velocity.0);
@@ -295,8 +692,14 @@ struct CorrectVelocitySys;
 #[derive(SystemData)]
 struct CorrectVelocityData<'a> {
     game_info: Fetch<'a, GameInfo>,
+    constraints: Fetch<'a, Constraints>,
+    lambdas: Fetch<'a, Lambdas>,
 
+    entities: Entities<'a>,
     filter: Filter<'a>,
+    inv_mass: ReadStorage<'a, InvMass>,
+    inv_angular_mass: ReadStorage<'a, InvAngularMass>,
+    material: ReadStorage<'a, Material>,
     position: ReadStorage<'a, Position>,
     old_position: ReadStorage<'a, OldPosition>,
     orientation: ReadStorage<'a, Orientation>,
@@ -306,6 +709,88 @@ struct CorrectVelocityData<'a> {
     angular_velocity: WriteStorage<'a, AngularVelocity>,
 }
 
+impl<'a> CorrectVelocityData<'a> {
+    fn body_velocity(&self, entity: Entity) -> constraint::BodyVelocity {
+        constraint::BodyVelocity {
+            linear: self.velocity.get(entity).map_or(zero(), |v| v.0),
+            angular: self.angular_velocity.get(entity).map_or(0.0, |v| v.0),
+        }
+    }
+
+    /// Apply dynamic friction and restitution to the contacts between entities that have a
+    /// `Material`.
+    fn apply_materials(
+        &mut self,
+        dt: f32,
+        old_velocity: &BTreeMap<Entity, constraint::BodyVelocity>,
+    ) {
+        for (c, &lambda) in self.constraints.0.iter().zip(&self.lambdas.0) {
+            match c.def {
+                constraint::Def::Contact { .. } if lambda != 0.0 => {}
+                _ => continue,
+            }
+
+            let material = match constraint::ContactMaterial::combine(
+                self.material.get(c.entity_a),
+                self.material.get(c.entity_b),
+            ) {
+                Some(material) => material,
+                None => continue,
+            };
+
+            // TODO: repl unwrap
+            let pose_a =
+                constraint::Pose::from_entity(&self.position, &self.orientation, c.entity_a)
+                    .unwrap();
+            let pose_b =
+                constraint::Pose::from_entity(&self.position, &self.orientation, c.entity_b)
+                    .unwrap();
+            let mass_a = solver_mass(
+                &self.filter,
+                &self.inv_mass,
+                &self.inv_angular_mass,
+                c.entity_a,
+                &c.vars_a,
+            );
+            let mass_b = solver_mass(
+                &self.filter,
+                &self.inv_mass,
+                &self.inv_angular_mass,
+                c.entity_b,
+                &c.vars_b,
+            );
+            let vel_a = self.body_velocity(c.entity_a);
+            let vel_b = self.body_velocity(c.entity_b);
+            let old_vel_a = old_velocity.get(&c.entity_a).unwrap_or(&vel_a).clone();
+            let old_vel_b = old_velocity.get(&c.entity_b).unwrap_or(&vel_b).clone();
+
+            let result = constraint::solve_contact_velocity(
+                &c.def,
+                &material,
+                lambda,
+                dt,
+                (&pose_a, &mass_a, &vel_a, &old_vel_a),
+                (&pose_b, &mass_b, &vel_b, &old_vel_b),
+            );
+
+            if let Some((new_vel_a, new_vel_b)) = result {
+                if mass_a.inv > 0.0 {
+                    self.velocity.insert(c.entity_a, Velocity(new_vel_a.linear));
+                }
+                if mass_b.inv > 0.0 {
+                    self.velocity.insert(c.entity_b, Velocity(new_vel_b.linear));
+                }
+                if mass_a.inv_angular > 0.0 {
+                    self.angular_velocity.insert(c.entity_a, AngularVelocity(new_vel_a.angular));
+                }
+                if mass_b.inv_angular > 0.0 {
+                    self.angular_velocity.insert(c.entity_b, AngularVelocity(new_vel_b.angular));
+                }
+            }
+        }
+    }
+}
+
 impl<'a> System<'a> for CorrectVelocitySys {
     type SystemData = CorrectVelocityData<'a>;
 
@@ -313,7 +798,13 @@ impl<'a> System<'a> for CorrectVelocitySys {
     fn run(&mut self, mut data: Self::SystemData) {
         profile!("correct velocity");
 
-        let dt = data.game_info.tick_duration_secs();
+        let dt = data.game_info.physics_step_secs();
+
+        // Velocities before the solver moved the bodies, which determine how strongly they bounce
+        let old_velocity = (&*data.entities, data.filter.join())
+            .join()
+            .map(|(entity, _)| (entity, data.body_velocity(entity)))
+            .collect::<BTreeMap<_, _>>();
 
         for (_, position, old_position, velocity) in
             (data.filter.join(), &data.position, &data.old_position, &mut data.velocity).join()
@@ -332,6 +823,8 @@ impl<'a> System<'a> for CorrectVelocitySys {
 
             angular_velocity.0 = d / dt;
         }
+
+        data.apply_materials(dt, &old_velocity);
     }
 }
 
@@ -340,9 +833,11 @@ struct IntegrateForceSys;
 impl<'a> System<'a> for IntegrateForceSys {
     type SystemData = (
         Fetch<'a, GameInfo>,
+        Entities<'a>,
         Filter<'a>,
         ReadStorage<'a, InvMass>,
         ReadStorage<'a, Force>,
+        ReadStorage<'a, Material>,
         WriteStorage<'a, Velocity>,
         WriteStorage<'a, AngularVelocity>,
     );
@@ -352,16 +847,19 @@ impl<'a> System<'a> for IntegrateForceSys {
         &mut self,
         (
             game_info,
+            entities,
             filter,
             inv_mass,
             force,
+            material,
             mut velocity,
             mut ang_velocity,
         ): Self::SystemData,
     ) {
-        let dt = game_info.tick_duration_secs();
+        let dt = game_info.physics_step_secs();
 
-        for (_, inv_mass, force, velocity, ang_velocity) in (
+        for (entity, _, inv_mass, force, velocity, ang_velocity) in (
+            &*entities,
             filter.join(),
             &inv_mass,
             &force,
@@ -371,15 +869,17 @@ impl<'a> System<'a> for IntegrateForceSys {
         {
             velocity.0 += force.0 * inv_mass.0 * dt;
 
-            // TODO: Angular friction
-            if ang_velocity.0.abs() > 0.01 {
-                let positive = ang_velocity.0.is_sign_positive();
-                let signum = ang_velocity.0.signum();
-                ang_velocity.0 -= 100.0 * signum * dt;
-                if ang_velocity.0.is_sign_positive() != positive {
-                    ang_velocity.0 = 0.0;
-                }
+            let positive = ang_velocity.0.is_sign_positive();
+
+            if ang_velocity.0.abs() <= MIN_ANGULAR_SPEED {
+                ang_velocity.0 = 0.0;
+            } else if let Some(material) = material.get(entity) {
+                ang_velocity.0 -= ang_velocity.0 * material.angular_drag * dt;
             } else {
+                ang_velocity.0 -= DEFAULT_ANGULAR_DECELERATION * ang_velocity.0.signum() * dt;
+            }
+
+            if ang_velocity.0.is_sign_positive() != positive {
                 ang_velocity.0 = 0.0;
             }
         }
@@ -388,206 +888,620 @@ impl<'a> System<'a> for IntegrateForceSys {
 
 struct HandleContactsSys;
 
-impl<'a> System<'a> for HandleContactsSys {
-    type SystemData = (
-        Fetch<'a, CollisionWorld>,
-        Fetch<'a, interaction::Handlers>,
-        FetchMut<'a, InteractionEvents>,
-        FetchMut<'a, Constraints>,
-        Filter<'a>,
-        ReadStorage<'a, entity::Meta>,
-        ReadStorage<'a, repl::Id>,
-        ReadStorage<'a, Velocity>,
-    );
+#[derive(SystemData)]
+struct HandleContactsData<'a> {
+    game_info: Fetch<'a, GameInfo>,
+    collision_world: Fetch<'a, CollisionWorld>,
+    interaction_handlers: Fetch<'a, interaction::Handlers>,
+    interactions: FetchMut<'a, InteractionEvents>,
+    contact_events: FetchMut<'a, ContactEvents>,
+    contact_pairs: FetchMut<'a, ContactPairs>,
+    sensor_events: FetchMut<'a, SensorEvents>,
+    sensor_overlaps: FetchMut<'a, SensorOverlaps>,
+    constraints: FetchMut<'a, Constraints>,
+
+    entities: Entities<'a>,
+    filter: Filter<'a>,
+    ccd: ReadStorage<'a, Ccd>,
+    meta: ReadStorage<'a, entity::Meta>,
+    repl_id: ReadStorage<'a, repl::Id>,
+    velocity: ReadStorage<'a, Velocity>,
+    old_position: ReadStorage<'a, OldPosition>,
+    orientation: ReadStorage<'a, Orientation>,
+    shape: ReadStorage<'a, collision::Shape>,
+    object: ReadStorage<'a, collision::Object>,
 
-    #[cfg_attr(rustfmt, rustfmt_skip)] // rustfmt bug
-    fn run(
+    position: WriteStorage<'a, Position>,
+}
+
+impl<'a> HandleContactsData<'a> {
+    /// Should we consider contacts between these two entities?
+    fn may_interact(&self, entity_a: Entity, entity_b: Entity) -> bool {
+        // Only consider contacts where at least one object is currently being simulated
+        if !self.filter.filter(entity_a) && !self.filter.filter(entity_b) {
+            return false;
+        }
+
+        // Let's not have a player's entities collide with each other just yet
+        match (self.repl_id.get(entity_a), self.repl_id.get(entity_b)) {
+            (Some(&repl::Id((owner_a, _))), Some(&repl::Id((owner_b, _))))
+                if owner_a == owner_b => false,
+            _ => true,
+        }
+    }
+
+    /// Should we keep remembering a pair that we did not see in this step? We only get to see
+    /// pairs with at least one simulated entity, so pairs of active entities that are both not
+    /// simulated are kept as they are.
+    fn keeps_pair(&self, entity_a: Entity, entity_b: Entity) -> bool {
+        !self.filter.filter(entity_a) && !self.filter.filter(entity_b) &&
+            self.filter.active.get(entity_a).is_some() &&
+            self.filter.active.get(entity_b).is_some()
+    }
+
+    /// Canonical order of pairs of entities, regardless of the order of the entities in the pair.
+    fn pair_key(&self, a: Entity, b: Entity) -> (OrderKey, OrderKey) {
+        let (key_a, key_b) = (order_key(&self.repl_id, a), order_key(&self.repl_id, b));
+        (key_a.min(key_b), key_a.max(key_b))
+    }
+
+    /// Add a constraint for a contact if the entities' interaction asks for it, and record the
+    /// collision event.
+    fn add_contact(
         &mut self,
-        (
-            collision_world,
-            interaction_handlers,
-            mut interactions,
-            mut constraints,
-            filter,
-            meta,
-            repl_id,
-            velocity,
-        ): Self::SystemData
+        entity_a: Entity,
+        entity_b: Entity,
+        isometry_a: &Isometry2<f32>,
+        isometry_b: &Isometry2<f32>,
+        contact: &Contact<Point2<f32>>,
+    ) -> Option<interaction::Event> {
+        let action = interaction::get_action(
+            &self.interaction_handlers,
+            &self.meta,
+            entity_a,
+            entity_b,
+        );
+
+        // TODO: Easier way to get object-space contact coordinates?
+        let object_pos_a = isometry_a.inverse() * contact.world1;
+        let object_pos_b = isometry_b.inverse() * contact.world2;
+
+        if let Some(action) = action {
+            match action {
+                interaction::Action::PreventOverlap { rotate_a, rotate_b } => {
+                    // Try to resolve the overlap with a constraint
+                    let constraint = Constraint {
+                        def: constraint::Def::Contact {
+                            normal: contact.normal.unwrap(),
+                            margin: self.game_info.physics.contact_margin,
+                            object_pos_a,
+                            object_pos_b,
+                        },
+                        compliance: 0.0,
+                        damping: 0.0,
+                        entity_a,
+                        entity_b,
+                        vars_a: constraint::Vars {
+                            pos: true,
+                            angle: rotate_a,
+                        },
+                        vars_b: constraint::Vars {
+                            pos: true,
+                            angle: rotate_b,
+                        },
+                    };
+                    self.constraints.add(constraint);
+                }
+                interaction::Action::Sensor => {
+                    // Sensors are handled separately in `update_sensors`
+                    return None;
+                }
+            }
+        }
+
+        // Record the collision event
+        let info_a = interaction::EntityInfo {
+            entity: entity_a,
+            object_pos: object_pos_a,
+            vel: self.velocity.get(entity_a).map(|v| v.0),
+        };
+        let info_b = interaction::EntityInfo {
+            entity: entity_b,
+            object_pos: object_pos_b,
+            vel: self.velocity.get(entity_b).map(|v| v.0),
+        };
+        let event = interaction::Event {
+            a: info_a,
+            b: info_b,
+            // TODO: What is the difference between `world1` and `world2` here?
+            pos: contact.world1,
+            normal: contact.normal.unwrap(),
+        };
+        self.interactions.0.push(event.clone());
+
+        Some(event)
+    }
+
+    /// Remember the contact if it is the first one in this step for a pair of entities that has
+    /// contact lifecycle callbacks.
+    fn track_contact(
+        &self,
+        touching: &mut BTreeMap<(Entity, Entity), interaction::Event>,
+        event: interaction::Event,
     ) {
-        profile!("handle contacts");
+        let (a, b) = (event.a.entity, event.b.entity);
 
-        for (oa, ob, gen) in collision_world.contact_pairs() {
-            let mut contacts = Vec::new();
-            gen.contacts(&mut contacts);
+        if interaction::has_lifecycle(&self.interaction_handlers, &self.meta, a, b) {
+            touching.entry((a.min(b), a.max(b))).or_insert(event);
+        }
+    }
 
-            for contact in &contacts {
-                let entity_a = *oa.data();
-                let entity_b = *ob.data();
+    /// Compare the pairs with contact lifecycle callbacks that are touching in this step with the
+    /// previous ones, and record the resulting contact events. For each pair, we are given the
+    /// first contact of this step.
+    fn update_contacts(&mut self, touching: BTreeMap<(Entity, Entity), interaction::Event>) {
+        let previous = mem::replace(&mut self.contact_pairs.0, BTreeSet::new());
+        let num_events = self.contact_events.0.len();
+
+        for &(a, b) in &previous {
+            if !self.entities.is_alive(a) || !self.entities.is_alive(b) {
+                // Entities that are removed with `entity::Remove` have been ended already in
+                // `Runner::run_removals`. Others have been deleted directly, and there is nothing
+                // left that we could give to the callbacks.
+                continue;
+            }
 
-                // Only consider contacts where at least one object is currently being simulated
-                // FIXME: We should be able to get this with an ncollide filter!
-                if !filter.filter(entity_a) && !filter.filter(entity_b) {
-                    continue;
-                }
+            if touching.contains_key(&(a, b)) {
+                // Handled below
+            } else if self.keeps_pair(a, b) {
+                self.contact_pairs.0.insert((a, b));
+            } else {
+                self.contact_events.0.push(interaction::ContactEvent::End(a, b));
+            }
+        }
+
+        for (pair, event) in touching {
+            if previous.contains(&pair) {
+                self.contact_events.0.push(interaction::ContactEvent::Persist(event));
+            } else {
+                self.contact_events.0.push(interaction::ContactEvent::Begin(event));
+            }
+
+            self.contact_pairs.0.insert(pair);
+        }
+
+        // The pairs are ordered by entity, so bring the events into canonical order
+        let mut events = self.contact_events.0.split_off(num_events);
+        events.sort_by_key(|event| match *event {
+            interaction::ContactEvent::Begin(ref event) |
+            interaction::ContactEvent::Persist(ref event) => {
+                self.pair_key(event.a.entity, event.b.entity)
+            }
+            interaction::ContactEvent::End(a, b) => self.pair_key(a, b),
+        });
+        self.contact_events.0.extend(events);
+    }
+
+    /// Compare the sensor pairs that are overlapping in this step with the previous ones, and
+    /// record the resulting sensor events.
+    fn update_sensors(&mut self, overlaps: BTreeSet<(Entity, Entity)>) {
+        let previous = mem::replace(&mut self.sensor_overlaps.0, BTreeSet::new());
+        let num_events = self.sensor_events.0.len();
+
+        for &(a, b) in &previous {
+            if !self.entities.is_alive(a) || !self.entities.is_alive(b) {
+                // See `update_contacts`
+                continue;
+            }
+
+            if overlaps.contains(&(a, b)) {
+                self.sensor_events.0.push(interaction::SensorEvent {
+                    a,
+                    b,
+                    phase: interaction::SensorPhase::Stay,
+                });
+            } else if self.keeps_pair(a, b) {
+                self.sensor_overlaps.0.insert((a, b));
+            } else {
+                self.sensor_events.0.push(interaction::SensorEvent {
+                    a,
+                    b,
+                    phase: interaction::SensorPhase::Exit,
+                });
+            }
+        }
+
+        for (a, b) in overlaps {
+            if !previous.contains(&(a, b)) {
+                self.sensor_events.0.push(interaction::SensorEvent {
+                    a,
+                    b,
+                    phase: interaction::SensorPhase::Enter,
+                });
+            }
+
+            self.sensor_overlaps.0.insert((a, b));
+        }
 
-                // Let's not have a player's entities collide with each other just yet
-                match (repl_id.get(entity_a), repl_id.get(entity_b)) {
-                    (Some(&repl::Id((owner_a, _))), Some(&repl::Id((owner_b, _))))
-                        if owner_a == owner_b => continue,
-                    _ => {},
+        let mut events = self.sensor_events.0.split_off(num_events);
+        events.sort_by_key(|event| self.pair_key(event.a, event.b));
+        self.sensor_events.0.extend(events);
+    }
+
+    /// Find the first object that a `Ccd` entity hits on its way from `OldPosition` to
+    /// `Position`. Since we only sweep the translation, the entity's rotation in this tick is
+    /// ignored. Other objects are assumed to rest at the position they have in the collision
+    /// world, which is fine for walls.
+    ///
+    /// Objects that the entity already touches at the start of the tick are ignored, leaving them
+    /// to the regular contact handling.
+    fn first_impact(&self, entity: Entity) -> Option<Impact> {
+        let old_position = self.old_position.get(entity)?.0;
+        let position = self.position.get(entity)?.0;
+        let orientation = self.orientation.get(entity)?.0;
+        let shape = &self.shape.get(entity)?.0;
+        let groups = &self.object.get(entity)?.groups;
+
+        let delta = position - old_position;
+        if norm(&delta) < MIN_SWEEP_DISTANCE {
+            return None;
+        }
+
+        let start = Isometry2::new(old_position.coords, orientation);
+        let end = Isometry2::new(position.coords, orientation);
+        let swept_aabb = shape.aabb(&start).merged(&shape.aabb(&end));
+
+        let mut first: Option<Impact> = None;
+
+        for object in self.collision_world.interferences_with_aabb(&swept_aabb, groups) {
+            let other = *object.data();
+
+            if other == entity || !self.may_interact(entity, other) {
+                continue;
+            }
+
+            // Only entities that are kept from overlapping can block each other
+            match interaction::get_action(&self.interaction_handlers, &self.meta, entity, other) {
+                Some(interaction::Action::PreventOverlap { .. }) => {}
+                _ => continue,
+            }
+
+            let toi = query::time_of_impact(
+                &start,
+                &delta,
+                &**shape,
+                object.position(),
+                &zero(),
+                &**object.shape(),
+            );
+
+            if let Some(toi) = toi {
+                // Break ties in a canonical order, independently of the collision world
+                let is_first = first.as_ref().map_or(true, |f| {
+                    toi < f.toi ||
+                        toi == f.toi &&
+                            order_key(&self.repl_id, other) < order_key(&self.repl_id, f.other)
+                });
+
+                if toi > 0.0 && toi <= 1.0 && is_first {
+                    let pos = old_position + delta * toi;
+
+                    first = Some(Impact {
+                        toi,
+                        other,
+                        pos,
+                        isometry: Isometry2::new(pos.coords, orientation),
+                        other_isometry: *object.position(),
+                    });
                 }
+            }
+        }
+
+        first
+    }
+}
 
-                let action = interaction::get_action(
-                    &interaction_handlers,
-                    &meta,
-                    entity_a,
-                    entity_b
-				);
-
-                // TODO: Easier way to get object-space contact coordinates?
-                let object_pos_a = oa.position().inverse() * contact.world1;
-                let object_pos_b = ob.position().inverse() * contact.world2;
-
-                if let Some(action) = action {
-                    match action {
-                        interaction::Action::PreventOverlap { rotate_a, rotate_b } => {
-                            // Try to resolve the overlap with a constraint
-                            let constraint = Constraint {
-                                def: constraint::Def::Contact {
-                                    normal: contact.normal.unwrap(),
-                                    margin: CONTACT_MARGIN,
-                                    object_pos_a,
-                                    object_pos_b,
-                                },
-                                stiffness: 1.0,
-                                entity_a,
-                                entity_b,
-                                vars_a: constraint::Vars {
-                                    pos: true,
-                                    angle: rotate_a,
-                                },
-                                vars_b: constraint::Vars {
-                                    pos: true,
-                                    angle: rotate_b,
-                                },
-                            };
-                            constraints.add(constraint);
-                        }
+/// The point in time at which a `Ccd` entity first touches some other object in a tick.
+struct Impact {
+    /// Time of impact as a fraction of the tick.
+    toi: f32,
+    other: Entity,
+    pos: Point2<f32>,
+    isometry: Isometry2<f32>,
+    other_isometry: Isometry2<f32>,
+}
+
+impl<'a> System<'a> for HandleContactsSys {
+    type SystemData = HandleContactsData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        profile!("handle contacts");
+
+        // First contact in this step of each pair that has contact lifecycle callbacks
+        let mut touching = BTreeMap::new();
+
+        // Continuous collision detection. Fast entities are stopped at their first impact in this
+        // tick, so that they can not tunnel through thin walls. The contact at the impact is
+        // handled like any other.
+        let mut fast_entities = (&*data.entities, data.filter.join(), &data.ccd)
+            .join()
+            .map(|(entity, _, _)| entity)
+            .collect::<Vec<_>>();
+        fast_entities.sort_by_key(|&entity| order_key(&data.repl_id, entity));
+
+        for entity in fast_entities {
+            if let Some(impact) = data.first_impact(entity) {
+                data.position.insert(entity, Position(impact.pos));
+
+                let contact = {
+                    let shape = &data.shape.get(entity).unwrap().0;
+                    let other_shape = &data.shape.get(impact.other).unwrap().0;
+
+                    query::contact(
+                        &impact.isometry,
+                        &**shape,
+                        &impact.other_isometry,
+                        &**other_shape,
+                        data.game_info.physics.contact_margin,
+                    )
+                };
+
+                if let Some(contact) = contact {
+                    let event = data.add_contact(
+                        entity,
+                        impact.other,
+                        &impact.isometry,
+                        &impact.other_isometry,
+                        &contact,
+                    );
+
+                    if let Some(event) = event {
+                        data.track_contact(&mut touching, event);
                     }
                 }
+            }
+        }
 
-                // Record the collision event
-                let info_a = interaction::EntityInfo {
-                    entity: entity_a,
-                    object_pos: object_pos_a,
-                    vel: velocity.get(entity_a).map(|v| v.0),
-                };
-                let info_b = interaction::EntityInfo {
-                    entity: entity_b,
-                    object_pos: object_pos_b,
-                    vel: velocity.get(entity_b).map(|v| v.0),
-                };
-                let event = interaction::Event {
-                    a: info_a,
-                    b: info_b,
-                    // TODO: What is the difference between `world1` and `world2` here?
-                    pos: contact.world1,
-                    normal: contact.normal.unwrap(),
-                };
-                interactions.0.push(event);
+        // Regular contacts, as found by the collision world at the start of the tick. Only the
+        // entities that are being simulated look for contacts.
+        let simulated = (&*data.entities, data.filter.join())
+            .join()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+
+        let mut pairs = data.collision_world.candidate_pairs(&simulated);
+
+        // Put the pairs in canonical order, so that the constraints do not depend on the order in
+        // which entities happen to be stored
+        for pair in &mut pairs {
+            if order_key(&data.repl_id, pair.1) < order_key(&data.repl_id, pair.0) {
+                *pair = (pair.1, pair.0);
+            }
+        }
+        pairs.sort_by_key(|&(entity_a, entity_b)| {
+            (
+                order_key(&data.repl_id, entity_a),
+                order_key(&data.repl_id, entity_b),
+            )
+        });
+
+        let mut contacts = Vec::new();
+        let mut overlaps = BTreeSet::new();
+
+        for (entity_a, entity_b) in pairs {
+            if !data.may_interact(entity_a, entity_b) {
+                continue;
+            }
+
+            let mut pair_contacts = Vec::new();
+            data.collision_world.contacts(entity_a, entity_b, &mut pair_contacts);
+
+            if pair_contacts.is_empty() {
+                continue;
+            }
+
+            let action = interaction::get_action(
+                &data.interaction_handlers,
+                &data.meta,
+                entity_a,
+                entity_b,
+            );
+
+            if action == Some(interaction::Action::Sensor) {
+                overlaps.insert((entity_a.min(entity_b), entity_a.max(entity_b)));
+                continue;
+            }
+
+            let isometry_a = *data.collision_world.get(entity_a).unwrap().position();
+            let isometry_b = *data.collision_world.get(entity_b).unwrap().position();
+
+            for contact in pair_contacts {
+                contacts.push((entity_a, entity_b, isometry_a, isometry_b, contact));
+            }
+        }
+
+        for (entity_a, entity_b, isometry_a, isometry_b, contact) in contacts {
+            let event = data.add_contact(entity_a, entity_b, &isometry_a, &isometry_b, &contact);
+
+            if let Some(event) = event {
+                data.track_contact(&mut touching, event);
             }
         }
+
+        data.update_contacts(touching);
+        data.update_sensors(overlaps);
     }
 }
 
 struct SolveConstraintsSys;
 
+#[derive(SystemData)]
+struct SolveConstraintsData<'a> {
+    game_info: Fetch<'a, GameInfo>,
+    constraints: FetchMut<'a, Constraints>,
+    lambdas: FetchMut<'a, Lambdas>,
+
+    filter: Filter<'a>,
+    repl_id: ReadStorage<'a, repl::Id>,
+    inv_mass: ReadStorage<'a, InvMass>,
+    inv_angular_mass: ReadStorage<'a, InvAngularMass>,
+    material: ReadStorage<'a, Material>,
+    old_position: ReadStorage<'a, OldPosition>,
+    old_orientation: ReadStorage<'a, OldOrientation>,
+
+    position: WriteStorage<'a, Position>,
+    orientation: WriteStorage<'a, Orientation>,
+}
+
+impl<'a> SolveConstraintsData<'a> {
+    fn body(&self, entity: Entity, vars: &constraint::Vars) -> constraint::Body {
+        let mass = solver_mass(
+            &self.filter,
+            &self.inv_mass,
+            &self.inv_angular_mass,
+            entity,
+            vars,
+        );
+
+        // TODO: repl unwrap
+        let pose = constraint::Pose::from_entity(&self.position, &self.orientation, entity)
+            .unwrap();
+        let prev_pose = constraint::Pose {
+            pos: self.old_position.get(entity).map(|p| p.0).unwrap_or(pose.pos),
+            angle: self.old_orientation.get(entity).map(|o| o.0).unwrap_or(pose.angle),
+        };
+
+        constraint::Body {
+            pose,
+            prev_pose,
+            mass,
+        }
+    }
+
+    /// Static friction of a contact constraint, if any.
+    fn static_friction(&self, c: &Constraint) -> Option<f32> {
+        match c.def {
+            constraint::Def::Contact { .. } => {}
+            _ => return None,
+        }
+
+        let material = constraint::ContactMaterial::combine(
+            self.material.get(c.entity_a),
+            self.material.get(c.entity_b),
+        )?;
+
+        if material.static_friction > 0.0 {
+            Some(material.static_friction)
+        } else {
+            None
+        }
+    }
+}
+
+fn write_poses(
+    position: &mut WriteStorage<Position>,
+    orientation: &mut WriteStorage<Orientation>,
+    c: &Constraint,
+    (a, p_new_a): (&constraint::Body, constraint::Pose),
+    (b, p_new_b): (&constraint::Body, constraint::Pose),
+) {
+    if a.mass.inv > 0.0 {
+        position.insert(c.entity_a, Position(p_new_a.pos));
+    }
+    if b.mass.inv > 0.0 {
+        position.insert(c.entity_b, Position(p_new_b.pos));
+    }
+
+    if a.mass.inv_angular > 0.0 {
+        orientation.insert(c.entity_a, Orientation(normalize_angle(p_new_a.angle)));
+    }
+    if b.mass.inv_angular > 0.0 {
+        orientation.insert(c.entity_b, Orientation(normalize_angle(p_new_b.angle)));
+    }
+}
+
 impl<'a> System<'a> for SolveConstraintsSys {
-    type SystemData = (
-        Fetch<'a, Constraints>,
-        Filter<'a>,
-        ReadStorage<'a, InvMass>,
-        ReadStorage<'a, InvAngularMass>,
-        WriteStorage<'a, Position>,
-        WriteStorage<'a, Orientation>,
-    );
+    type SystemData = SolveConstraintsData<'a>;
 
-    #[cfg_attr(rustfmt, rustfmt_skip)] // rustfmt bug
-    fn run(
-        &mut self,
-        (
-            constraints,
-            filter,
-            inv_mass,
-            inv_angular_mass,
-            mut position,
-            mut orientation,
-        ): Self::SystemData
-    ) {
+    fn run(&mut self, mut data: Self::SystemData) {
         profile!("solve");
 
-        stats::record("constraints", constraints.0.len() as f32);
-
-        for _ in 0..NUM_ITERATIONS {
-            for c in &constraints.0 {
-                let (p_new_a, p_new_b) = {
-                    // Set up input for constraint solving
-                    let m = |entity| {
-                        constraint::Mass {
-                            inv: inv_mass.get(entity).map(|m| m.0).unwrap_or(0.0),
-                            inv_angular: inv_angular_mass.get(entity).map(|m| m.0).unwrap_or(0.0),
-                        }
-                    };
+        stats::record("constraints", data.constraints.0.len() as f32);
 
-                    // TODO: repl unwrap
-                    let x_a = constraint::Pose::from_entity(
-                        &position,
-                        &orientation,
-                        c.entity_a
-                    ).unwrap();
-                    let x_b = constraint::Pose::from_entity(
-                        &position,
-                        &orientation,
-                        c.entity_b
-                    ).unwrap();
-                    let m_a = m(c.entity_a);
-                    let m_b = m(c.entity_b);
-                    let vars_a = if filter.filter(c.entity_a) {
-                        c.vars_a.clone()
-                    } else {
-                        constraint::Vars::none()
-                    };
-                    let vars_b = if filter.filter(c.entity_b) {
-                        c.vars_b.clone()
-                    } else {
-                        constraint::Vars::none()
-                    };
+        // Game logic adds constraints in the order in which it joins over entities, so solve them
+        // in canonical order. The sort is stable, keeping the order of constraints between the
+        // same entities.
+        {
+            let repl_id = &data.repl_id;
+            data.constraints.0.sort_by_key(|c| {
+                (
+                    order_key(repl_id, c.entity_a),
+                    order_key(repl_id, c.entity_b),
+                )
+            });
+        }
 
-                    constraint::solve_for_position(
-                        &c.def,
-                        c.stiffness,
-                        &x_a,
-                        &x_b,
-                        &m_a.zero_out_constants(&vars_a),
-                        &m_b.zero_out_constants(&vars_b),
-                    )
-                };
+        let dt = data.game_info.physics_step_secs();
 
-                if c.vars_a.pos {
-                    position.insert(c.entity_a, Position(p_new_a.pos));
-                }
-                if c.vars_b.pos {
-                    position.insert(c.entity_b, Position(p_new_b.pos));
-                }
+        // Accumulated Lagrange multipliers of the constraints in this time step
+        let mut lambdas = vec![0.0; data.constraints.0.len()];
 
-                if c.vars_a.angle {
-                    orientation.insert(c.entity_a, Orientation(normalize_angle(p_new_a.angle)));
+        for _ in 0..data.game_info.physics.solver_iterations {
+            for (i, c) in data.constraints.0.iter().enumerate() {
+                // Corrections are split between the two bodies by their inverse masses
+                let a = data.body(c.entity_a, &c.vars_a);
+                let b = data.body(c.entity_b, &c.vars_b);
+
+                if a.mass.is_kinematic() && b.mass.is_kinematic() {
+                    continue;
                 }
-                if c.vars_b.angle {
-                    orientation.insert(c.entity_b, Orientation(normalize_angle(p_new_b.angle)));
+
+                let (p_new_a, p_new_b) = constraint::solve_for_position(
+                    &c.def,
+                    c.compliance,
+                    c.damping,
+                    dt,
+                    &mut lambdas[i],
+                    &a,
+                    &b,
+                );
+
+                write_poses(
+                    &mut data.position,
+                    &mut data.orientation,
+                    c,
+                    (&a, p_new_a),
+                    (&b, p_new_b),
+                );
+
+                // Keep touching bodies from sliding if their materials have enough static
+                // friction. Sliding bodies are slowed down later by `CorrectVelocitySys`.
+                if let Some(static_friction) = data.static_friction(c) {
+                    let a = data.body(c.entity_a, &c.vars_a);
+                    let b = data.body(c.entity_b, &c.vars_b);
+
+                    let result = constraint::solve_static_friction(
+                        &c.def,
+                        static_friction,
+                        lambdas[i],
+                        &a,
+                        &b,
+                    );
+
+                    if let Some((p_new_a, p_new_b)) = result {
+                        write_poses(
+                            &mut data.position,
+                            &mut data.orientation,
+                            c,
+                            (&a, p_new_a),
+                            (&b, p_new_b),
+                        );
+                    }
                 }
             }
         }
+
+        data.lambdas.0 = lambdas;
     }
 }
 
@@ -615,7 +1529,7 @@ impl<'a> System<'a> for IntegrateVelocitySys {
             mut orientation
         ): Self::SystemData
     ) {
-        let dt = game_info.tick_duration_secs();
+        let dt = game_info.physics_step_secs();
 
         for (_, velocity, position) in (
             filter.join(),
@@ -638,3 +1552,765 @@ impl<'a> System<'a> for IntegrateVelocitySys {
         }
     }
 }
+
+/// Set the velocity of `Kinematic` bodies with a `KinematicPath`, so that they head for their
+/// current waypoint without overshooting it in this tick.
+struct KinematicPathSys;
+
+impl<'a> System<'a> for KinematicPathSys {
+    type SystemData = (
+        Fetch<'a, GameInfo>,
+        ReadStorage<'a, Kinematic>,
+        ReadStorage<'a, Active>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, KinematicPath>,
+        WriteStorage<'a, Velocity>,
+    );
+
+    fn run(
+        &mut self,
+        (game_info, kinematic, active, position, mut path, mut velocity): Self::SystemData,
+    ) {
+        let dt = game_info.tick_duration_secs();
+
+        for (_, _, position, path, velocity) in
+            (&kinematic, &active, &position, &mut path, &mut velocity).join()
+        {
+            if path.waypoints.is_empty() {
+                velocity.0 = zero();
+                continue;
+            }
+
+            path.next %= path.waypoints.len();
+
+            // Head for the following waypoint once we reach the current one in this tick
+            let mut delta = path.waypoints[path.next] - position.0;
+            if norm(&delta) <= path.speed * dt {
+                path.next = (path.next + 1) % path.waypoints.len();
+                delta = path.waypoints[path.next] - position.0;
+            }
+
+            let distance = norm(&delta);
+
+            velocity.0 = if distance > 0.0 {
+                delta / distance * path.speed.min(distance / dt)
+            } else {
+                zero()
+            };
+        }
+    }
+}
+
+struct MoveKinematicSys;
+
+#[derive(SystemData)]
+struct MoveKinematicData<'a> {
+    game_info: Fetch<'a, GameInfo>,
+
+    entities: Entities<'a>,
+    kinematic: ReadStorage<'a, Kinematic>,
+    active: ReadStorage<'a, Active>,
+    velocity: ReadStorage<'a, Velocity>,
+    angular_velocity: ReadStorage<'a, AngularVelocity>,
+
+    position: WriteStorage<'a, Position>,
+    orientation: WriteStorage<'a, Orientation>,
+    old_position: WriteStorage<'a, OldPosition>,
+    old_orientation: WriteStorage<'a, OldOrientation>,
+}
+
+impl<'a> System<'a> for MoveKinematicSys {
+    type SystemData = MoveKinematicData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let dt = data.game_info.tick_duration_secs();
+
+        // The old pose is kept for the whole tick, so that the constraint solver sees the
+        // kinematic bodies' motion when running each player's input.
+        for (entity, _, _, velocity, position) in (
+            &*data.entities,
+            &data.kinematic,
+            &data.active,
+            &data.velocity,
+            &mut data.position,
+        ).join()
+        {
+            data.old_position.insert(entity, OldPosition(position.0));
+            position.0 += velocity.0 * dt;
+        }
+
+        for (entity, _, _, angular_velocity, orientation) in (
+            &*data.entities,
+            &data.kinematic,
+            &data.active,
+            &data.angular_velocity,
+            &mut data.orientation,
+        ).join()
+        {
+            data.old_orientation.insert(entity, OldOrientation(orientation.0));
+            orientation.0 += angular_velocity.0 * dt;
+        }
+    }
+}
+
+/// Moving bodies wake up the islands of sleeping bodies that they are constrained with. Sleeping
+/// bodies that are given a velocity, e.g. by game logic, also wake up.
+struct WakeSys;
+
+#[derive(SystemData)]
+struct WakeData<'a> {
+    constraints: Fetch<'a, Constraints>,
+
+    entities: Entities<'a>,
+    dynamic: ReadStorage<'a, Dynamic>,
+    kinematic: ReadStorage<'a, Kinematic>,
+    active: ReadStorage<'a, Active>,
+    update: ReadStorage<'a, Update>,
+    velocity: ReadStorage<'a, Velocity>,
+    angular_velocity: ReadStorage<'a, AngularVelocity>,
+    position: ReadStorage<'a, Position>,
+    orientation: ReadStorage<'a, Orientation>,
+
+    sleeping: WriteStorage<'a, Sleeping>,
+    rest_ticks: WriteStorage<'a, RestTicks>,
+    old_position: WriteStorage<'a, OldPosition>,
+    old_orientation: WriteStorage<'a, OldOrientation>,
+}
+
+impl<'a> WakeData<'a> {
+    fn is_moving(&self, entity: Entity) -> bool {
+        let speed = self.velocity.get(entity).map_or(0.0, |v| norm(&v.0));
+        let angular_speed = self.angular_velocity.get(entity).map_or(0.0, |v| v.0.abs());
+
+        speed >= SLEEP_SPEED || angular_speed >= SLEEP_ANGULAR_SPEED
+    }
+
+    /// Is this entity moving on its own, so that it can wake up the bodies it touches?
+    fn is_awake(&self, entity: Entity) -> bool {
+        if self.kinematic.get(entity).is_some() {
+            return self.active.get(entity).is_some();
+        }
+
+        self.dynamic.get(entity).is_some() && self.active.get(entity).is_some() &&
+            self.update.get(entity).is_some() && self.sleeping.get(entity).is_none()
+    }
+}
+
+impl<'a> System<'a> for WakeSys {
+    type SystemData = WakeData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let mut islands = BTreeSet::new();
+
+        for (entity, sleeping) in (&*data.entities, &data.sleeping).join() {
+            if data.is_moving(entity) {
+                islands.insert(sleeping.island);
+            }
+        }
+
+        for c in &data.constraints.0 {
+            for &(entity, other) in &[(c.entity_a, c.entity_b), (c.entity_b, c.entity_a)] {
+                if let Some(sleeping) = data.sleeping.get(other) {
+                    if data.is_awake(entity) && data.is_moving(entity) {
+                        islands.insert(sleeping.island);
+                    }
+                }
+            }
+        }
+
+        if islands.is_empty() {
+            return;
+        }
+
+        let woken = (&*data.entities, &data.sleeping)
+            .join()
+            .filter(|&(_, sleeping)| islands.contains(&sleeping.island))
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+
+        for entity in woken {
+            data.sleeping.remove(entity);
+            data.rest_ticks.insert(entity, RestTicks(0));
+
+            // The old pose may be from before the body fell asleep
+            if let Some(position) = data.position.get(entity) {
+                data.old_position.insert(entity, OldPosition(position.0));
+            }
+            if let Some(orientation) = data.orientation.get(entity) {
+                data.old_orientation.insert(entity, OldOrientation(orientation.0));
+            }
+        }
+    }
+}
+
+/// Put islands of resting bodies to sleep, once per tick.
+struct SleepSys;
+
+#[derive(SystemData)]
+struct SleepData<'a> {
+    game_info: Fetch<'a, GameInfo>,
+    island_pairs: Fetch<'a, IslandPairs>,
+    next_island: FetchMut<'a, NextIsland>,
+
+    entities: Entities<'a>,
+    repl_id: ReadStorage<'a, repl::Id>,
+    dynamic: ReadStorage<'a, Dynamic>,
+    active: ReadStorage<'a, Active>,
+
+    sleeping: WriteStorage<'a, Sleeping>,
+    rest_ticks: WriteStorage<'a, RestTicks>,
+    velocity: WriteStorage<'a, Velocity>,
+    angular_velocity: WriteStorage<'a, AngularVelocity>,
+}
+
+impl<'a> System<'a> for SleepSys {
+    type SystemData = SleepData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let sleep_ticks = (SLEEP_SECS / data.game_info.tick_duration_secs()).ceil() as u32;
+
+        // Count how long the bodies have been resting. Entities owned by a player are predicted
+        // by that player's client, so they stay awake.
+        let mut bodies = (&*data.entities, &data.dynamic, &data.active, !&data.sleeping)
+            .join()
+            .map(|(entity, _, _, _)| entity)
+            .filter(|&entity| {
+                data.repl_id
+                    .get(entity)
+                    .map_or(true, |id| (id.0).0 == INVALID_PLAYER_ID)
+            })
+            .collect::<Vec<_>>();
+
+        // Islands are numbered in the order of their bodies
+        bodies.sort_by_key(|&entity| order_key(&data.repl_id, entity));
+
+        for &entity in &bodies {
+            let speed = data.velocity.get(entity).map_or(0.0, |v| norm(&v.0));
+            let angular_speed = data.angular_velocity.get(entity).map_or(0.0, |v| v.0.abs());
+
+            let ticks = if speed < SLEEP_SPEED && angular_speed < SLEEP_ANGULAR_SPEED {
+                data.rest_ticks.get(entity).map_or(0, |t| t.0) + 1
+            } else {
+                0
+            };
+
+            data.rest_ticks.insert(entity, RestTicks(ticks));
+        }
+
+        // Find the islands of bodies that are connected by constraints, using union-find
+        let index = bodies
+            .iter()
+            .enumerate()
+            .map(|(i, &entity)| (entity, i))
+            .collect::<BTreeMap<_, _>>();
+        let mut parent = (0..bodies.len()).collect::<Vec<_>>();
+
+        fn find(parent: &mut Vec<usize>, i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        for &(entity_a, entity_b) in &data.island_pairs.0 {
+            if let (Some(&i), Some(&j)) = (index.get(&entity_a), index.get(&entity_b)) {
+                let root_i = find(&mut parent, i);
+                let root_j = find(&mut parent, j);
+                parent[root_i] = root_j;
+            }
+        }
+
+        let mut islands = BTreeMap::new();
+        for (i, &entity) in bodies.iter().enumerate() {
+            islands
+                .entry(find(&mut parent, i))
+                .or_insert_with(Vec::new)
+                .push(entity);
+        }
+
+        // Islands in which every body has been resting long enough fall asleep
+        for (_, members) in islands {
+            let resting = members
+                .iter()
+                .all(|&entity| data.rest_ticks.get(entity).map_or(0, |t| t.0) >= sleep_ticks);
+
+            if !resting {
+                continue;
+            }
+
+            let island = data.next_island.0;
+            data.next_island.0 = data.next_island.0.wrapping_add(1);
+
+            for entity in members {
+                data.sleeping.insert(entity, Sleeping { island });
+
+                if let Some(velocity) = data.velocity.get_mut(entity) {
+                    velocity.0 = zero();
+                }
+                if let Some(angular_velocity) = data.angular_velocity.get_mut(entity) {
+                    angular_velocity.0 = 0.0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{norm, Point2, Vector2};
+    use specs::prelude::{Entity, World};
+
+    use defs::{GameInfo, TickNum};
+    use entity;
+    use game::testing::game_info;
+    use physics::interaction::{self, Action, Callbacks, EntityInfo, SensorPhase};
+    use physics::testing::{self, BODY, WALL};
+    use physics::{Ccd, Position, Velocity};
+    use repl;
+
+    use super::{Constraints, Runner, Sleeping, SLEEP_SECS, SLEEP_SPEED};
+
+    /// Contact callbacks that have been called in the current tick.
+    #[derive(Default)]
+    struct Calls(Vec<&'static str>);
+
+    fn begin(
+        world: &World,
+        _: &EntityInfo,
+        _: &EntityInfo,
+        _: Point2<f32>,
+        _: Vector2<f32>,
+    ) -> Result<(), repl::Error> {
+        world.write_resource::<Calls>().0.push("begin");
+        Ok(())
+    }
+
+    fn persist(
+        world: &World,
+        _: &EntityInfo,
+        _: &EntityInfo,
+        _: Point2<f32>,
+        _: Vector2<f32>,
+    ) -> Result<(), repl::Error> {
+        world.write_resource::<Calls>().0.push("persist");
+        Ok(())
+    }
+
+    fn end(world: &World, _: Entity, _: Entity) -> Result<(), repl::Error> {
+        world.write_resource::<Calls>().0.push("end");
+        Ok(())
+    }
+
+    fn sensor(world: &World, _: Entity, _: Entity, phase: SensorPhase) -> Result<(), repl::Error> {
+        let call = match phase {
+            SensorPhase::Enter => "enter",
+            SensorPhase::Stay => "stay",
+            SensorPhase::Exit => "exit",
+        };
+        world.write_resource::<Calls>().0.push(call);
+        Ok(())
+    }
+
+    fn drain_calls(world: &World) -> Vec<&'static str> {
+        world.write_resource::<Calls>().0.drain(..).collect()
+    }
+
+    #[test]
+    fn test_contact_lifecycle() {
+        let game_info = game_info();
+        let mut reg = testing::registry(&game_info);
+        reg.resource(Calls::default());
+
+        // Without an action, the body passes through the wall at constant speed
+        let callbacks = Callbacks {
+            begin: Some(begin),
+            persist: Some(persist),
+            end: Some(end),
+            ..Callbacks::none()
+        };
+        interaction::set(&mut reg, BODY, WALL, None, callbacks);
+
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        let body = testing::create(
+            &mut world,
+            BODY,
+            Point2::new(-105.0, 0.0),
+            Vector2::new(20.0, 20.0),
+        );
+        world
+            .write::<Velocity>()
+            .insert(body, Velocity(Vector2::new(600.0, 0.0)));
+
+        let mut runner = Runner::new(&mut world);
+        let mut calls = Vec::new();
+
+        for tick in 1..25 {
+            testing::run_tick(&world, &mut runner);
+
+            for call in drain_calls(&world) {
+                calls.push((tick, call));
+            }
+        }
+
+        // The body moves by 10 per tick, and contacts are found at the position of the start of
+        // the tick, so it overlaps the wall from tick 6 to tick 17
+        let mut expected: Vec<(TickNum, &str)> = vec![(6, "begin")];
+        expected.extend((7..18).map(|tick| (tick, "persist")));
+        expected.push((18, "end"));
+
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn test_sensor_phases() {
+        let game_info = game_info();
+        let mut reg = testing::registry(&game_info);
+        reg.resource(Calls::default());
+        interaction::set_sensor(&mut reg, BODY, WALL, sensor);
+
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        let body = testing::create(
+            &mut world,
+            BODY,
+            Point2::new(-105.0, 0.0),
+            Vector2::new(20.0, 20.0),
+        );
+        world
+            .write::<Velocity>()
+            .insert(body, Velocity(Vector2::new(600.0, 0.0)));
+
+        let mut runner = Runner::new(&mut world);
+        let mut calls = Vec::new();
+
+        for tick in 1..25 {
+            testing::run_tick(&world, &mut runner);
+
+            for call in drain_calls(&world) {
+                calls.push((tick, call));
+            }
+        }
+
+        // Same timing as in `test_contact_lifecycle`, and sensors do not stop the body
+        let mut expected: Vec<(TickNum, &str)> = vec![(6, "enter")];
+        expected.extend((7..18).map(|tick| (tick, "stay")));
+        expected.push((18, "exit"));
+
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn test_removal_ends_pairs() {
+        let game_info = game_info();
+        let mut reg = testing::registry(&game_info);
+        reg.resource(Calls::default());
+        interaction::set_sensor(&mut reg, BODY, WALL, sensor);
+        interaction::set(
+            &mut reg,
+            BODY,
+            BODY,
+            None,
+            Callbacks {
+                begin: Some(begin),
+                end: Some(end),
+                ..Callbacks::none()
+            },
+        );
+
+        // Two resting bodies that overlap each other and the wall
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        let body_a = testing::create(&mut world, BODY, Point2::origin(), Vector2::new(20.0, 20.0));
+        testing::create(&mut world, BODY, Point2::new(5.0, 0.0), Vector2::new(20.0, 20.0));
+
+        let mut runner = Runner::new(&mut world);
+
+        testing::run_tick(&world, &mut runner);
+        let mut calls = drain_calls(&world);
+        calls.sort();
+        assert_eq!(calls, vec!["begin", "enter", "enter"]);
+
+        entity::deferred_remove(&world, body_a);
+        runner.run_removals(&world).unwrap();
+        entity::perform_removals(&mut world);
+
+        let mut calls = drain_calls(&world);
+        calls.sort();
+        assert_eq!(calls, vec!["end", "exit"]);
+
+        // The remaining body is still in the wall, and nothing is ended twice
+        testing::run_tick(&world, &mut runner);
+        assert_eq!(drain_calls(&world), vec!["stay"]);
+    }
+
+    /// Positions of a small body that is shot at a thin wall, at the end of every tick.
+    fn shoot_at_thin_wall(ccd: bool) -> Vec<f32> {
+        let game_info = game_info();
+        let mut reg = testing::registry(&game_info);
+        let action = Action::PreventOverlap {
+            rotate_a: false,
+            rotate_b: false,
+        };
+        interaction::set(&mut reg, BODY, WALL, Some(action), Callbacks::none());
+
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(4.0, 200.0));
+        let body = testing::create(
+            &mut world,
+            BODY,
+            Point2::new(-50.0, 0.0),
+            Vector2::new(10.0, 10.0),
+        );
+        world
+            .write::<Velocity>()
+            .insert(body, Velocity(Vector2::new(6000.0, 0.0)));
+        if ccd {
+            world.write::<Ccd>().insert(body, Ccd);
+        }
+
+        let mut runner = Runner::new(&mut world);
+
+        (0..10)
+            .map(|_| {
+                testing::run_tick(&world, &mut runner);
+                world.read::<Position>().get(body).unwrap().0.x
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ccd_thin_wall() {
+        // The body moves by 100 in the first tick, so without CCD it jumps over the wall
+        assert!(shoot_at_thin_wall(false)[0] > 0.0);
+
+        // With CCD, it is stopped at its impact, where it touches the wall
+        let positions = shoot_at_thin_wall(true);
+        assert!((positions[0] + 7.0).abs() < 1.0, "{:?}", positions);
+        assert!(positions.iter().all(|&x| x < -5.0), "{:?}", positions);
+    }
+
+    fn is_sleeping(world: &World, entity: Entity) -> bool {
+        world.read::<Sleeping>().get(entity).is_some()
+    }
+
+    fn sleep_ticks(game_info: &GameInfo) -> u32 {
+        (SLEEP_SECS / game_info.tick_duration_secs()).ceil() as u32
+    }
+
+    #[test]
+    fn test_fall_asleep() {
+        let game_info = game_info();
+        let mut world = testing::world(testing::registry(&game_info));
+        let size = Vector2::new(20.0, 20.0);
+
+        // The bodies are too far apart to touch each other
+        let resting = testing::create(&mut world, BODY, Point2::new(-500.0, 0.0), size);
+        let slow = testing::create(&mut world, BODY, Point2::origin(), size);
+        let fast = testing::create(&mut world, BODY, Point2::new(0.0, 500.0), size);
+        {
+            let mut velocity = world.write::<Velocity>();
+            velocity.insert(slow, Velocity(Vector2::new(SLEEP_SPEED / 2.0, 0.0)));
+            velocity.insert(fast, Velocity(Vector2::new(SLEEP_SPEED * 2.0, 0.0)));
+        }
+
+        let mut runner = Runner::new(&mut world);
+
+        for _ in 1..sleep_ticks(&game_info) {
+            testing::run_tick(&world, &mut runner);
+        }
+        for &entity in &[resting, slow, fast] {
+            assert!(!is_sleeping(&world, entity));
+        }
+
+        testing::run_tick(&world, &mut runner);
+        assert!(is_sleeping(&world, resting));
+        assert!(is_sleeping(&world, slow));
+        assert!(!is_sleeping(&world, fast));
+
+        // Bodies come to a complete stop when they fall asleep
+        assert_eq!(world.read::<Velocity>().get(slow).unwrap().0, Vector2::new(0.0, 0.0));
+
+        // Giving a body a velocity wakes it up
+        world
+            .write::<Velocity>()
+            .insert(resting, Velocity(Vector2::new(100.0, 0.0)));
+        testing::run_tick(&world, &mut runner);
+        assert!(!is_sleeping(&world, resting));
+        assert!(is_sleeping(&world, slow));
+    }
+
+    #[test]
+    fn test_wake_on_contact() {
+        let game_info = game_info();
+        let mut reg = testing::registry(&game_info);
+        let action = Action::PreventOverlap {
+            rotate_a: false,
+            rotate_b: false,
+        };
+        interaction::set(&mut reg, BODY, BODY, Some(action), Callbacks::none());
+
+        let mut world = testing::world(reg);
+        let size = Vector2::new(20.0, 20.0);
+
+        // Two resting bodies that overlap by less than the contact margin form an island, and a
+        // third one is on its way towards them
+        let touched = testing::create(&mut world, BODY, Point2::origin(), size);
+        let neighbor = testing::create(&mut world, BODY, Point2::new(19.5, 0.0), size);
+        let moving = testing::create(&mut world, BODY, Point2::new(-500.0, 0.0), size);
+        world
+            .write::<Velocity>()
+            .insert(moving, Velocity(Vector2::new(600.0, 0.0)));
+
+        let mut runner = Runner::new(&mut world);
+
+        for _ in 0..sleep_ticks(&game_info) {
+            testing::run_tick(&world, &mut runner);
+        }
+        assert!(is_sleeping(&world, touched));
+        assert!(is_sleeping(&world, neighbor));
+        assert!(!is_sleeping(&world, moving));
+
+        for _ in 0..60 {
+            testing::run_tick(&world, &mut runner);
+
+            if !is_sleeping(&world, touched) {
+                break;
+            }
+        }
+
+        // The moving body wakes up the body that it touches, and the whole island with it
+        assert!(!is_sleeping(&world, touched));
+        assert!(!is_sleeping(&world, neighbor));
+        assert!(world.read::<Position>().get(moving).unwrap().0.x > -30.0);
+    }
+
+    /// A body that slides along a wall after hitting it at an angle.
+    fn slide_along_wall(game_info: &GameInfo) -> (World, Entity) {
+        let mut reg = testing::registry(game_info);
+        let action = Action::PreventOverlap {
+            rotate_a: false,
+            rotate_b: false,
+        };
+        interaction::set(&mut reg, BODY, WALL, Some(action), Callbacks::none());
+
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        let body = testing::create(
+            &mut world,
+            BODY,
+            Point2::new(-105.0, 10.0),
+            Vector2::new(20.0, 20.0),
+        );
+        world
+            .write::<Velocity>()
+            .insert(body, Velocity(Vector2::new(600.0, -50.0)));
+
+        (world, body)
+    }
+
+    fn body_state(world: &World, body: Entity) -> (Point2<f32>, Vector2<f32>) {
+        (
+            world.read::<Position>().get(body).unwrap().0,
+            world.read::<Velocity>().get(body).unwrap().0,
+        )
+    }
+
+    #[test]
+    fn test_single_substep() {
+        let game_info = game_info();
+        assert_eq!(game_info.physics_substeps(), 1);
+
+        let (mut world_a, body_a) = slide_along_wall(&game_info);
+        let (mut world_b, body_b) = slide_along_wall(&game_info);
+        let mut runner_a = Runner::new(&mut world_a);
+        let mut runner_b = Runner::new(&mut world_b);
+
+        for tick in 1..31 {
+            testing::run_tick(&world_a, &mut runner_a);
+
+            // A tick that consists of just one step
+            testing::update_all(&world_b);
+            runner_b.run_step(&world_b);
+            world_b.write_resource::<Constraints>().0.clear();
+
+            assert_eq!(
+                body_state(&world_a, body_a),
+                body_state(&world_b, body_b),
+                "tick {}",
+                tick
+            );
+        }
+    }
+
+    #[test]
+    fn test_substeps_match_ticks() {
+        // Two substeps per tick should give the same result as twice the tick rate
+        let mut game_info_a = game_info();
+        game_info_a.physics.substeps = 2;
+        let mut game_info_b = game_info();
+        game_info_b.ticks_per_second *= 2;
+        assert_eq!(game_info_a.physics_step_secs(), game_info_b.physics_step_secs());
+
+        let (mut world_a, body_a) = slide_along_wall(&game_info_a);
+        let (mut world_b, body_b) = slide_along_wall(&game_info_b);
+        let mut runner_a = Runner::new(&mut world_a);
+        let mut runner_b = Runner::new(&mut world_b);
+
+        for tick in 1..21 {
+            testing::run_tick(&world_a, &mut runner_a);
+            testing::run_tick(&world_b, &mut runner_b);
+            testing::run_tick(&world_b, &mut runner_b);
+
+            let (pos_a, vel_a) = body_state(&world_a, body_a);
+            let (pos_b, vel_b) = body_state(&world_b, body_b);
+            assert!(norm(&(pos_a - pos_b)) < 1e-3, "tick {}", tick);
+            assert!(norm(&(vel_a - vel_b)) < 1e-3, "tick {}", tick);
+        }
+
+        // The body has hit the wall and slid along it
+        let (pos, _) = body_state(&world_a, body_a);
+        assert!(pos.x < -58.9 && pos.y < 0.0, "{:?}", pos);
+    }
+
+    /// Position of a resting body that overlaps a wall by two units, after a few ticks.
+    fn overlap_wall(contact_margin: f32) -> f32 {
+        let mut game_info = game_info();
+        game_info.physics.contact_margin = contact_margin;
+
+        let mut reg = testing::registry(&game_info);
+        let action = Action::PreventOverlap {
+            rotate_a: false,
+            rotate_b: false,
+        };
+        interaction::set(&mut reg, BODY, WALL, Some(action), Callbacks::none());
+
+        let mut world = testing::world(reg);
+        testing::create(&mut world, WALL, Point2::origin(), Vector2::new(100.0, 100.0));
+        let body = testing::create(
+            &mut world,
+            BODY,
+            Point2::new(-58.0, 0.0),
+            Vector2::new(20.0, 20.0),
+        );
+
+        let mut runner = Runner::new(&mut world);
+        for _ in 0..10 {
+            testing::run_tick(&world, &mut runner);
+        }
+
+        let x = world.read::<Position>().get(body).unwrap().0.x;
+        x
+    }
+
+    #[test]
+    fn test_contact_margin() {
+        // Overlaps within the margin are left alone, so that resting contacts are stable
+        assert!((overlap_wall(3.0) + 58.0).abs() < 1e-3);
+
+        // Larger overlaps are resolved
+        assert!(overlap_wall(1.0) < -58.5);
+    }
+}
@@ -0,0 +1,90 @@
+//! Helpers for tests that simulate a small world of boxes.
+
+use nalgebra::{zero, Point2, Vector2};
+use specs::prelude::*;
+
+use defs::GameInfo;
+use entity::{self, Meta};
+use game::state::State;
+use physics::collision::Shape;
+use physics::sim::Runner;
+use physics::{layer, AngularVelocity, Dynamic, InvAngularMass, InvMass, Orientation, Position,
+              Update, Velocity};
+use plugin::Plugin;
+use registry::Registry;
+use CorePlugin;
+
+/// Entity class of dynamic bodies. Its collision layer has the same name.
+pub const BODY: &str = "body";
+
+/// Entity class of static walls. Its collision layer has the same name.
+pub const WALL: &str = "wall";
+
+/// Registry with the core modules and the `BODY` and `WALL` classes. Bodies collide with bodies
+/// and walls. Tests set the interactions that they need before calling `world`.
+pub fn registry(game_info: &GameInfo) -> Registry {
+    let mut reg = Registry::new();
+    CorePlugin.register(&mut reg, game_info);
+
+    for &class in &[BODY, WALL] {
+        layer::add(&mut reg, class);
+        layer::set_class(&mut reg, class, class);
+    }
+    layer::set_collide(&mut reg, BODY, BODY);
+    layer::set_collide(&mut reg, BODY, WALL);
+
+    entity::register_class(&mut reg, BODY, |builder| {
+        builder
+            .with(Orientation(0.0))
+            .with(Velocity(zero()))
+            .with(AngularVelocity(0.0))
+            .with(InvMass(1.0))
+            .with(InvAngularMass(1.0))
+            .with(Dynamic)
+    });
+    entity::register_class(&mut reg, WALL, |builder| builder.with(Orientation(0.0)));
+
+    reg
+}
+
+/// Finish registration.
+pub fn world(reg: Registry) -> World {
+    State::from_registry(reg).world
+}
+
+/// Create a box of the given class and total size.
+pub fn create(world: &mut World, class: &str, position: Point2<f32>, size: Vector2<f32>) -> Entity {
+    let meta = Meta {
+        class_id: entity::get_class_id(world, class).unwrap(),
+        parent: None,
+    };
+    let object = layer::object(world, class);
+
+    entity::create(world, meta, |builder| {
+        builder
+            .with(Position(position))
+            .with(Shape::cuboid(size))
+            .with(object)
+    })
+}
+
+/// Simulate all dynamic bodies in the following physics runs.
+pub fn update_all(world: &World) {
+    let dynamic = world.read::<Dynamic>();
+    let mut update = world.write::<Update>();
+
+    update.clear();
+    for (entity, _) in (&*world.entities(), &dynamic).join() {
+        update.insert(entity, Update);
+    }
+}
+
+/// Run the physics of one tick in the same order as the server, simulating all bodies.
+pub fn run_tick(world: &World, runner: &mut Runner) {
+    update_all(world);
+
+    runner.run_kinematic(world);
+    runner.run(world);
+    runner.run_interaction_events(world).unwrap();
+    runner.run_sleep(world);
+}
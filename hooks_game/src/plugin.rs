@@ -0,0 +1,181 @@
+use std::collections::BTreeSet;
+
+use defs::GameInfo;
+use registry::Registry;
+
+/// Which side of the game we are building a `Registry` for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The server, which runs the authoritative game state.
+    Auth,
+
+    /// Clients, which view (and possibly predict) the game state replicated from the server.
+    View,
+}
+
+/// A module of the game that registers its components, resources, events, entity classes and
+/// systems. Server and clients are built from the same list of plugins, so that both sides always
+/// agree on the shared registration.
+pub trait Plugin {
+    /// Unique name of the plugin, used for declaring dependencies.
+    fn name(&self) -> &'static str;
+
+    /// Names of plugins that need to be registered before this one.
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Registration that is performed on both the server and the clients.
+    fn register(&self, _reg: &mut Registry, _game_info: &GameInfo) {}
+
+    /// Registration that is only performed on the server.
+    fn register_auth(&self, _reg: &mut Registry, _game_info: &GameInfo) {}
+
+    /// Registration that is only performed on the clients.
+    fn register_view(&self, _reg: &mut Registry, _game_info: &GameInfo) {}
+}
+
+/// An ordered list of plugins.
+#[derive(Default)]
+pub struct Plugins(Vec<Box<Plugin>>);
+
+impl Plugins {
+    pub fn new() -> Plugins {
+        Default::default()
+    }
+
+    pub fn add<P: Plugin + 'static>(&mut self, plugin: P) {
+        assert!(
+            !self.0.iter().any(|p| p.name() == plugin.name()),
+            "plugin {} was added twice",
+            plugin.name()
+        );
+
+        self.0.push(Box::new(plugin));
+    }
+
+    /// Append all plugins of another list.
+    pub fn extend(&mut self, plugins: Plugins) {
+        for plugin in plugins.0 {
+            assert!(
+                !self.0.iter().any(|p| p.name() == plugin.name()),
+                "plugin {} was added twice",
+                plugin.name()
+            );
+
+            self.0.push(plugin);
+        }
+    }
+
+    /// Register all plugins for one side, making sure that every plugin is registered after its
+    /// dependencies. Apart from that, plugins are registered in the order in which they were
+    /// added.
+    pub fn register(&self, reg: &mut Registry, side: Side, game_info: &GameInfo) {
+        for plugin in self.sorted() {
+            debug!("Registering plugin {} for {:?}", plugin.name(), side);
+
+            plugin.register(reg, game_info);
+
+            match side {
+                Side::Auth => plugin.register_auth(reg, game_info),
+                Side::View => plugin.register_view(reg, game_info),
+            }
+        }
+    }
+
+    fn get(&self, name: &str) -> &Plugin {
+        self.0
+            .iter()
+            .find(|p| p.name() == name)
+            .map(|p| &**p)
+            .unwrap_or_else(|| panic!("plugin {} is missing", name))
+    }
+
+    fn sorted(&self) -> Vec<&Plugin> {
+        let mut sorted = Vec::new();
+        let mut done = BTreeSet::new();
+        let mut visiting = Vec::new();
+
+        for plugin in &self.0 {
+            self.visit(&**plugin, &mut visiting, &mut done, &mut sorted);
+        }
+
+        sorted
+    }
+
+    fn visit<'a>(
+        &'a self,
+        plugin: &'a Plugin,
+        visiting: &mut Vec<&'static str>,
+        done: &mut BTreeSet<&'static str>,
+        sorted: &mut Vec<&'a Plugin>,
+    ) {
+        if done.contains(plugin.name()) {
+            return;
+        }
+
+        assert!(
+            !visiting.contains(&plugin.name()),
+            "cyclic plugin dependencies: {:?}",
+            visiting
+        );
+
+        visiting.push(plugin.name());
+
+        for dependency in plugin.dependencies() {
+            self.visit(self.get(dependency), visiting, done, sorted);
+        }
+
+        visiting.pop();
+        done.insert(plugin.name());
+        sorted.push(plugin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plugin, Plugins};
+
+    struct Test(&'static str, &'static [&'static str]);
+
+    impl Plugin for Test {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn dependencies(&self) -> &'static [&'static str] {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let mut plugins = Plugins::new();
+        plugins.add(Test("c", &["b"]));
+        plugins.add(Test("a", &[]));
+        plugins.add(Test("d", &[]));
+        plugins.add(Test("b", &["a"]));
+
+        let names: Vec<_> = plugins.sorted().iter().map(|p| p.name()).collect();
+        assert!(names == ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_cycle() {
+        let mut plugins = Plugins::new();
+        plugins.add(Test("a", &["b"]));
+        plugins.add(Test("b", &["a"]));
+
+        plugins.sorted();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_dependency() {
+        let mut plugins = Plugins::new();
+        plugins.add(Test("a", &["b"]));
+
+        plugins.sorted();
+    }
+}
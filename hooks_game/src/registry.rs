@@ -3,11 +3,15 @@ use bit_manager::data::BitStore;
 use shred::Resource;
 use specs::prelude::{Component, DispatcherBuilder, System, World};
 
+use defs::GameInfo;
 use event::{self, Event};
+use plugin::{Plugins, Side};
 use repl;
+use save;
 
 pub type TickFn = fn(&mut World) -> Result<(), repl::Error>;
 pub type EventHandler = fn(&mut World, &Event) -> Result<(), repl::Error>;
+pub type FreezeFn = fn(&mut World);
 
 #[derive(Default)]
 pub struct Registry {
@@ -20,6 +24,7 @@ pub struct Registry {
     pub tick_systems: DispatcherBuilder<'static, 'static>,
     pub post_tick_event_handlers: Vec<EventHandler>,
     pub removal_systems: DispatcherBuilder<'static, 'static>,
+    pub freeze_fns: Vec<FreezeFn>,
 }
 
 impl Registry {
@@ -27,6 +32,20 @@ impl Registry {
         Default::default()
     }
 
+    /// Build the registry for the server from a list of plugins.
+    pub fn auth(plugins: &Plugins, game_info: &GameInfo) -> Registry {
+        let mut reg = Registry::new();
+        plugins.register(&mut reg, Side::Auth, game_info);
+        reg
+    }
+
+    /// Build the registry for clients from a list of plugins.
+    pub fn view(plugins: &Plugins, game_info: &GameInfo) -> Registry {
+        let mut reg = Registry::new();
+        plugins.register(&mut reg, Side::View, game_info);
+        reg
+    }
+
     pub fn world(&self) -> &World {
         &self.world
     }
@@ -42,8 +61,35 @@ impl Registry {
         self.world.add_resource(res);
     }
 
-    pub fn event<T: Event + BitStore + Send>(&mut self) {
-        self.event_reg.register::<T>();
+    /// Register a resource that is replicated from the server to the clients in every tick.
+    pub fn repl_resource<T: repl::resource::Resource>(&mut self, res: T) {
+        self.resource(res);
+        self.world
+            .write_resource::<repl::resource::Types>()
+            .register::<T>();
+    }
+
+    /// Register a component that is not replicated, but should be included when saving the game
+    /// state. The component needs to be registered with `component` as well.
+    pub fn saved_component<T: save::Component>(&mut self) {
+        self.world
+            .write_resource::<save::Components>()
+            .register::<T>();
+    }
+
+    /// Register a resource that is not replicated, but should be included when saving the game
+    /// state.
+    pub fn saved_resource<T: save::Resource>(&mut self, res: T) {
+        self.resource(res);
+        self.world
+            .write_resource::<save::Resources>()
+            .register::<T>();
+    }
+
+    /// Register an event type that can be sent over the network. The name needs to be unique.
+    /// Scripts see events by this name.
+    pub fn event<T: Event + BitStore + Send>(&mut self, name: &'static str) {
+        self.event_reg.register::<T>(name);
     }
 
     pub fn event_handler_pre_tick(&mut self, f: EventHandler) {
@@ -71,4 +117,10 @@ impl Registry {
     {
         self.removal_systems.add(system, name, &[]);
     }
+
+    /// Register a function that is called once registration is finished, in the order of
+    /// registration. This can be used to assign ids that must not depend on registration order.
+    pub fn freeze_fn(&mut self, f: FreezeFn) {
+        self.freeze_fns.push(f);
+    }
 }
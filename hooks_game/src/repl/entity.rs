@@ -1,10 +1,13 @@
 use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::mem;
 
 use specs::prelude::*;
 
 use defs::{EntityClassId, EntityId, EntityIndex, GameInfo, PlayerId, INVALID_PLAYER_ID};
 use entity;
 use event::{self, Event};
+use plugin;
 use registry::Registry;
 use repl::{self, player};
 
@@ -13,8 +16,10 @@ pub use repl::snapshot::{ComponentType, EntityClass, EntityClasses, EntitySnapsh
 
 fn register<T: EntitySnapshot>(reg: &mut Registry) {
     reg.resource(EntityClasses::<T::ComponentType>(BTreeMap::new()));
+    reg.resource(PendingClasses::<T::ComponentType>(BTreeMap::new()));
+    reg.freeze_fn(freeze_classes::<T::ComponentType>);
 
-    reg.event::<RemoveOrder>();
+    reg.event::<RemoveOrder>("RemoveOrder");
 
     reg.removal_system(RemovalSys, "repl::entity");
 
@@ -22,6 +27,34 @@ fn register<T: EntitySnapshot>(reg: &mut Registry) {
     reg.resource(auth::IndexSource { next: 1 });
 }
 
+/// Plugin for replicating entities, whose replicated components are given by the snapshot type
+/// `T`.
+pub struct Plugin<T>(PhantomData<T>);
+
+impl<T> Plugin<T> {
+    pub fn new() -> Plugin<T> {
+        Plugin(PhantomData)
+    }
+}
+
+impl<T: EntitySnapshot> plugin::Plugin for Plugin<T> {
+    fn name(&self) -> &'static str {
+        "repl::entity"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["core"]
+    }
+
+    fn register_auth(&self, reg: &mut Registry, _: &GameInfo) {
+        auth::register::<T>(reg);
+    }
+
+    fn register_view(&self, reg: &mut Registry, _: &GameInfo) {
+        view::register::<T>(reg);
+    }
+}
+
 /// Event to remove entities, broadcast to clients
 #[derive(Debug, Clone, BitStore)]
 pub struct RemoveOrder(pub EntityId);
@@ -32,54 +65,76 @@ impl Event for RemoveOrder {
     }
 }
 
+/// Replicated entity classes by name, before `entity::freeze` has assigned their ids.
+struct PendingClasses<T: ComponentType>(BTreeMap<String, EntityClass<T>>);
+
 /// Register a new entity class. This should only be called in register functions that are used by
 /// both the server and the clients. Server and clients can attach their specific entity
 /// constructors locally via `entity::add_ctor`.
 ///
 /// Note that this function must only be called after this module's register function.
-pub fn register_class<T: ComponentType>(
+pub fn register_class<T, F>(
     reg: &mut Registry,
     name: &str,
     repl_components: &[T],
-    ctor: entity::Ctor,
-) -> EntityClassId {
+    ctor: F,
+)
+where
+    T: ComponentType,
+    F: Fn(EntityBuilder) -> EntityBuilder + Send + Sync + 'static,
+{
     register_class_internal(reg, name, repl_components, ctor, true)
 }
 
 /// Register a new entity class that should not be included in tick snapshots. This can be used for
 /// implicitly replicated entities that require a `repl::Id`.
-pub fn register_class_nosync<T: ComponentType>(
-    reg: &mut Registry,
-    name: &str,
-    ctor: entity::Ctor,
-) -> EntityClassId {
-    register_class_internal::<T>(reg, name, &[], ctor, false)
+pub fn register_class_nosync<T, F>(reg: &mut Registry, name: &str, ctor: F)
+where
+    T: ComponentType,
+    F: Fn(EntityBuilder) -> EntityBuilder + Send + Sync + 'static,
+{
+    register_class_internal::<T, F>(reg, name, &[], ctor, false)
 }
 
-fn register_class_internal<T: ComponentType>(
+fn register_class_internal<T, F>(
     reg: &mut Registry,
     name: &str,
     repl_components: &[T],
-    ctor: entity::Ctor,
+    ctor: F,
     sync: bool,
-) -> EntityClassId {
-    let class_id = entity::register_class(reg, name, ctor);
+) where
+    T: ComponentType,
+    F: Fn(EntityBuilder) -> EntityBuilder + Send + Sync + 'static,
+{
+    entity::register_class(reg, name, ctor);
 
     info!(
-        "Registering replicated entity class {} with id {} and repl components {:?}",
-        name, class_id, repl_components,
+        "Registering replicated entity class {} with repl components {:?}",
+        name, repl_components,
     );
 
-    let mut classes = reg.world().write_resource::<EntityClasses<T>>();
+    let mut classes = reg.world().write_resource::<PendingClasses<T>>();
 
     let class = EntityClass::<T> {
         components: repl_components.to_vec(),
         sync,
     };
 
-    classes.0.insert(class_id, class);
+    classes.0.insert(name.to_string(), class);
+}
+
+/// Key the replicated entity classes by the ids that have been assigned in `entity::freeze`.
+fn freeze_classes<T: ComponentType>(world: &mut World) {
+    let pending = mem::replace(
+        &mut world.write_resource::<PendingClasses<T>>().0,
+        BTreeMap::new(),
+    );
+    let mut classes = world.write_resource::<EntityClasses<T>>();
 
-    class_id
+    for (name, class) in pending {
+        let class_id = entity::get_class_id(world, &name).unwrap();
+        classes.0.insert(class_id, class);
+    }
 }
 
 fn try_get_class_id(world: &World, name: &str) -> Result<EntityClassId, repl::Error> {
@@ -90,21 +145,33 @@ fn try_get_class_id(world: &World, name: &str) -> Result<EntityClassId, repl::Er
     }
 }
 
-/// Create an entity with a shared, replicated id.
-fn create<F>(
-    world: &mut World,
-    id: EntityId,
-    class_id: EntityClassId,
-    ctor: F,
-) -> Result<Entity, repl::Error>
+/// Create an entity with a shared, replicated id. If the entity has a parent, the parent must
+/// already exist.
+fn create<F>(world: &mut World, id: EntityId, meta: Meta, ctor: F) -> Result<Entity, repl::Error>
 where
     F: FnOnce(EntityBuilder) -> EntityBuilder,
 {
+    let class_id = meta.class_id;
+
     if !entity::is_class_id_valid(world, class_id) {
         return Err(repl::Error::InvalidEntityClassId(class_id));
     }
 
-    let entity = entity::create(world, class_id, |builder| ctor(builder).with(repl::Id(id)));
+    let parent = if let Some(parent_id) = meta.parent {
+        Some(repl::try_id_to_entity(world, parent_id)?)
+    } else {
+        None
+    };
+
+    let entity = entity::create(world, meta, |builder| {
+        let builder = ctor(builder).with(repl::Id(id));
+
+        if let Some(parent) = parent {
+            builder.with(entity::Parent(parent))
+        } else {
+            builder
+        }
+    });
 
     // Remember player-controlled main entity
     if id.0 != INVALID_PLAYER_ID {
@@ -205,9 +272,41 @@ pub mod auth {
     pub fn register<T: EntitySnapshot>(reg: &mut Registry) {
         super::register::<T>(reg);
 
+        reg.event_handler_pre_tick(handle_event_pre_tick);
         reg.removal_system(RemovalSys, "repl::auth::entity");
     }
 
+    /// Remove the entities of players that leave. We only need to remove the root entities here,
+    /// since their descendants are removed with them. Clients are informed of the removal with a
+    /// `RemoveOrder` for every single entity.
+    fn handle_event_pre_tick(world: &mut World, event: &Event) -> Result<(), repl::Error> {
+        match_event!(event:
+            player::LeftEvent => {
+                let owned_roots = {
+                    let entities = world.entities();
+                    let repl_id = world.read::<repl::Id>();
+                    let parent = world.read::<entity::Parent>();
+
+                    (&*entities, &repl_id, !&parent).join()
+                        .filter_map(|(entity, &repl::Id(id), _)| {
+                            if id.0 == event.id {
+                                Some(entity)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                for &entity in &owned_roots {
+                    entity::deferred_remove(world, entity);
+                }
+            },
+        );
+
+        Ok(())
+    }
+
     /// Send out an `RemoveOrder` when replicated entities are removed on the server.
     struct RemovalSys;
 
@@ -239,9 +338,64 @@ pub mod auth {
         }
     }
 
+    /// Index of the next entity that will be created without an owner. This is needed for saving
+    /// the game state.
+    pub fn next_index(world: &World) -> EntityIndex {
+        world.read_resource::<IndexSource>().next
+    }
+
+    pub fn set_next_index(world: &World, next: EntityIndex) {
+        world.write_resource::<IndexSource>().next = next;
+    }
+
+    /// Recreate an entity with a known id and meta information, e.g. when loading a saved game
+    /// state. The parent of the entity, if any, must already exist.
+    pub fn restore<F>(
+        world: &mut World,
+        id: EntityId,
+        meta: Meta,
+        ctor: F,
+    ) -> Result<Entity, repl::Error>
+    where
+        F: FnOnce(EntityBuilder) -> EntityBuilder,
+    {
+        if repl::is_entity(world, id) {
+            return Err(repl::Error::InvalidEntity(id));
+        }
+
+        super::create(world, id, meta, ctor)
+    }
+
     /// Create a new entity on the server side. Here, it is possible to pass a custom constructor
     /// that can for example spawn the entity at some given position.
     pub fn create<F>(world: &mut World, owner: PlayerId, class: &str, ctor: F) -> (EntityId, Entity)
+    where
+        F: FnOnce(EntityBuilder) -> EntityBuilder,
+    {
+        create_internal(world, owner, None, class, ctor)
+    }
+
+    /// Create a new entity on the server side that is a child of an existing replicated entity.
+    /// The child is owned by the owner of the parent, and it is removed together with the parent.
+    pub fn create_child<F>(
+        world: &mut World,
+        parent: EntityId,
+        class: &str,
+        ctor: F,
+    ) -> (EntityId, Entity)
+    where
+        F: FnOnce(EntityBuilder) -> EntityBuilder,
+    {
+        create_internal(world, parent.0, Some(parent), class, ctor)
+    }
+
+    fn create_internal<F>(
+        world: &mut World,
+        owner: PlayerId,
+        parent: Option<EntityId>,
+        class: &str,
+        ctor: F,
+    ) -> (EntityId, Entity)
     where
         F: FnOnce(EntityBuilder) -> EntityBuilder,
     {
@@ -257,7 +411,7 @@ pub mod auth {
 
         let id = (owner, index);
         let class_id = entity::get_class_id(world, class).unwrap();
-        let entity = super::create(world, id, class_id, ctor);
+        let entity = super::create(world, id, Meta { class_id, parent }, ctor);
 
         // On the server, replication errors are definitely a bug, so unwrap
         (id, entity.unwrap())
@@ -273,7 +427,8 @@ pub mod view {
 
     pub fn register<T: EntitySnapshot>(reg: &mut Registry) {
         super::register::<T>(reg);
-        // TODO: Register event handler for removing repl entities
+
+        reg.event_handler_pre_tick(handle_event);
     }
 
     /// Create entities that are new in this snapshot. Note that this doesn't mean that the entity
@@ -295,16 +450,39 @@ pub mod view {
                 .collect::<Vec<_>>()
         };
 
-        for &(id, (ref meta, ref _snapshot)) in &new_entities {
-            debug!("Replicating entity {:?} of type {}", id, meta.class_id);
+        // Parents need to be created before their children. Usually, parents have smaller ids, but
+        // we can not rely on that, so we create the entities in rounds.
+        let mut pending = new_entities;
+
+        while !pending.is_empty() {
+            let (ready, not_ready): (Vec<_>, Vec<_>) =
+                pending.into_iter().partition(|&(_, (ref meta, _))| {
+                    meta.parent
+                        .map(|parent_id| repl::is_entity(world, parent_id))
+                        .unwrap_or(true)
+                });
+
+            if ready.is_empty() {
+                return Err(repl::Error::Replication(format!(
+                    "entities {:?} have parents that we do not have",
+                    not_ready.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+                )));
+            }
+
+            for (id, (meta, _snapshot)) in ready {
+                debug!("Replicating entity {:?} of type {}", id, meta.class_id);
+
+                super::create(world, id, meta, |builder| builder)?;
+            }
 
-            super::create(world, id, meta.class_id, |builder| builder)?;
+            pending = not_ready;
         }
 
         Ok(())
     }
 
-    /// Remove entities as ordered.
+    /// Remove entities as ordered. The server sends a `RemoveOrder` for every entity that it
+    /// removes, including the descendants of removed entities.
     pub fn handle_event(world: &mut World, event: &Event) -> Result<(), repl::Error> {
         match_event!(event:
             RemoveOrder => {
@@ -338,3 +516,69 @@ pub mod view {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point2;
+    use specs::prelude::RunNow;
+
+    use entity;
+    use game::run::AuthRunner;
+    use game::{self, testing};
+    use physics::Position;
+    use repl;
+
+    use super::{auth, view, RemoveOrder};
+
+    #[test]
+    fn test_remove_order() {
+        let game_info = testing::game_info();
+        let mut server = testing::auth_state(&game_info);
+
+        let (parent, parent_entity) = auth::create(&mut server.world, 0, "test", |builder| {
+            builder.with(Position(Point2::origin()))
+        });
+        let (child, _) = auth::create_child(&mut server.world, parent, "test", |builder| {
+            builder.with(Position(Point2::origin()))
+        });
+
+        // The client learns about the entities from a snapshot
+        let mut sys = game::StoreSnapshotSys {
+            snapshot: game::WorldSnapshot::new(),
+            only_player: None,
+        };
+        sys.run_now(&server.world.res);
+
+        let mut client = testing::view_state(&game_info);
+        view::create_new_entities(&mut client.world, &sys.snapshot).unwrap();
+
+        let client_entities = [
+            repl::get_id_to_entity(&client.world, parent).unwrap(),
+            repl::get_id_to_entity(&client.world, child).unwrap(),
+        ];
+
+        // Removing the parent on the server removes the child as well
+        let mut runner = AuthRunner::new(&mut server.world);
+        entity::deferred_remove(&server.world, parent_entity);
+        let events = runner.run_tick(&mut server, Vec::new()).unwrap();
+
+        let mut orders = events
+            .iter()
+            .filter_map(|event| event.downcast_ref::<RemoveOrder>())
+            .map(|order| order.0)
+            .collect::<Vec<_>>();
+        orders.sort();
+        assert_eq!(orders, vec![parent, child]);
+        assert!(!repl::is_entity(&server.world, parent));
+        assert!(!repl::is_entity(&server.world, child));
+
+        for event in &events {
+            view::handle_event(&mut client.world, &**event).unwrap();
+        }
+        entity::perform_removals(&mut client.world);
+
+        for &entity in &client_entities {
+            assert!(!client.world.entities().is_alive(entity));
+        }
+    }
+}
@@ -3,6 +3,7 @@ pub mod snapshot;
 pub mod entity;
 pub mod interp;
 pub mod player;
+pub mod resource;
 pub mod tick;
 
 #[cfg(test)]
@@ -29,6 +30,7 @@ pub fn register(reg: &mut Registry) {
     reg.resource(EntityMap(BTreeMap::new()));
 
     player::register(reg);
+    resource::register(reg);
 }
 
 /// Trait that needs to be implemented by components that want to be replicated.
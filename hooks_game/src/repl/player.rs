@@ -1,10 +1,9 @@
 use std::collections::btree_map;
 use std::collections::BTreeMap;
 
-use specs::prelude::{Entity, Join, World};
+use specs::prelude::{Entity, World};
 
-use defs::{EntityIndex, LeaveReason, PlayerId, PlayerInfo};
-use entity;
+use defs::{EntityIndex, LeaveReason, PlayerId, PlayerInfo, PlayerStatsUpdate};
 use event::{self, Event};
 use registry::Registry;
 use repl;
@@ -12,8 +11,9 @@ use repl;
 pub fn register(reg: &mut Registry) {
     reg.resource(Players(BTreeMap::new()));
 
-    reg.event::<JoinedEvent>();
-    reg.event::<LeftEvent>();
+    reg.event::<JoinedEvent>("JoinedEvent");
+    reg.event::<LeftEvent>("LeftEvent");
+    reg.event::<PlayerStatsUpdate>("PlayerStatsUpdate");
 
     reg.event_handler_pre_tick(handle_event_pre_tick);
 }
@@ -109,8 +109,18 @@ impl Event for LeftEvent {
     }
 }
 
+impl Event for PlayerStatsUpdate {
+    fn class(&self) -> event::Class {
+        event::Class::Order
+    }
+}
+
 /// Handle events regarding player creation. Note that, both on the server and the clients, this
 /// event comes from the outside. Thus, we want to handle these before starting the tick.
+///
+/// `PlayerStatsUpdate` is emitted by the server during a tick, after it has already changed the
+/// statistics, so it never reaches this handler on the server. Clients receive it with the tick
+/// and apply it here.
 fn handle_event_pre_tick(world: &mut World, event: &Event) -> Result<(), repl::Error> {
     match_event!(event:
         JoinedEvent => {
@@ -137,38 +147,32 @@ fn handle_event_pre_tick(world: &mut World, event: &Event) -> Result<(), repl::E
                 debug!("Player {} with name {} left", event.id, players.0[&event.id].info.name);
             }
 
-            // Remove all entities owned by the disconnected player
-            let owned_entities = {
-                let entities = world.entities();
-                let mut repl_ids = world.read::<repl::Id>();
-
-                (&*entities, &repl_ids).join()
-                    .filter_map(|(entity, &repl::Id(id))| {
-                        if id.0 == event.id {
-                            Some(entity)
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            };
-
-            // Possible issue here: we remove the entities in a deferred way, so that systems can
-            // do something when an entity is removed --- most importantly, removing it from the
-            // `CollisionWorld`. But here, we remove the player immediately. Thus, everything after
-            // the removal here and before the next call to `entity::perform_removals` needs to be
-            // careful not to assume that the player information is still there!
+            // Note that the entities owned by the player are not removed here. On the server, this
+            // is done by `repl::entity::auth`, which then sends a `RemoveOrder` to the clients for
+            // every removed entity.
+            //
+            // Possible issue here: we remove the player immediately, while entities are removed in
+            // a deferred way. Thus, everything after the removal here and before the next call to
+            // `entity::perform_removals` needs to be careful not to assume that the player
+            // information is still there!
             //
             // Right now, we immediately call `entity::perform_removals` after running the pre tick
             // event handlers (in `game::state::run_pre_tick`), so we should be safe unless other
             // pre tick event handlers do something stupid.
 
-            for &id in &owned_entities {
-                entity::deferred_remove(world, id);
-            }
-
             world.write_resource::<Players>().0.remove(&event.id).unwrap();
         },
+        PlayerStatsUpdate => {
+            let mut players = world.write_resource::<Players>();
+
+            match players.0.get_mut(&event.id) {
+                Some(player) => player.info.stats = event.stats.clone(),
+                None => {
+                    // Replication error. This should not happen.
+                    return Err(repl::Error::InvalidPlayerId(event.id));
+                }
+            }
+        },
     );
 
     Ok(())
@@ -0,0 +1,278 @@
+use std::any::TypeId;
+use std::fmt::Debug;
+use std::intrinsics::type_name;
+
+use bit_manager::data::BitStore;
+use bit_manager::{self, BitRead, BitWrite};
+
+use mopa;
+use shred;
+
+use specs::prelude::World;
+
+use event;
+use registry::Registry;
+use repl::snapshot::Error;
+
+pub fn register(reg: &mut Registry) {
+    reg.resource(Types(Vec::new()));
+}
+
+/// Trait that needs to be implemented by resources that want to be replicated. This is meant for
+/// global game state that does not belong to any entity, e.g. a round timer or team scores.
+pub trait Resource: shred::Resource + Clone + PartialEq + BitStore + Debug {}
+
+/// Type-erased value of a replicated resource.
+pub trait Value: mopa::Any + Debug + Sync + Send {
+    fn write(&self, writer: &mut event::Writer) -> bit_manager::Result<()>;
+    fn clone_value(&self) -> Box<Value>;
+    fn eq_value(&self, other: &Value) -> bool;
+}
+
+impl<T: Resource> Value for T {
+    fn write(&self, writer: &mut event::Writer) -> bit_manager::Result<()> {
+        self.write_to(writer)
+    }
+
+    fn clone_value(&self) -> Box<Value> {
+        Box::new(self.clone())
+    }
+
+    fn eq_value(&self, other: &Value) -> bool {
+        other
+            .downcast_ref::<T>()
+            .map(|other| self == other)
+            .unwrap_or(false)
+    }
+}
+
+mopafy!(Value);
+
+/// Replicated resource type.
+struct Type {
+    name: &'static str,
+    type_id: TypeId,
+    store: fn(&World) -> Box<Value>,
+    load: fn(&World, &Value),
+    read: fn(&mut event::Reader) -> bit_manager::Result<Box<Value>>,
+}
+
+fn store<T: Resource>(world: &World) -> Box<Value> {
+    Box::new(world.read_resource::<T>().clone())
+}
+
+fn load<T: Resource>(world: &World, value: &Value) {
+    // The snapshot was created with the same `Types`, so the value must be of type `T`
+    let value = value.downcast_ref::<T>().unwrap();
+    let mut resource = world.write_resource::<T>();
+
+    if *resource != *value {
+        *resource = value.clone();
+    }
+}
+
+fn read<T: Resource>(reader: &mut event::Reader) -> bit_manager::Result<Box<Value>> {
+    Ok(Box::new(T::read_from(reader)?))
+}
+
+/// All resources that are replicated, in the order in which they are written in ticks. This
+/// should be exactly the same on server and clients and not change during a game.
+pub struct Types(Vec<Type>);
+
+impl Types {
+    /// Register a new replicated resource type. Use `Registry::repl_resource` instead of calling
+    /// this directly.
+    pub fn register<T: Resource>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        let name = unsafe { type_name::<T>() };
+
+        assert!(
+            !self.0.iter().any(|t| t.type_id == type_id),
+            "replicated resource {} was registered twice",
+            name
+        );
+
+        info!("Registering replicated resource {}", name);
+
+        self.0.push(Type {
+            name,
+            type_id,
+            store: store::<T>,
+            load: load::<T>,
+            read: read::<T>,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// State of all the replicated resources at one point in time. The values are stored in the order
+/// given by `Types`.
+#[derive(Debug, Default)]
+pub struct Snapshot(pub Vec<Box<Value>>);
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Snapshot {
+        Snapshot(self.0.iter().map(|value| value.clone_value()).collect())
+    }
+}
+
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Snapshot) -> bool {
+        self.0.len() == other.0.len() &&
+            self.0
+                .iter()
+                .zip(other.0.iter())
+                .all(|(a, b)| a.eq_value(&**b))
+    }
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot(Vec::new())
+    }
+
+    /// Copy the current state of all replicated resources from the `World`.
+    pub fn store(world: &World) -> Snapshot {
+        let types = world.read_resource::<Types>();
+
+        Snapshot(types.0.iter().map(|t| (t.store)(world)).collect())
+    }
+
+    /// Overwrite the replicated resources in the `World` with the state in this snapshot.
+    pub fn load(&self, world: &World) {
+        let types = world.read_resource::<Types>();
+
+        assert!(self.0.len() == types.0.len());
+
+        for (t, value) in types.0.iter().zip(self.0.iter()) {
+            (t.load)(world, &**value);
+        }
+    }
+
+    /// Write only those resources that have changed compared to a previous snapshot.
+    pub fn delta_write(
+        &self,
+        cur: &Snapshot,
+        types: &Types,
+        writer: &mut event::Writer,
+    ) -> Result<(), bit_manager::Error> {
+        assert!(cur.0.len() == types.0.len());
+
+        for (i, cur_value) in cur.0.iter().enumerate() {
+            let changed = match self.0.get(i) {
+                Some(prev_value) => !prev_value.eq_value(&**cur_value),
+                None => true,
+            };
+
+            writer.write_bit(changed)?;
+            if changed {
+                cur_value.write(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return a new snapshot, updating the resources that have changed according to the delta.
+    pub fn delta_read(
+        &self,
+        types: &Types,
+        reader: &mut event::Reader,
+    ) -> Result<Snapshot, Error> {
+        let mut values = Vec::new();
+
+        for (i, t) in types.0.iter().enumerate() {
+            let changed = reader.read_bit()?;
+
+            let value = if changed {
+                (t.read)(reader)?
+            } else {
+                match self.0.get(i) {
+                    Some(prev_value) => prev_value.clone_value(),
+                    None => {
+                        return Err(Error::ReceivedInvalidSnapshot(format!(
+                            "previous snapshot is missing resource {}",
+                            t.name
+                        )));
+                    }
+                }
+            };
+
+            values.push(value);
+        }
+
+        Ok(Snapshot(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bit_manager::{BitReader, BitWriter};
+
+    use super::{Resource, Snapshot, Types};
+
+    #[derive(Debug, Clone, PartialEq, BitStore)]
+    struct Timer(u32);
+
+    #[derive(Debug, Clone, PartialEq, BitStore)]
+    struct Scores(u32, u32);
+
+    impl Resource for Timer {}
+    impl Resource for Scores {}
+
+    fn roundtrip(types: &Types, prev: &Snapshot, cur: &Snapshot) -> (Vec<u8>, Snapshot) {
+        let data = {
+            let mut writer = BitWriter::new(Vec::new());
+            prev.delta_write(cur, types, &mut writer).unwrap();
+            writer.into_inner().unwrap()
+        };
+
+        let snapshot = {
+            let mut reader = BitReader::new(Cursor::new(data.clone()));
+            prev.delta_read(types, &mut reader).unwrap()
+        };
+
+        (data, snapshot)
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut types = Types(Vec::new());
+        types.register::<Timer>();
+        types.register::<Scores>();
+
+        let empty = Snapshot::new();
+        let a = Snapshot(vec![Box::new(Timer(10)), Box::new(Scores(1, 2))]);
+        let b = Snapshot(vec![Box::new(Timer(9)), Box::new(Scores(1, 2))]);
+
+        let (_, read_a) = roundtrip(&types, &empty, &a);
+        assert!(read_a == a);
+
+        let (_, read_b) = roundtrip(&types, &a, &b);
+        assert!(read_b == b);
+        assert!(read_b != a);
+
+        // Only the two change bits should be written if nothing has changed
+        let (data, read_a) = roundtrip(&types, &a, &a);
+        assert!(data == [0]);
+        assert!(read_a == a);
+
+        // Reading a delta without a base should fail
+        let data = {
+            let mut writer = BitWriter::new(Vec::new());
+            a.delta_write(&b, &types, &mut writer).unwrap();
+            writer.into_inner().unwrap()
+        };
+        let mut reader = BitReader::new(Cursor::new(data));
+        assert!(empty.delta_read(&types, &mut reader).is_err());
+    }
+}
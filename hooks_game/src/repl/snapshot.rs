@@ -47,6 +47,9 @@ pub trait EntitySnapshot: Clone + Copy + PartialEq + 'static {
 
     /// Calculate some kind of measure of how different two entity snapshots are.
     fn distance(&self, other: &Self) -> Result<f32, repl::Error>;
+
+    /// Types of the components that are given in this snapshot.
+    fn component_types(&self) -> Vec<Self::ComponentType>;
 }
 
 pub trait HasComponent<T> {
@@ -56,6 +59,13 @@ pub trait HasComponent<T> {
 /// Trait implemented by the component type enum associated with an EntitySnapshot.
 pub trait ComponentType: Debug + Clone + Sync + Send + Sized + 'static {
     type EntitySnapshot: EntitySnapshot<ComponentType = Self>;
+
+    /// Look up a component type by the name of its type, e.g. for loading entity class
+    /// definitions from files.
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Name of the component's type, the inverse of `from_name`.
+    fn name(&self) -> &'static str;
 }
 
 /// Meta information about replicated entity types.
@@ -97,7 +107,8 @@ impl<T: EntitySnapshot> WorldSnapshot<T> {
 
 impl<T: EntitySnapshot> WorldSnapshot<T> {
     /// Write only those entities and components that have changed compared to a previous tick.
-    /// The entities are written ordered by id.
+    /// The entities are written ordered by id. After that, we write the ids of the entities that
+    /// no longer exist, so that the receiver explicitly knows about despawns.
     pub fn delta_write<W: BitWrite>(
         &self,
         cur: &Self,
@@ -105,12 +116,15 @@ impl<T: EntitySnapshot> WorldSnapshot<T> {
         _recv_player_id: PlayerId,
         writer: &mut W,
     ) -> Result<(), bit_manager::Error> {
+        let mut despawned = Vec::new();
+
         // Iterate entity pairs contained in the previous (left) and the next (right) snapshot
         for join_item in join::FullJoinIter::new(self.0.iter(), cur.0.iter()) {
             match join_item {
                 join::Item::Left(&id, _left) => {
-                    // The entity stopped existing in the new snapshot - write nothing
+                    // The entity stopped existing in the new snapshot - remember for later
                     assert!(id != INVALID_ENTITY_ID);
+                    despawned.push(id);
                 }
                 join::Item::Right(&id, &(ref right_meta, ref right_snapshot)) => {
                     // We have a new entity
@@ -152,6 +166,12 @@ impl<T: EntitySnapshot> WorldSnapshot<T> {
         // TODO: Figure out if this can be left out by knowing when the BitRead is exhausted.
         writer.write(&INVALID_ENTITY_ID)?;
 
+        // Write the despawned entities, again terminated by the invalid entity id
+        for id in &despawned {
+            writer.write(id)?;
+        }
+        writer.write(&INVALID_ENTITY_ID)?;
+
         Ok(())
     }
 
@@ -271,6 +291,22 @@ impl<T: EntitySnapshot> WorldSnapshot<T> {
             }
         }
 
+        // Read despawned entities
+        loop {
+            let id: EntityId = reader.read()?;
+
+            if id == INVALID_ENTITY_ID {
+                break;
+            }
+
+            if cur_snapshot.0.remove(&id).is_none() || new_entities.contains(&id) {
+                return Err(Error::ReceivedInvalidSnapshot(format!(
+                    "despawned entity {:?} is not in the previous snapshot",
+                    id
+                )));
+            }
+        }
+
         stats::record("tick num entities", num_entities_read as f32);
 
         Ok((new_entities, cur_snapshot))
@@ -346,6 +382,23 @@ macro_rules! snapshot {
 
             impl snapshot::ComponentType for ComponentType {
                 type EntitySnapshot = EntitySnapshot;
+
+                fn from_name(name: &str) -> Option<Self> {
+                    match name {
+                        $(
+                            stringify!($field_type) => Some(ComponentType::$field_type),
+                        )+
+                        _ => None,
+                    }
+                }
+
+                fn name(&self) -> &'static str {
+                    match *self {
+                        $(
+                            ComponentType::$field_type => stringify!($field_type),
+                        )+
+                    }
+                }
             }
 
             /// Build an entity with a given list of component types.
@@ -502,6 +555,18 @@ macro_rules! snapshot {
 
                     Ok(max_dist)
                 }
+
+                fn component_types(&self) -> Vec<ComponentType> {
+                    let mut result = Vec::new();
+
+                    $(
+                        if self.$field_name.is_some() {
+                            result.push(ComponentType::$field_type);
+                        }
+                    )+
+
+                    result
+                }
             }
 
             pub type EntityClass = snapshot::EntityClass<EntitySnapshot>;
@@ -572,6 +637,40 @@ macro_rules! snapshot {
                 }
             }
 
+            /// Store the state of all entities with `ReplId` component, including every replicated
+            /// component that they have. In contrast to `StoreSnapshotSys`, this ignores which
+            /// components are replicated for an entity class, and it also includes entities that
+            /// are not synced. This is meant for saving the game state.
+            pub struct StoreFullSnapshotSys {
+                pub snapshot: WorldSnapshot,
+            }
+
+            impl<'a> System<'a> for StoreFullSnapshotSys {
+                type SystemData = (
+                    Entities<'a>,
+                    ReadStorage<'a, repl::Id>,
+                    ReadStorage<'a, Meta>,
+                    StoreData<'a>,
+                );
+
+                fn run(
+                    &mut self,
+                    (entities, repl_id, meta, ($($field_name,)+)): Self::SystemData,
+                ) {
+                    self.snapshot.0.clear();
+
+                    for (entity, repl_id, meta) in (&*entities, &repl_id, &meta).join() {
+                        let entity_snapshot = EntitySnapshot {
+                            $(
+                                $field_name: $field_name.get(entity).cloned(),
+                            )+
+                        };
+
+                        self.snapshot.0.insert(repl_id.0, (meta.clone(), entity_snapshot));
+                    }
+                }
+            }
+
             /// Overwrite World state of entities with `ReplId` component with the state in a
             /// Snapshot. Note that this system does not create new entities.
             pub struct LoadSnapshotSys<'a> {
@@ -7,7 +7,7 @@ use bit_manager::{self, BitRead, BitWrite};
 use defs::{TickDeltaNum, TickNum, INVALID_PLAYER_ID, NO_DELTA_TICK};
 use event::{self, Event};
 use repl::snapshot::{self, EntityClasses, EntitySnapshot, WorldSnapshot};
-use repl::{entity, player};
+use repl::resource;
 
 pub struct Data<T: EntitySnapshot> {
     /// Game events that happened in this tick.
@@ -19,6 +19,10 @@ pub struct Data<T: EntitySnapshot> {
     /// intermediate ticks, together with the data of the next tick that does include a snapshot.
     pub snapshot: Option<WorldSnapshot<T>>,
 
+    /// State of replicated resources at the end of the tick. This is given if and only if
+    /// `snapshot` is given.
+    pub resources: Option<resource::Snapshot>,
+
     /// The last of our player input that has been run in this tick, if any.
     pub last_input_tick: Option<TickNum>,
 }
@@ -129,6 +133,7 @@ impl<T: EntitySnapshot> History<T> {
         prev_num: Option<TickNum>,
         cur_num: TickNum,
         classes: &EntityClasses<T::ComponentType>,
+        resource_types: &resource::Types,
         writer: &mut event::Writer,
     ) -> Result<(), bit_manager::Error> {
         writer.write(&cur_num)?;
@@ -209,6 +214,20 @@ impl<T: EntitySnapshot> History<T> {
             )?;
         }
 
+        // Send delta of replicated resources after the entity stream
+        {
+            let empty_resources = resource::Snapshot::new();
+            let prev_resources = if let Some(prev_num) = prev_num {
+                self.ticks[&prev_num].resources.as_ref().unwrap()
+            } else {
+                &empty_resources
+            };
+
+            let cur_resources = cur_data.resources.as_ref().unwrap();
+
+            prev_resources.delta_write(cur_resources, resource_types, writer)?;
+        }
+
         Ok(())
     }
 
@@ -216,6 +235,7 @@ impl<T: EntitySnapshot> History<T> {
     pub fn delta_read_tick(
         &mut self,
         classes: &EntityClasses<T::ComponentType>,
+        resource_types: &resource::Types,
         reader: &mut event::Reader,
     ) -> Result<Option<ReadInfo>, Error> {
         let cur_num = reader.read::<TickNum>()?;
@@ -279,6 +299,7 @@ impl<T: EntitySnapshot> History<T> {
                 let prev_data = Data {
                     events,
                     snapshot: None,
+                    resources: None,
                     last_input_tick: None,
                 };
 
@@ -299,7 +320,7 @@ impl<T: EntitySnapshot> History<T> {
         }
 
         // Finally, we are done with events and can delta read the snapshot
-        let (_new_entities, mut cur_snapshot) = {
+        let (_new_entities, cur_snapshot) = {
             let empty_snapshot = WorldSnapshot::new();
             let prev_snapshot = if let Some(prev_num) = prev_num {
                 // We have an entry for `prev_num` due to the loop for reading events
@@ -322,46 +343,25 @@ impl<T: EntitySnapshot> History<T> {
             prev_snapshot.delta_read(classes, reader)?
         };
 
-        // In case we receive an `entity::RemoveOrder`, we have to make sure not to carry around
-        // that entity's snapshot anymore --- otherwise, the local world snapshots could grow
-        // indefinitely. I didn't consider this at first, which led to removed entities immediately
-        // being recreated on the next tick. Putting this special case here feels like a bit of a
-        // hack, I need to think about the repercussions.
-        for (_num, tick_data) in self.ticks
-            .range((Included(event_tick_num), Included(cur_num)))
-        {
-            for event in &tick_data.events {
-                match_event!(event:
-                    entity::RemoveOrder => {
-                        debug!("Tick remove {:?}", event.0);
-                        cur_snapshot.0.remove(&event.0);
-                    },
-                    player::LeftEvent => {
-                        // Player entities are removed implicitly on disconnect, so we have to do
-                        // this here as well...
-                        let ids = cur_snapshot.0.iter()
-                            .filter_map(|(&id, &(ref entity, ref _snapshot))| {
-                                if id.0 == event.id {
-                                    Some(id)
-                                } else {
-                                    None
-                                }
-                            }).collect::<Vec<_>>();
-
-                        for id in &ids {
-                            debug!("Tick remove {:?}", id);
-                            cur_snapshot.0.remove(id);
-                        }
-                    },
-                );
-            }
-        }
+        // Replicated resources follow after the entity stream
+        let cur_resources = {
+            let empty_resources = resource::Snapshot::new();
+            let prev_resources = if let Some(prev_num) = prev_num {
+                // If we have the entity snapshot for `prev_num`, we also have its resources
+                self.ticks[&prev_num].resources.as_ref().unwrap()
+            } else {
+                &empty_resources
+            };
+
+            prev_resources.delta_read(resource_types, reader)?
+        };
 
         // Finally, add the new snapshot in the history
         // NOTE: Here, the tick data entry has already been created by the loop for reading events
         //       of intermediate ticks. The intermediate ticks do not have a snapshot.
         let cur_data = self.ticks.get_mut(&cur_num).unwrap();
         cur_data.snapshot = Some(cur_snapshot);
+        cur_data.resources = Some(cur_resources);
         cur_data.last_input_tick = last_input_tick;
 
         Ok(Some(ReadInfo {
@@ -0,0 +1,228 @@
+use std::any::TypeId;
+use std::intrinsics::type_name;
+
+use bit_manager::data::BitStore;
+use bit_manager::{self, BitRead, BitWrite};
+
+use shred;
+use specs;
+use specs::prelude::{Entity, World};
+
+use event;
+use registry::Registry;
+
+pub fn register(reg: &mut Registry) {
+    reg.resource(Components(Vec::new()));
+    reg.resource(Resources(Vec::new()));
+}
+
+/// Trait that needs to be implemented by components that are not replicated, but still need to be
+/// included when saving the game state, e.g. server-side state of game logic. Replicated
+/// components are always saved.
+pub trait Component: specs::prelude::Component + Clone + BitStore + Send + Sync {}
+
+/// Saved component type.
+struct Type {
+    name: &'static str,
+    type_id: TypeId,
+    write: fn(&World, Entity, &mut event::Writer) -> bit_manager::Result<()>,
+    read: fn(&World, Entity, &mut event::Reader) -> bit_manager::Result<()>,
+}
+
+fn write<T: Component>(
+    world: &World,
+    entity: Entity,
+    writer: &mut event::Writer,
+) -> bit_manager::Result<()> {
+    let storage = world.read::<T>();
+
+    match storage.get(entity) {
+        Some(component) => {
+            writer.write_bit(true)?;
+            component.write_to(writer)
+        }
+        None => writer.write_bit(false),
+    }
+}
+
+fn read<T: Component>(
+    world: &World,
+    entity: Entity,
+    reader: &mut event::Reader,
+) -> bit_manager::Result<()> {
+    if reader.read_bit()? {
+        let component = T::read_from(reader)?;
+        world.write::<T>().insert(entity, component);
+    }
+
+    Ok(())
+}
+
+/// All the additional component types that are saved, in the order in which they are written.
+pub struct Components(Vec<Type>);
+
+impl Components {
+    /// Register a new saved component type. Use `Registry::saved_component` instead of calling
+    /// this directly.
+    pub fn register<T: Component>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        let name = unsafe { type_name::<T>() };
+
+        assert!(
+            !self.0.iter().any(|t| t.type_id == type_id),
+            "saved component {} was registered twice",
+            name
+        );
+
+        self.0.push(Type {
+            name,
+            type_id,
+            write: write::<T>,
+            read: read::<T>,
+        });
+    }
+
+    /// Write the saved components of one entity. For compatibility checks, the list of component
+    /// names should be written separately with `write_names`.
+    pub fn write_entity(
+        &self,
+        world: &World,
+        entity: Entity,
+        writer: &mut event::Writer,
+    ) -> bit_manager::Result<()> {
+        for t in &self.0 {
+            (t.write)(world, entity, writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the saved components of one entity and insert them into the world.
+    pub fn read_entity(
+        &self,
+        world: &World,
+        entity: Entity,
+        reader: &mut event::Reader,
+    ) -> bit_manager::Result<()> {
+        for t in &self.0 {
+            (t.read)(world, entity, reader)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_names(&self, writer: &mut event::Writer) -> bit_manager::Result<()> {
+        write_names(self.0.iter().map(|t| t.name), writer)
+    }
+
+    /// Check that the list of component names matches the registered components.
+    pub fn read_names(&self, reader: &mut event::Reader) -> bit_manager::Result<bool> {
+        read_names(self.0.iter().map(|t| t.name).collect(), reader)
+    }
+}
+
+/// Trait that needs to be implemented by resources that are not replicated, but still need to be
+/// included when saving the game state, e.g. timers of a game mode.
+pub trait Resource: shred::Resource + BitStore {}
+
+/// Saved resource type.
+struct ResourceType {
+    name: &'static str,
+    type_id: TypeId,
+    write: fn(&World, &mut event::Writer) -> bit_manager::Result<()>,
+    read: fn(&World, &mut event::Reader) -> bit_manager::Result<()>,
+}
+
+fn write_resource<T: Resource>(
+    world: &World,
+    writer: &mut event::Writer,
+) -> bit_manager::Result<()> {
+    world.read_resource::<T>().write_to(writer)
+}
+
+fn read_resource<T: Resource>(
+    world: &World,
+    reader: &mut event::Reader,
+) -> bit_manager::Result<()> {
+    *world.write_resource::<T>() = T::read_from(reader)?;
+
+    Ok(())
+}
+
+/// All the additional resource types that are saved, in the order in which they are written.
+pub struct Resources(Vec<ResourceType>);
+
+impl Resources {
+    /// Register a new saved resource type. Use `Registry::saved_resource` instead of calling
+    /// this directly.
+    pub fn register<T: Resource>(&mut self) {
+        let type_id = TypeId::of::<T>();
+        let name = unsafe { type_name::<T>() };
+
+        assert!(
+            !self.0.iter().any(|t| t.type_id == type_id),
+            "saved resource {} was registered twice",
+            name
+        );
+
+        self.0.push(ResourceType {
+            name,
+            type_id,
+            write: write_resource::<T>,
+            read: read_resource::<T>,
+        });
+    }
+
+    /// Write the saved resources. For compatibility checks, the list of resource names should be
+    /// written separately with `write_names`.
+    pub fn write(&self, world: &World, writer: &mut event::Writer) -> bit_manager::Result<()> {
+        for t in &self.0 {
+            (t.write)(world, writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the saved resources and replace them in the world.
+    pub fn read(&self, world: &World, reader: &mut event::Reader) -> bit_manager::Result<()> {
+        for t in &self.0 {
+            (t.read)(world, reader)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_names(&self, writer: &mut event::Writer) -> bit_manager::Result<()> {
+        write_names(self.0.iter().map(|t| t.name), writer)
+    }
+
+    /// Check that the list of resource names matches the registered resources.
+    pub fn read_names(&self, reader: &mut event::Reader) -> bit_manager::Result<bool> {
+        read_names(self.0.iter().map(|t| t.name).collect(), reader)
+    }
+}
+
+fn write_names<I>(names: I, writer: &mut event::Writer) -> bit_manager::Result<()>
+where
+    I: ExactSizeIterator<Item = &'static str>,
+{
+    writer.write(&(names.len() as u32))?;
+
+    for name in names {
+        writer.write(&name.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn read_names(names: Vec<&'static str>, reader: &mut event::Reader) -> bit_manager::Result<bool> {
+    let len = reader.read::<u32>()? as usize;
+    let mut matches = len == names.len();
+
+    for i in 0..len {
+        let name = reader.read::<String>()?;
+        matches = matches && names.get(i).map(|&n| n == name).unwrap_or(false);
+    }
+
+    Ok(matches)
+}
@@ -0,0 +1,90 @@
+//! Inspect physics recordings written by the server's `--record-physics` option.
+//!
+//! Usage:
+//!     physics_trace print <recording> [--tick <tick>]
+//!     physics_trace diff <recording> <recording> [--tolerance <tolerance>]
+
+extern crate hooks_game;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use hooks_game::physics::record::{self, Step};
+
+/// Numbers that differ by less than this are considered equal by default when diffing.
+const DEFAULT_TOLERANCE: f32 = 1e-4;
+
+/// Value of a command line argument given as `--name value`.
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("    physics_trace print <recording> [--tick <tick>]");
+    eprintln!("    physics_trace diff <recording> <recording> [--tolerance <tolerance>]");
+    process::exit(1);
+}
+
+fn read(path: &str) -> Vec<Step> {
+    record::read_file(Path::new(path)).unwrap_or_else(|error| {
+        eprintln!("Failed to read recording {}: {:?}", path, error);
+        process::exit(1);
+    })
+}
+
+fn print(steps: &[Step]) {
+    let tick = arg_value("--tick").map(|tick| tick.parse().unwrap_or_else(|_| usage()));
+
+    for step in steps {
+        if tick.map_or(true, |tick| step.tick == tick) {
+            println!("{}", step);
+        }
+    }
+}
+
+/// Print the differences of the steps in both recordings. Returns true if they are the same.
+fn diff(steps_a: &[Step], steps_b: &[Step]) -> bool {
+    let tolerance = arg_value("--tolerance")
+        .map(|tolerance| tolerance.parse().unwrap_or_else(|_| usage()))
+        .unwrap_or(DEFAULT_TOLERANCE);
+
+    let mut same = true;
+
+    for (step_a, step_b) in steps_a.iter().zip(steps_b) {
+        let diffs = record::diff(step_a, step_b, tolerance);
+
+        if !diffs.is_empty() {
+            println!(
+                "tick {} run {} substep {}:",
+                step_a.tick, step_a.run, step_a.substep
+            );
+            for diff in diffs {
+                println!("  {}", diff);
+            }
+            same = false;
+        }
+    }
+
+    if steps_a.len() != steps_b.len() {
+        println!("steps: {} vs {}", steps_a.len(), steps_b.len());
+        same = false;
+    }
+
+    same
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(|command| command.as_str()) {
+        Some("print") if args.len() >= 3 => print(&read(&args[2])),
+        Some("diff") if args.len() >= 4 => {
+            if !diff(&read(&args[2]), &read(&args[3])) {
+                process::exit(1);
+            }
+        }
+        _ => usage(),
+    }
+}
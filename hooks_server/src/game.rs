@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::mem;
+use std::path::Path;
 use std::time::Instant;
 
 use bit_manager::BitWriter;
@@ -9,10 +10,10 @@ use shred::{Fetch, RunNow};
 use hooks_game::net::protocol::{ClientGameMsg, ServerCommMsg};
 use hooks_game::net::transport::PeerId;
 use hooks_game::registry::Registry;
-use hooks_game::repl::{player, tick};
+use hooks_game::repl::{player, resource, tick};
 use hooks_game::INVALID_PLAYER_ID;
-use hooks_game::{self, event, game, GameInfo, LeaveReason, PlayerId, PlayerInfo, PlayerInput,
-                 TickDeltaNum, TickNum};
+use hooks_game::{self, event, game, physics, GameInfo, LeaveReason, PlayerId, PlayerInfo,
+                 PlayerInput, TickDeltaNum, TickNum};
 use hooks_util::profile;
 use hooks_util::timer::{Stopwatch, Timer};
 
@@ -192,33 +193,56 @@ pub struct Game {
     write_buffer: Vec<u8>,
 }
 
-fn register(reg: &mut Registry, game_info: &GameInfo) {
-    hooks_game::auth::register(reg, game_info);
-}
-
 impl Game {
-    pub fn new(game_info: &GameInfo) -> Game {
-        let mut game_state = {
-            let mut reg = Registry::new();
-
-            register(&mut reg, &game_info);
+    pub fn new(game_info: &GameInfo, check_determinism: bool, load_path: Option<&Path>) -> Game {
+        let create_state = || {
+            let reg = Registry::auth(&hooks_game::plugins(), &game_info);
+            let mut game_state = game::State::from_registry(reg);
+
+            let next_tick = if let Some(load_path) = load_path {
+                // Can unwrap here, since we can't continue without the requested state anyway
+                game::save::load_file(&mut game_state.world, load_path).unwrap()
+            } else {
+                game::init::auth::create_state(&mut game_state.world);
+                1
+            };
 
-            game::State::from_registry(reg)
+            (game_state, next_tick)
         };
-        game::init::auth::create_state(&mut game_state.world);
 
-        let game_runner = game::run::AuthRunner::new(&mut game_state.world);
+        let (mut game_state, next_tick) = create_state();
+        let mut game_runner = game::run::AuthRunner::new(&mut game_state.world);
+
+        if check_determinism {
+            info!("Checking parallel dispatch against sequential dispatch in every tick");
+            game_runner.check_determinism(create_state().0);
+        }
+
+        // Players from a loaded state are not connected anymore, so they need to leave the game.
+        // Continue player ids after theirs, so that clients do not confuse old and new players.
+        let mut queued_events = event::Sink::new();
+        let mut next_player_id = INVALID_PLAYER_ID + 1;
+
+        for &player_id in game_state.world.read_resource::<player::Players>().0.keys() {
+            info!("Removing player {} from loaded state", player_id);
+
+            queued_events.push(player::LeftEvent {
+                id: player_id,
+                reason: LeaveReason::Disconnected,
+            });
+            next_player_id = next_player_id.max(player_id + 1);
+        }
 
         Game {
             game_state,
             game_runner,
-            next_player_id: INVALID_PLAYER_ID + 1,
+            next_player_id,
             players: BTreeMap::new(),
             bots: Vec::new(),
             tick_timer: Timer::new(game_info.tick_duration()),
-            next_tick: 1,
+            next_tick,
             update_stopwatch: Stopwatch::new(),
-            queued_events: event::Sink::new(),
+            queued_events,
             write_buffer: Vec::new(),
         }
     }
@@ -227,6 +251,16 @@ impl Game {
         self.game_state.world.read_resource::<GameInfo>()
     }
 
+    /// Save the game state, so that it can be restored with `Game::new`.
+    pub fn save(&self, path: &Path) -> Result<(), game::save::Error> {
+        game::save::save_file(&self.game_state.world, self.next_tick, path)
+    }
+
+    /// Record every physics step to a file, for inspecting with the `physics_trace` tool.
+    pub fn record_physics(&self, path: &Path) -> Result<(), physics::record::Error> {
+        physics::record::start(&self.game_state.world, path)
+    }
+
     pub fn add_bot(&mut self, name: &str) -> PlayerId {
         let player_id = self.register_player(name);
         self.bots.push((player_id, Bot::default()));
@@ -502,6 +536,8 @@ impl Game {
                 .push((player_id, input));
         }
 
+        physics::record::set_tick(&self.game_state.world, next_tick);
+
         let tick_events = {
             profile!("run");
 
@@ -519,6 +555,7 @@ impl Game {
         profile!("tick history");
 
         let entity_classes = self.game_state.world.read_resource::<game::EntityClasses>();
+        let resource_types = self.game_state.world.read_resource::<resource::Types>();
         let send_snapshot = self.next_tick % self.game_info().ticks_per_snapshot == 0;
 
         let snapshot = if send_snapshot {
@@ -535,6 +572,11 @@ impl Game {
         } else {
             None
         };
+        let resources = if send_snapshot {
+            Some(resource::Snapshot::store(&self.game_state.world))
+        } else {
+            None
+        };
 
         for (&peer_id, player) in &mut self.players {
             // Events for this player are the special queued events as well as the shared
@@ -550,6 +592,7 @@ impl Game {
                 let tick_data = tick::Data {
                     events: player_events.into_vec(),
                     snapshot: snapshot.clone(),
+                    resources: resources.clone(),
                     last_input_tick: player
                         .last_ran_input
                         .as_ref()
@@ -570,6 +613,7 @@ impl Game {
                         player.last_ack_tick,
                         self.next_tick,
                         &entity_classes,
+                        &resource_types,
                         &mut writer,
                     )?;
                 }
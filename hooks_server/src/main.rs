@@ -17,10 +17,18 @@ mod game;
 mod host;
 mod server;
 
-use hooks_game::{GameInfo, MapInfo};
+use std::env;
+use std::path::PathBuf;
+
+use hooks_game::{GameInfo, MapInfo, PhysicsConfig};
 
 use server::Server;
 
+/// Value of a command line argument given as `--name value`.
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
 fn main() {
     env_logger::init();
 
@@ -32,11 +40,16 @@ fn main() {
         player_entity_class: "player".to_string(),
         server_target_lag_inputs: 1,
         client_target_lag_snapshots: 2,
+        physics: PhysicsConfig::default(),
     };
     let config = server::Config {
         port: 32444,
         game_info,
         num_bots: 5,
+        check_determinism: env::args().any(|arg| arg == "--check-determinism"),
+        load_path: arg_value("--load").map(PathBuf::from),
+        save_path: arg_value("--save").map(PathBuf::from),
+        record_physics_path: arg_value("--record-physics").map(PathBuf::from),
     };
 
     let mut server = Server::create(&config).unwrap();
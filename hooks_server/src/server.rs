@@ -1,4 +1,5 @@
 use std::io;
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
@@ -12,11 +13,26 @@ use hooks_util::timer::{Stopwatch, Timer};
 use game::Game;
 use host::{self, Host};
 
+/// How often the game state is saved, if a save path is given.
+const SAVE_INTERVAL_SECS: u64 = 60;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
     pub game_info: GameInfo,
     pub num_bots: usize,
+
+    /// Run every tick a second time with sequential dispatch and assert identical results.
+    pub check_determinism: bool,
+
+    /// Load the game state from this file at startup instead of creating a new one.
+    pub load_path: Option<PathBuf>,
+
+    /// Periodically save the game state to this file.
+    pub save_path: Option<PathBuf>,
+
+    /// Record every physics step to this file.
+    pub record_physics_path: Option<PathBuf>,
 }
 
 pub struct Server {
@@ -24,6 +40,9 @@ pub struct Server {
     profile_timer: Timer,
     stopwatch: Stopwatch,
 
+    save_path: Option<PathBuf>,
+    save_timer: Timer,
+
     host: Host,
     game: Game,
     #[cfg(feature = "show")]
@@ -38,7 +57,18 @@ impl Server {
         );
 
         let host = Host::create(config.port, &config.game_info)?;
-        let mut game = Game::new(&config.game_info);
+        let mut game = Game::new(
+            &config.game_info,
+            config.check_determinism,
+            config.load_path.as_ref().map(|path| path.as_path()),
+        );
+
+        if let Some(ref path) = config.record_physics_path {
+            info!("Recording physics steps to {}", path.display());
+
+            // Can unwrap here, since recording was explicitly requested
+            game.record_physics(path).unwrap();
+        }
 
         if config.num_bots > 0 {
             game.add_bot("alphasnd");
@@ -59,6 +89,8 @@ impl Server {
             profile: true,
             profile_timer: Timer::new(Duration::from_secs(5)),
             stopwatch: Stopwatch::new(),
+            save_path: config.save_path.clone(),
+            save_timer: Timer::new(Duration::from_secs(SAVE_INTERVAL_SECS)),
             host,
             game,
         })
@@ -66,8 +98,10 @@ impl Server {
 
     pub fn run(&mut self) -> Result<(), host::Error> {
         loop {
+            let duration = self.stopwatch.get_reset();
+
             if self.profile {
-                self.profile_timer += self.stopwatch.get_reset();
+                self.profile_timer += duration;
                 if self.profile_timer.trigger_reset() {
                     PROFILER.with(|p| p.borrow().inspect().print(&mut io::stdout()));
                 }
@@ -77,6 +111,17 @@ impl Server {
 
             self.game.update(&mut self.host)?;
 
+            if let Some(ref save_path) = self.save_path {
+                self.save_timer += duration;
+                if self.save_timer.trigger_reset() {
+                    info!("Saving game state to {}", save_path.display());
+
+                    if let Err(error) = self.game.save(save_path) {
+                        warn!("Failed to save game state: {:?}", error);
+                    }
+                }
+            }
+
             thread::sleep(Duration::from_millis(1));
         }
     }
@@ -1,16 +1,21 @@
+use ggez::graphics;
+
 use hooks_game;
 use hooks_game::entity;
 use hooks_game::game::entity::player;
 
 use rect;
+use shape;
 
 pub fn register(reg: &mut hooks_game::Registry) {
-    // TODO: Get sizes from e.g. collision shapes?
-
     entity::add_ctor(reg, "test", |builder| {
-        builder.with(rect::Draw {
-            width: 200.0,
-            height: 200.0,
+        builder.with(shape::Draw {
+            color: graphics::Color {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
             fill: true,
         })
     });
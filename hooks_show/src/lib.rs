@@ -20,6 +20,7 @@ mod entity;
 mod hook;
 mod player;
 mod rect;
+pub mod shape;
 mod wall;
 
 use nalgebra::{Matrix4, Point2};
@@ -35,9 +36,23 @@ use hooks_util::profile;
 
 use self::camera::Camera;
 
-pub fn register(reg: &mut hooks_game::Registry) {
-    rect::register(reg);
-    entity::register(reg);
+/// Plugin that adds the components for showing the game state to the client's game registry.
+pub struct Plugin;
+
+impl hooks_game::Plugin for Plugin {
+    fn name(&self) -> &'static str {
+        "show"
+    }
+
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["game::entity", "prefab"]
+    }
+
+    fn register_view(&self, reg: &mut hooks_game::Registry, _: &GameInfo) {
+        rect::register(reg);
+        shape::register(reg);
+        entity::register(reg);
+    }
 }
 
 pub fn register_show(reg: &mut Registry) {
@@ -45,6 +60,7 @@ pub fn register_show(reg: &mut Registry) {
     hook::register_show(reg);
     player::register_show(reg);
     rect::register_show(reg);
+    shape::register_show(reg);
 }
 
 pub struct Assets {
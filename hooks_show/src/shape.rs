@@ -0,0 +1,114 @@
+use std::f32;
+
+use nalgebra::{Isometry3, Point2, Vector3};
+use specs::prelude::{Join, ReadStorage, SystemData, VecStorage, World};
+
+use ggez;
+use ggez::graphics::{self, DrawMode};
+
+use hooks_game;
+use hooks_game::entity::Active;
+use hooks_game::physics::collision::{self, Ball, Capsule, ConvexHull, Cuboid};
+use hooks_game::physics::{Orientation, Position};
+use hooks_util::profile;
+
+use {with_transform, Input, Registry};
+
+/// Number of line segments used for each half circle of a capsule.
+const CAPSULE_SEGMENTS: usize = 16;
+
+/// Maximal distance between a drawn circle and the actual circle.
+const CIRCLE_TOLERANCE: f32 = 0.5;
+
+pub fn register(reg: &mut hooks_game::Registry) {
+    reg.component::<Draw>();
+}
+
+pub fn register_show(reg: &mut Registry) {
+    reg.draw_fn(draw);
+}
+
+/// Draw an entity by its `collision::Shape`.
+#[derive(Component, Clone, Debug)]
+#[storage(VecStorage)]
+pub struct Draw {
+    pub color: graphics::Color,
+    pub fill: bool,
+}
+
+type DrawData<'a> = (
+    ReadStorage<'a, Active>,
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Orientation>,
+    ReadStorage<'a, collision::Shape>,
+    ReadStorage<'a, Draw>,
+);
+
+fn draw(ctx: &mut ggez::Context, _input: &Input, world: &World) -> ggez::error::GameResult<()> {
+    profile!("shape");
+
+    let (active, position, orientation, shape, draw) = DrawData::fetch(&world.res);
+
+    for (_active, position, orientation, shape, draw) in
+        (&active, &position, &orientation, &shape, &draw).join()
+    {
+        let coords = position.0.coords;
+        let isometry = Isometry3::new(
+            Vector3::new(coords.x, coords.y, 0.0),
+            orientation.0 * Vector3::z_axis().unwrap(),
+        );
+        let mode = if draw.fill {
+            DrawMode::Fill
+        } else {
+            DrawMode::Line(1.0)
+        };
+
+        graphics::set_color(ctx, draw.color)?;
+
+        with_transform(ctx, isometry.to_homogeneous(), |ctx| {
+            if let Some(ball) = shape.0.as_shape::<Ball<f32>>() {
+                graphics::circle(ctx, mode, Point2::origin(), ball.radius(), CIRCLE_TOLERANCE)
+            } else if let Some(cuboid) = shape.0.as_shape::<Cuboid<_>>() {
+                let h = cuboid.half_extents();
+                graphics::polygon(
+                    ctx,
+                    mode,
+                    &[
+                        Point2::new(-h.x, -h.y),
+                        Point2::new(h.x, -h.y),
+                        Point2::new(h.x, h.y),
+                        Point2::new(-h.x, h.y),
+                    ],
+                )
+            } else if let Some(capsule) = shape.0.as_shape::<Capsule<f32>>() {
+                graphics::polygon(ctx, mode, &capsule_outline(capsule))
+            } else if let Some(polygon) = shape.0.as_shape::<ConvexHull<_>>() {
+                graphics::polygon(ctx, mode, polygon.points())
+            } else {
+                warn!("Don't know how to draw shape");
+                Ok(())
+            }
+        })?;
+    }
+
+    graphics::set_color(ctx, graphics::WHITE)?;
+
+    Ok(())
+}
+
+/// Approximate the outline of a capsule with a polygon in object space.
+fn capsule_outline(capsule: &Capsule<f32>) -> Vec<Point2<f32>> {
+    let half_height = capsule.half_height();
+    let radius = capsule.radius();
+
+    let half_circle = |center_y: f32, start_angle: f32| {
+        (0..CAPSULE_SEGMENTS + 1).map(move |i| {
+            let angle = start_angle + f32::consts::PI * i as f32 / CAPSULE_SEGMENTS as f32;
+            Point2::new(radius * angle.cos(), center_y + radius * angle.sin())
+        })
+    };
+
+    half_circle(half_height, 0.0)
+        .chain(half_circle(-half_height, f32::consts::PI))
+        .collect()
+}